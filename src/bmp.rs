@@ -3,7 +3,7 @@ use std::convert::TryInto;
 use std::collections::VecDeque;
 use std::fmt;
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::f64::consts::PI;
 //use std::io::ErrorKind;
 
@@ -19,38 +19,48 @@ http://fileformats.archiveteam.org/wiki/BMP
 const HEADER_OFFSET: usize = 14;
 
 //Errors
-#[derive(PartialEq)]
+/// The errors returned by this library.
+#[derive(Debug)]
 pub enum ErrorKind {
-  NotFound,
   OutOfBounds,
   Unsupported,
   DoesNotExist,
   WrongFileType,
   UseExtraBitMasks,
-  FailedToWrite,
   Missing,
-  InvalidParameter(String)
+  InvalidParameter(String),
+  /// A header field has a value that is invalid, or that does not fit the rest of the file. `offset` is the byte offset of the field in the file.
+  CorruptHeader { field: String, offset: usize, reason: String },
+  /// The pixel array uses a compression (eg: `BI_JPEG`) that cannot be read or written.
+  UnsupportedCompression(String),
+  /// The bits per pixel of the image are not one of 1, 2, 4, 8, 16, 24 or 32.
+  UnsupportedBitDepth(u16),
+  /// The pixel array is compressed (eg: an embedded PNG or JPEG) but could not be decoded.
+  CorruptPixelData(String),
+  /// Reading or writing the file failed, eg: the file was not found (`std::io::ErrorKind::NotFound`).
+  Io(std::io::Error),
 }
 
 impl ErrorKind {
   fn get_text(&self) -> String {
     match &*self {
-      ErrorKind::NotFound => "File not found".to_string(),
       ErrorKind::OutOfBounds => "x or y coord exceeds width or height of image".to_string(),
       ErrorKind::Unsupported => "File is unsupported".to_string(),
       ErrorKind::DoesNotExist => "Requested object does not exist".to_string(),
       ErrorKind::WrongFileType => "Wrong file type. Must be a .bmp file".to_string(),
       ErrorKind::UseExtraBitMasks => "Use extra bit masks instead".to_string(),
-      ErrorKind::FailedToWrite => "Failed to write to file".to_string(),
       ErrorKind::Missing => "Missing expected parameter or object".to_string(),
       ErrorKind::InvalidParameter(reason) => "Invalid Parameter: ".to_owned()+reason,
+      ErrorKind::CorruptHeader { field, offset, reason } => format!("Corrupt header: {} at byte {}: {}", field, offset, reason),
+      ErrorKind::UnsupportedCompression(compression) => format!("Compression {} is unsupported", compression),
+      ErrorKind::UnsupportedBitDepth(bitcount) => format!("Bit depth of {} bits per pixel is unsupported", bitcount),
+      ErrorKind::CorruptPixelData(reason) => format!("Corrupt pixel data: {}", reason),
+      ErrorKind::Io(e) => format!("I/O error: {}", e),
     }
   }
-}
-
-impl fmt::Debug for ErrorKind {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}", self.get_text())
+  //for BI_PNG and BI_JPEG images where the embedded image is not the size the DIB header says
+  pub(crate) fn embedded_size_mismatch(width: u32, height: u32, embedded_width: u32, embedded_height: u32) -> ErrorKind {
+    return ErrorKind::CorruptHeader { field: "width".to_string(), offset: HEADER_OFFSET+4, reason: format!("header says the image is {}x{} but the embedded image is {}x{}", width, height, embedded_width, embedded_height) };
   }
 }
 
@@ -60,6 +70,38 @@ impl fmt::Display for ErrorKind {
   }
 }
 
+impl std::error::Error for ErrorKind {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ErrorKind::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for ErrorKind {
+  fn from(e: std::io::Error) -> ErrorKind {
+    ErrorKind::Io(e)
+  }
+}
+
+//std::io::Error is not PartialEq, so I/O errors are equal if they are the same kind of I/O error
+impl PartialEq for ErrorKind {
+  fn eq(&self, other: &ErrorKind) -> bool {
+    match (self, other) {
+      (ErrorKind::Io(e1), ErrorKind::Io(e2)) => e1.kind() == e2.kind(),
+      (ErrorKind::InvalidParameter(reason1), ErrorKind::InvalidParameter(reason2)) => reason1 == reason2,
+      (ErrorKind::CorruptHeader { field: field1, offset: offset1, reason: reason1 }, ErrorKind::CorruptHeader { field: field2, offset: offset2, reason: reason2 }) => {
+        field1 == field2 && offset1 == offset2 && reason1 == reason2
+      },
+      (ErrorKind::UnsupportedCompression(compression1), ErrorKind::UnsupportedCompression(compression2)) => compression1 == compression2,
+      (ErrorKind::UnsupportedBitDepth(bitcount1), ErrorKind::UnsupportedBitDepth(bitcount2)) => bitcount1 == bitcount2,
+      (ErrorKind::CorruptPixelData(reason1), ErrorKind::CorruptPixelData(reason2)) => reason1 == reason2,
+      _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+    }
+  }
+}
+
 //Color
 pub enum RGBAChannel {
   Red,
@@ -68,6 +110,264 @@ pub enum RGBAChannel {
   Alpha,
 }
 
+//Resizing
+/// How pixels are interpolated by `BMP::resize()`, `BMP::scale_to_fit()`, `BMP::scale_to_fill()` and `BMP::rotate_with_options()`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResizeFilter {
+  /// The closest pixel, blocky but keeps hard edges.
+  Nearest,
+  /// Linear interpolation between the 2 closest pixels in each direction (a triangle filter when shrinking).
+  Bilinear,
+  /// Bicubic interpolation with the Catmull-Rom spline, sharp.
+  CatmullRom,
+  /// Bicubic interpolation with the Mitchell-Netravali filter (B = C = 1/3), softer than Catmull-Rom with less ringing.
+  Mitchell,
+  /// Windowed sinc with 3 lobes, the sharpest, good for thumbnails.
+  Lanczos3,
+}
+
+/// Where the image goes when the canvas size is changed by `BMP::resize_canvas()`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Anchor {
+  TopLeft,
+  Top,
+  TopRight,
+  Left,
+  Center,
+  Right,
+  BottomLeft,
+  Bottom,
+  BottomRight,
+}
+
+/// Options for `BMP::rotate_with_options()`.
+///
+/// ```rust
+/// use bmp_rust::bmp::{BMP, ResizeFilter, RotateOptions};
+///
+/// let bmp = BMP::new(20, 40, Some([255, 0, 0, 255]));
+/// let rotated = bmp.rotate_with_options(30.0, &RotateOptions { expand: true, ..RotateOptions::default() }).unwrap();
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct RotateOptions {
+  /// The point to rotate around, where `[0.0, 0.0]` is the top left corner of the top left pixel. If `None`, the center of the image.
+  pub center: Option<[f64; 2]>,
+  pub filter: ResizeFilter,
+  /// Make the canvas big enough to fit the whole rotated image, instead of keeping the size and cutting off the corners.
+  pub expand: bool,
+  /// The color of the parts of the canvas the image does not cover.
+  pub background: [u8; 4],
+}
+
+impl Default for RotateOptions {
+  fn default() -> RotateOptions {
+    return RotateOptions {
+      center: None,
+      filter: ResizeFilter::Bilinear,
+      expand: false,
+      background: [0, 0, 0, 0],
+    };
+  }
+}
+
+/// How `BMP::transform()` and `BMP::warp_perspective()` treat the parts of the image they sample outside of it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EdgeMode {
+  /// Outside of the image is transparent.
+  Transparent,
+  /// The closest edge pixel is repeated.
+  Clamp,
+  /// The image is tiled.
+  Wrap,
+  /// The image is tiled, with every other tile flipped so the edges meet.
+  Mirror,
+}
+
+/// A mapping from points in an image to points in the transformed image, for `BMP::transform()`. `[0.0, 0.0]` is the top left corner of the top left pixel.
+///
+/// Transforms can be composed with `then()`:
+///
+/// ```rust
+/// use bmp_rust::bmp::Transform;
+///
+/// let transform = Transform::scaling(2.0, 2.0).then(&Transform::rotation(30.0, [0.0, 0.0])).then(&Transform::translation(10.0, 0.0));
+/// assert_eq!(transform.apply([0.0, 0.0]), Some([10.0, 0.0]));
+/// ```
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transform {
+  /// `[[a, b, c], [d, e, f]]` maps `(x, y)` to `(a*x+b*y+c, d*x+e*y+f)`.
+  Affine([[f64; 3]; 2]),
+  /// A homography, maps `(x, y)` to `(X/W, Y/W)`, where `[X, Y, W]` is the matrix times `[x, y, 1]`.
+  /// The matrix times any number other than 0 is the same mapping, points on the same side of the horizon as `(0, 0)` are the ones in front of it.
+  Perspective([[f64; 3]; 3]),
+}
+
+/// Options for `BMP::transform()` and `BMP::warp_perspective()`.
+#[derive(Clone, Debug)]
+pub struct TransformOptions {
+  pub filter: ResizeFilter,
+  pub edge: EdgeMode,
+  /// The width and height of the new image. If `None`, the same as the image.
+  pub size: Option<[u32; 2]>,
+}
+
+impl Default for TransformOptions {
+  fn default() -> TransformOptions {
+    return TransformOptions {
+      filter: ResizeFilter::Bilinear,
+      edge: EdgeMode::Transparent,
+      size: None,
+    };
+  }
+}
+
+impl Transform {
+  /// Move points by `x` and `y`.
+  pub fn translation(x: f64, y: f64) -> Transform {
+    return Transform::Affine([[1.0, 0.0, x], [0.0, 1.0, y]]);
+  }
+  /// Scale points away from `(0, 0)`.
+  pub fn scaling(x: f64, y: f64) -> Transform {
+    return Transform::Affine([[x, 0.0, 0.0], [0.0, y, 0.0]]);
+  }
+  /// Move points horizontally by `x` times their y coordinate, and vertically by `y` times their x coordinate.
+  pub fn shear(x: f64, y: f64) -> Transform {
+    return Transform::Affine([[1.0, x, 0.0], [y, 1.0, 0.0]]);
+  }
+  /// Rotate points clockwise around the center, like `BMP::rotate()`.
+  pub fn rotation(deg: f64, center: [f64; 2]) -> Transform {
+    let rad: f64 = BMP::deg_to_rad(deg);
+    //where the origin and the points one to the right and one down end up
+    let origin = BMP::rotate_point(rad, [0.0, 0.0], center);
+    let right = BMP::rotate_point(rad, [1.0, 0.0], center);
+    let down = BMP::rotate_point(rad, [0.0, 1.0], center);
+    return Transform::Affine([[right[0]-origin[0], down[0]-origin[0], origin[0]], [right[1]-origin[1], down[1]-origin[1], origin[1]]]);
+  }
+  /// The homography that maps each of the four source points to the destination point at the same index, for `BMP::warp_perspective()`.
+  /// Errors if three of the source or destination points are on a line.
+  pub fn perspective_from_points(source: [[f64; 2]; 4], destination: [[f64; 2]; 4]) -> Result<Transform, ErrorKind> {
+    //with the bottom right of the matrix being 1, each pair of points gives two equations for the other 8:
+    //a*x+b*y+c-g*x*u-h*y*u = u and d*x+e*y+f-g*x*v-h*y*v = v
+    let mut equations: [[f64; 9]; 8] = [[0.0; 9]; 8];
+    for (i, ([x, y], [u, v])) in source.into_iter().zip(destination).enumerate() {
+      equations[i*2] = [x, y, 1.0, 0.0, 0.0, 0.0, -x*u, -y*u, u];
+      equations[i*2+1] = [0.0, 0.0, 0.0, x, y, 1.0, -x*v, -y*v, v];
+    }
+    //gaussian elimination, with the largest pivot for stability
+    for column in 0..8 {
+      let pivot = (column..8).max_by(|a, b| equations[*a][column].abs().total_cmp(&equations[*b][column].abs())).unwrap_or(column);
+      if equations[pivot][column].abs() < 1e-12 {
+        return Err(ErrorKind::InvalidParameter("three of the corners are on a line".to_string()));
+      }
+      equations.swap(column, pivot);
+      let pivot_row = equations[column];
+      for (row, equation) in equations.iter_mut().enumerate() {
+        if row == column {
+          continue;
+        }
+        let factor = equation[column]/pivot_row[column];
+        for (value, pivot_value) in equation.iter_mut().zip(pivot_row) {
+          *value -= factor*pivot_value;
+        }
+      }
+    }
+    let h: Vec<f64> = equations.iter().enumerate().map(|(i, equation)| equation[8]/equation[i]).collect();
+    return Ok(Transform::Perspective([[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]]));
+  }
+  /// The transform that does this one and then `next`.
+  pub fn then(&self, next: &Transform) -> Transform {
+    let (first, second) = (self.matrix(), next.matrix());
+    let mut matrix: [[f64; 3]; 3] = [[0.0; 3]; 3];
+    for (row, values) in matrix.iter_mut().enumerate() {
+      for (column, value) in values.iter_mut().enumerate() {
+        *value = (0..3).map(|i| second[row][i]*first[i][column]).sum();
+      }
+    }
+    match (self, next) {
+      (Transform::Affine(_), Transform::Affine(_)) => return Transform::Affine([matrix[0], matrix[1]]),
+      _ => return Transform::Perspective(matrix),
+    }
+  }
+  /// The transform that undoes this one. Errors if it squashes the image onto a line or point.
+  pub fn inverse(&self) -> Result<Transform, ErrorKind> {
+    let m = self.matrix();
+    //the adjugate divided by the determinant
+    let cofactor = |row: usize, column: usize| -> f64 {
+      let (r1, r2) = ((row+1) % 3, (row+2) % 3);
+      let (c1, c2) = ((column+1) % 3, (column+2) % 3);
+      return m[r1][c1]*m[r2][c2]-m[r1][c2]*m[r2][c1];
+    };
+    let determinant: f64 = (0..3).map(|column| m[0][column]*cofactor(0, column)).sum();
+    if determinant == 0.0 || !determinant.is_finite() {
+      return Err(ErrorKind::InvalidParameter("transform can not be undone".to_string()));
+    }
+    let mut inverse: [[f64; 3]; 3] = [[0.0; 3]; 3];
+    for (row, values) in inverse.iter_mut().enumerate() {
+      for (column, value) in values.iter_mut().enumerate() {
+        *value = cofactor(column, row)/determinant;
+      }
+    }
+    match self {
+      Transform::Affine(_) => return Ok(Transform::Affine([inverse[0], inverse[1]])),
+      Transform::Perspective(_) => return Ok(Transform::Perspective(inverse)),
+    }
+  }
+  /// Where a point ends up. `None` if a perspective transform sends it to or past the horizon.
+  pub fn apply(&self, point: [f64; 2]) -> Option<[f64; 2]> {
+    let m = self.matrix();
+    let w = m[2][0]*point[0]+m[2][1]*point[1]+m[2][2];
+    let x = (m[0][0]*point[0]+m[0][1]*point[1]+m[0][2])/w;
+    let y = (m[1][0]*point[0]+m[1][1]*point[1]+m[1][2])/w;
+    //the sign of the matrix is arbitrary, so the side of the horizon `(0, 0)` is on counts as in front (or, if it is on the horizon, the side with larger x, then y)
+    let sign = [m[2][2], m[2][0], m[2][1]].into_iter().find(|value| *value != 0.0).map_or(1.0, f64::signum);
+    if w*sign <= 0.0 || !x.is_finite() || !y.is_finite() {
+      return None;
+    }
+    return Some([x, y]);
+  }
+  fn matrix(&self) -> [[f64; 3]; 3] {
+    match self {
+      Transform::Affine([first, second]) => return [*first, *second, [0.0, 0.0, 1.0]],
+      Transform::Perspective(matrix) => return *matrix,
+    }
+  }
+}
+
+impl ResizeFilter {
+  //how far (in pixels) from the center the filter reaches
+  fn support(&self) -> f64 {
+    match self {
+      ResizeFilter::Nearest => return 0.5,
+      ResizeFilter::Bilinear => return 1.0,
+      ResizeFilter::CatmullRom | ResizeFilter::Mitchell => return 2.0,
+      ResizeFilter::Lanczos3 => return 3.0,
+    }
+  }
+  //weight of a pixel `x` pixels from the center
+  fn weight(&self, x: f64) -> f64 {
+    let x = x.abs();
+    //cubic filters, with the B and C parameters of Mitchell and Netravali
+    let cubic = |b: f64, c: f64| -> f64 {
+      if x < 1.0 {
+        return ((12.0-9.0*b-6.0*c)*x.powi(3)+(-18.0+12.0*b+6.0*c)*x.powi(2)+(6.0-2.0*b))/6.0;
+      } else if x < 2.0 {
+        return ((-b-6.0*c)*x.powi(3)+(6.0*b+30.0*c)*x.powi(2)+(-12.0*b-48.0*c)*x+(8.0*b+24.0*c))/6.0;
+      }
+      return 0.0;
+    };
+    let sinc = |x: f64| -> f64 { if x == 0.0 { 1.0 } else { (PI*x).sin()/(PI*x) } };
+    match self {
+      ResizeFilter::Nearest => return if x <= 0.5 { 1.0 } else { 0.0 },
+      ResizeFilter::Bilinear => return (1.0-x).max(0.0),
+      ResizeFilter::CatmullRom => return cubic(0.0, 0.5),
+      ResizeFilter::Mitchell => return cubic(1.0/3.0, 1.0/3.0),
+      ResizeFilter::Lanczos3 => return if x < 3.0 { sinc(x)*sinc(x/3.0) } else { 0.0 },
+    }
+  }
+}
+
 //BMP Diff
 #[derive(Debug)]
 pub struct PixelDiff {
@@ -223,6 +523,88 @@ enum DIBHEADER {
 }
 */
 
+//color spaces
+//https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-wmf/eb4bbd50-b3ce-4917-895c-be31f214797f
+/// The color space of the image (`CSType` in BITMAPV4HEADER and BITMAPV5HEADER).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorSpaceType {
+  /// `LCS_CALIBRATED_RGB`, the colors are calibrated by the `Endpoints` and gamma in the header.
+  CalibratedRgb,
+  /// `LCS_sRGB`
+  Srgb,
+  /// `LCS_WINDOWS_COLOR_SPACE`, the system default color space (sRGB).
+  WindowsColorSpace,
+  /// `PROFILE_LINKED`, see `BMP::get_color_profile()`.
+  ProfileLinked,
+  /// `PROFILE_EMBEDDED`, see `BMP::get_color_profile()`.
+  ProfileEmbedded,
+  /// Any other value, kept so the header can be written back unchanged.
+  Other(u32),
+}
+
+impl ColorSpaceType {
+  //the non zero values are 4 ascii chars, eg: 'sRGB'
+  fn from_int(int: u32) -> ColorSpaceType {
+    match int {
+      0 => ColorSpaceType::CalibratedRgb,
+      0x73524742 => ColorSpaceType::Srgb,
+      0x57696E20 => ColorSpaceType::WindowsColorSpace,
+      0x4C494E4B => ColorSpaceType::ProfileLinked,
+      0x4D424544 => ColorSpaceType::ProfileEmbedded,
+      _ => ColorSpaceType::Other(int),
+    }
+  }
+  fn to_int(self) -> u32 {
+    match self {
+      ColorSpaceType::CalibratedRgb => 0,
+      ColorSpaceType::Srgb => 0x73524742,
+      ColorSpaceType::WindowsColorSpace => 0x57696E20,
+      ColorSpaceType::ProfileLinked => 0x4C494E4B,
+      ColorSpaceType::ProfileEmbedded => 0x4D424544,
+      ColorSpaceType::Other(int) => int,
+    }
+  }
+}
+
+/// The rendering intent of the image (`Intent` in BITMAPV5HEADER), how colors outside of the output device's gamut are handled.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderingIntent {
+  /// `LCS_GM_BUSINESS`, saturation.
+  Business,
+  /// `LCS_GM_GRAPHICS`, relative colorimetric.
+  Graphics,
+  /// `LCS_GM_IMAGES`, perceptual.
+  Images,
+  /// `LCS_GM_ABS_COLORIMETRIC`, absolute colorimetric.
+  AbsColorimetric,
+  /// Any other value (eg: 0, which some programs write), kept so the header can be written back unchanged.
+  Other(u32),
+}
+
+impl RenderingIntent {
+  fn from_int(int: u32) -> RenderingIntent {
+    match int {
+      1 => RenderingIntent::Business,
+      2 => RenderingIntent::Graphics,
+      4 => RenderingIntent::Images,
+      8 => RenderingIntent::AbsColorimetric,
+      _ => RenderingIntent::Other(int),
+    }
+  }
+  fn to_int(self) -> u32 {
+    match self {
+      RenderingIntent::Business => 1,
+      RenderingIntent::Graphics => 2,
+      RenderingIntent::Images => 4,
+      RenderingIntent::AbsColorimetric => 8,
+      RenderingIntent::Other(int) => int,
+    }
+  }
+}
+
+//the sRGB primaries as chromaticity coordinates (red 0.64, 0.33, 0.03, green 0.3, 0.6, 0.1, blue 0.15, 0.06, 0.79), in 2.30 fixed point
+const SRGB_ENDPOINTS: [[i32; 3]; 3] = [[687194752, 354334816, 32212256], [322122560, 644245120, 107374144], [161061280, 64424508, 848256036]];
+
 //DIB header
 /// The BMP DIB header, which has multiple versions.
 /// The officially documented versions (BITMAPCOREHEADER, BITMAPINFOHEADER, BITMAPV4HEADER, BITMAPV5HEADER) can be found [here](https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-structures), 
@@ -233,6 +615,11 @@ enum DIBHEADER {
 /// 
 /// Depending on what DIB header version is being used (BITMAPV5HEADER is the most modern and common), fields that are `Option`s may or may not exist.
 /// 
+/// The OS/2 2.x header (64 bytes, but it can be cut off after any field from the bitcount on, so any size from 16 to 64 other than 40, 52 and 56) has its own fields, `Units` to `Identifier`, after the first 40 bytes.
+/// Fields that are cut off are `None`. In OS/2 headers, compression 3 and 4 are `BI_HUFFMAN1D` and `BI_RLE24` instead of `BI_BITFIELDS` and `BI_JPEG`.
+/// 
+/// `Endpoints` (2.30 fixed point) and the gammas (16.16 fixed point) are the raw values, use `get_endpoints()` and `get_gamma()` for them as numbers.
+/// 
 #[allow(non_snake_case)]
 #[derive(Clone)]
 pub struct DIBHEADER {
@@ -251,87 +638,155 @@ pub struct DIBHEADER {
   pub GreenMask: Option<u32>,
   pub BlueMask: Option<u32>,
   pub AlphaMask: Option<u32>,
-  pub CSType: Option<String>,
+  pub CSType: Option<ColorSpaceType>,
   pub Endpoints: Option<[[i32; 3]; 3]>,
   pub GammaRed: Option<u32>,
   pub GammaGreen: Option<u32>,
   pub GammaBlue: Option<u32>,
-  pub Intent: Option<String>,
-  pub ProfileData: Option<u16>,
-  pub ProfileSize: Option<u16>,
+  pub Intent: Option<RenderingIntent>,
+  pub ProfileData: Option<u32>,
+  pub ProfileSize: Option<u32>,
   pub Reserved: Option<Vec<u8>>,
+  //OS/2 2.x (16 to 64 byte) header fields
+  //0 is pixels per meter, the only unit defined
+  pub Units: Option<u16>,
+  //2 bytes of padding after the units
+  pub Padding: Option<u16>,
+  //0 is bottom up, the only direction defined
+  pub Recording: Option<u16>,
+  //halftoning algorithm: 0 none, 1 error diffusion, 2 PANDA, 3 super-circle
+  pub Rendering: Option<u16>,
+  //halftoning algorithm parameters
+  pub Size1: Option<u32>,
+  pub Size2: Option<u32>,
+  //0 is RGB, the only color encoding defined
+  pub ColorEncoding: Option<u32>,
+  //for application use
+  pub Identifier: Option<u32>,
 }
 
-//for non 124 byte dib headers, cut off excess bytes
-impl IntoIterator for DIBHEADER {
-  //DIBHEADER example: line 504ish
-  type Item = u8;
-  type IntoIter = std::array::IntoIter<u8, 124>;
+impl DIBHEADER {
+  //OS/2 2.x headers can be cut off after any field, so any size from 16 to 64 that is not a windows header
+  fn is_os2_size(size: u32) -> bool {
+    return (16..=64).contains(&size) && size != 40 && size != 52 && size != 56;
+  }
+  /// Check if this is an OS/2 2.x header.
+  pub fn is_os2(&self) -> bool {
+    return DIBHEADER::is_os2_size(self.size);
+  }
+  /// The red, green and blue endpoints (CIEXYZ, each as x, y, z) used by calibrated rgb (`ColorSpaceType::CalibratedRgb`).
+  /// Only BITMAPV4HEADER and BITMAPV5HEADER have them.
+  pub fn get_endpoints(&self) -> Option<[[f64; 3]; 3]> {
+    //2.30 fixed point
+    return self.Endpoints.map(|endpoints| endpoints.map(|endpoint| endpoint.map(|value| f64::from(value)/f64::from(1 << 30))));
+  }
+  /// The red, green and blue gamma used by calibrated rgb (`ColorSpaceType::CalibratedRgb`).
+  /// Only BITMAPV4HEADER and BITMAPV5HEADER have them.
+  pub fn get_gamma(&self) -> Option<[f64; 3]> {
+    //16.16 fixed point
+    return match (self.GammaRed, self.GammaGreen, self.GammaBlue) {
+      (Some(red), Some(green), Some(blue)) => Some([red, green, blue].map(|gamma| f64::from(gamma)/65536.0)),
+      _ => None,
+    };
+  }
+}
 
-  fn into_iter(self) -> Self::IntoIter {
-    //let bytes_vec = [self.bfType.as_bytes(), &self.bfSize.to_le_bytes(), &BMP::vec_to_2u8_array(self.bfReserved1), &BMP::vec_to_2u8_array(self.bfReserved2), &self.bfOffBits.to_le_bytes()].concat();
-    let mut bytes_vec = [&self.size.to_le_bytes()[..], &self.width.to_le_bytes(), &self.height.to_le_bytes(), &self.planes.to_le_bytes(), &self.bitcount.to_le_bytes()].concat();
-    if self.size > 12 {
-      let compression_table: HashMap<String, u32> = HashMap::from([
-        ("BI_RGB".to_string(), 0),
-        ("BI_RLE8".to_string(), 1),
-        ("BI_RLE4".to_string(), 2),
-        ("BI_BITFIELDS".to_string(), 3),
-        ("BI_JPEG".to_string(), 4),
-        ("BI_PNG".to_string(), 5),
-        ("BI_ALPHABITFIELDS".to_string(), 6)
-      ]);
-      let compression: u32 = *compression_table.get(&self.compression.unwrap()).unwrap();
-      bytes_vec.append(&mut [&compression.to_le_bytes()[..], &self.sizeimage.unwrap().to_le_bytes(), &self.XPelsPerMeter.unwrap().to_le_bytes(), &self.YPelsPerMeter.unwrap().to_le_bytes(), &self.ClrUsed.unwrap().to_le_bytes(), &self.ClrImportant.unwrap().to_le_bytes()].concat());
-    }
-    if self.size > 40 {
-      let mut endpoints_l: [u8; 36] = [0u8; 36];
-      let endpoints = self.Endpoints.unwrap();
-      //create endpoints list
-      for i in 0..3 {
-        for ii in 0..3 {
-          //4 bytes
-          endpoints_l[(i*3+ii*4) as usize] = endpoints[i as usize][ii as usize].to_le_bytes()[0];
-          endpoints_l[(i*3+ii*4+1) as usize] = endpoints[i as usize][ii as usize].to_le_bytes()[1];
-          endpoints_l[(i*3+ii*4+2) as usize] = endpoints[i as usize][ii as usize].to_le_bytes()[2];
-          endpoints_l[(i*3+ii*4+3) as usize] = endpoints[i as usize][ii as usize].to_le_bytes()[3];
-        }
-      }
-      //println!("{:?}", endpoints_l);
-      //wip
-      bytes_vec.append(&mut [&self.RedMask.unwrap().to_le_bytes(), &self.GreenMask.unwrap().to_le_bytes(), &self.BlueMask.unwrap().to_le_bytes(), &self.AlphaMask.unwrap().to_le_bytes(), self.CSType.unwrap().as_bytes(), &endpoints_l[..], &self.GammaRed.unwrap().to_le_bytes(), &self.GammaGreen.unwrap().to_le_bytes(), &self.GammaBlue.unwrap().to_le_bytes()].concat());
-    }
-    if self.size > 108 {
+impl DIBHEADER {
+  /// The bytes of the header as they are stored in the file. Errors if a field the header size needs is `None`, or the compression is unknown.
+  //writes exactly `size` bytes, each header version adds fields to the end of the previous version
+  pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorKind> {
+    let mut bytes_vec: Vec<u8>;
+    if self.size == 12 {
+      //BITMAPCOREHEADER has 2 byte width and height
+      bytes_vec = [&self.size.to_le_bytes()[..], &(self.width as u16).to_le_bytes(), &(self.height as i16).to_le_bytes(), &self.planes.to_le_bytes(), &self.bitcount.to_le_bytes()].concat();
+    } else {
+      bytes_vec = [&self.size.to_le_bytes()[..], &self.width.to_le_bytes(), &self.height.to_le_bytes(), &self.planes.to_le_bytes(), &self.bitcount.to_le_bytes()].concat();
+    }
+    let compression_table: HashMap<String, u32> = HashMap::from([
+      ("BI_RGB".to_string(), 0),
+      ("BI_RLE8".to_string(), 1),
+      ("BI_RLE4".to_string(), 2),
+      ("BI_BITFIELDS".to_string(), 3),
+      ("BI_JPEG".to_string(), 4),
+      ("BI_PNG".to_string(), 5),
+      ("BI_ALPHABITFIELDS".to_string(), 6),
+      //OS/2 only
+      ("BI_HUFFMAN1D".to_string(), 3),
+      ("BI_RLE24".to_string(), 4)
+    ]);
+    let compression_to_int = |compression: &String| -> Result<u32, ErrorKind> {
+      match compression_table.get(compression) {
+        Some(compression) => return Ok(*compression),
+        None => return Err(ErrorKind::InvalidParameter(format!("unknown compression {}", compression))),
+      }
+    };
+    if self.is_os2() {
+      //OS/2 header, the fields that are cut off are left out, and missing fields before the end are 0
+      let compression: u32 = match &self.compression {
+        Some(compression) => compression_to_int(compression)?,
+        None => 0,
+      };
+      bytes_vec.append(&mut [compression, self.sizeimage.unwrap_or(0), self.XPelsPerMeter.unwrap_or(0), self.YPelsPerMeter.unwrap_or(0), self.ClrUsed.unwrap_or(0), self.ClrImportant.unwrap_or(0)].map(u32::to_le_bytes).concat());
+      bytes_vec.append(&mut [self.Units.unwrap_or(0), self.Padding.unwrap_or(0), self.Recording.unwrap_or(0), self.Rendering.unwrap_or(0)].map(u16::to_le_bytes).concat());
+      bytes_vec.append(&mut [self.Size1.unwrap_or(0), self.Size2.unwrap_or(0), self.ColorEncoding.unwrap_or(0), self.Identifier.unwrap_or(0)].map(u32::to_le_bytes).concat());
+      bytes_vec.truncate(self.size as usize);
+      return Ok(bytes_vec);
+    }
+    if self.size >= 40 {
+      let compression: u32 = compression_to_int(&self.required(self.compression.clone(), "compression")?)?;
+      bytes_vec.append(&mut [&compression.to_le_bytes()[..], &self.required(self.sizeimage, "sizeimage")?.to_le_bytes(), &self.required(self.XPelsPerMeter, "XPelsPerMeter")?.to_le_bytes(), &self.required(self.YPelsPerMeter, "YPelsPerMeter")?.to_le_bytes(), &self.required(self.ClrUsed, "ClrUsed")?.to_le_bytes(), &self.required(self.ClrImportant, "ClrImportant")?.to_le_bytes()].concat());
+    }
+    if self.size >= 52 {
+      //BITMAPV2INFOHEADER adds the rgb masks
+      bytes_vec.append(&mut [&self.required(self.RedMask, "RedMask")?.to_le_bytes()[..], &self.required(self.GreenMask, "GreenMask")?.to_le_bytes(), &self.required(self.BlueMask, "BlueMask")?.to_le_bytes()].concat());
+    }
+    if self.size >= 56 {
+      //BITMAPV3INFOHEADER adds the alpha mask
+      bytes_vec.extend(self.required(self.AlphaMask, "AlphaMask")?.to_le_bytes());
+    }
+    if self.size >= 108 {
+      let cstype_l: [u8; 4] = self.required(self.CSType, "CSType")?.to_int().to_le_bytes();
+      //three CIEXYZ, each with 3 4 byte values, so 36 bytes
+      let mut endpoints_l: Vec<u8> = Vec::new();
+      for endpoint in self.required(self.Endpoints, "Endpoints")? {
+        for value in endpoint {
+          endpoints_l.extend(value.to_le_bytes());
+        }
+      }
+      bytes_vec.append(&mut [&cstype_l[..], &endpoints_l[..], &self.required(self.GammaRed, "GammaRed")?.to_le_bytes(), &self.required(self.GammaGreen, "GammaGreen")?.to_le_bytes(), &self.required(self.GammaBlue, "GammaBlue")?.to_le_bytes()].concat());
+    }
+    if self.size >= 124 {
       //Reserved
       let mut reserved_l: [u8; 4] = [0u8; 4];
-      let reserved = self.Reserved.unwrap();
-      for i in 0..4 {
-        reserved_l[i as usize] = reserved[i as usize];
-      }
-      //Intent
-      let intent_table: HashMap<String, u32> = HashMap::from([
-        ("LCS_GM_ABS_COLORIMETRIC".to_string(), 0),
-        ("LCS_GM_BUSINESS".to_string(), 1),
-        ("LCS_WINDOWS_COLOR_SPACE".to_string(), 2),
-        ("LCS_GM_GRAPHICS".to_string(), 3),
-        ("LCS_GM_IMAGES".to_string(), 4)
-      ]);
-      let intent: u32 = *intent_table.get(&self.Intent.unwrap()).unwrap();
-      bytes_vec.append(&mut [&intent.to_le_bytes()[..], &self.ProfileData.unwrap().to_le_bytes(), &self.ProfileSize.unwrap().to_le_bytes(), &reserved_l[..]].concat());
-    }
-    let mut bytes_array: [u8; 124] = [0u8; 124];
-    //vector.len() should be 124
-    for i in 0..bytes_vec.len() {
-      bytes_array[i] = bytes_vec[i];
+      let reserved = self.required(self.Reserved.clone(), "Reserved")?;
+      let reserved_length = reserved.len().min(4);
+      reserved_l[..reserved_length].copy_from_slice(&reserved[..reserved_length]);
+      let intent: u32 = self.required(self.Intent, "Intent")?.to_int();
+      bytes_vec.append(&mut [&intent.to_le_bytes()[..], &self.required(self.ProfileData, "ProfileData")?.to_le_bytes(), &self.required(self.ProfileSize, "ProfileSize")?.to_le_bytes(), &reserved_l[..]].concat());
     }
-    //DEPRECATED
-    //return std::array::IntoIter::new(bytes_array);
-    return IntoIterator::into_iter(bytes_array);
+    //any bytes of unknown header versions are left as 0
+    bytes_vec.resize(self.size as usize, 0);
+    return Ok(bytes_vec);
+  }
+  fn required<T>(&self, value: Option<T>, name: &str) -> Result<T, ErrorKind> {
+    return value.ok_or_else(|| ErrorKind::InvalidParameter(format!("{} byte DIB header is missing {}", self.size, name)));
+  }
+}
+
+/// Panics if the header can not be serialized, see `DIBHEADER::to_bytes()`.
+impl IntoIterator for DIBHEADER {
+  type Item = u8;
+  type IntoIter = std::vec::IntoIter<u8>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    return self.to_bytes().expect("DIB header can not be serialized").into_iter();
   }
 }
   
 //rgbtriple and rgbquad
 /// Color table used in some versions of the format. Can be either an array of RGB (`RGBTRIPLE`) or RGBA (`RGBQUAD`) colors.
+/// The colors are in the order they are stored in the file: blue, green, red (and reserved, for `RGBQUAD`).
+#[derive(Clone, PartialEq, Debug)]
 pub enum ColorTable {
   RGBTRIPLE(Vec<[u8; 3]>),
   RGBQUAD(Vec<[u8; 4]>),
@@ -346,6 +801,7 @@ pub struct BI_BITFIELDS_MASKS {
 }
 
 #[allow(non_camel_case_types)]
+#[derive(Clone)]
 pub struct BI_ALPHABITFIELDS_MASKS {
   pub red: u32,
   pub green: u32,
@@ -353,12 +809,172 @@ pub struct BI_ALPHABITFIELDS_MASKS {
   pub alpha: u32,
 }
 
+/// The masks are used to pack RGBA colors into, and unpack RGBA colors from, the pixel values of 16 and 32 bit images.
+/// Each channel is found by the position and width of its mask, so any layout (eg: 5-6-5, 10-10-10-2, or 8-8-8-8 in any order) works.
+///
+/// A mask of 0 means the channel is not stored. A missing alpha channel reads as 255 (opaque).
+///
+impl BI_ALPHABITFIELDS_MASKS {
+  //shift is how far the mask is from the right, width is how many bits are in the mask
+  fn shift_and_width(mask: u32) -> (u32, u32) {
+    if mask == 0 {
+      return (0, 0);
+    }
+    let shift = mask.trailing_zeros();
+    return (shift, (mask >> shift).count_ones());
+  }
+  //rescale a channel value that is `width` bits wide to 8 bits
+  fn extract_channel(value: u32, mask: u32) -> Option<u8> {
+    let (shift, width) = BI_ALPHABITFIELDS_MASKS::shift_and_width(mask);
+    if width == 0 {
+      return None;
+    }
+    let max: u64 = (1u64 << width)-1;
+    let channel: u64 = u64::from((value & mask) >> shift);
+    return Some(((channel*255+max/2)/max) as u8);
+  }
+  //rescale an 8 bit channel value to `width` bits, and put it in the right place
+  fn insert_channel(channel: u8, mask: u32) -> u32 {
+    let (shift, width) = BI_ALPHABITFIELDS_MASKS::shift_and_width(mask);
+    if width == 0 {
+      return 0;
+    }
+    let max: u64 = (1u64 << width)-1;
+    let scaled: u64 = (u64::from(channel)*max+127)/255;
+    return ((scaled << shift) as u32) & mask;
+  }
+  /// Unpack a pixel value into RGBA.
+  pub fn decode(&self, value: u32) -> [u8; 4] {
+    return [
+      BI_ALPHABITFIELDS_MASKS::extract_channel(value, self.red).unwrap_or(0),
+      BI_ALPHABITFIELDS_MASKS::extract_channel(value, self.green).unwrap_or(0),
+      BI_ALPHABITFIELDS_MASKS::extract_channel(value, self.blue).unwrap_or(0),
+      BI_ALPHABITFIELDS_MASKS::extract_channel(value, self.alpha).unwrap_or(255),
+    ];
+  }
+  /// Pack RGBA into a pixel value.
+  pub fn encode(&self, rgba: [u8; 4]) -> u32 {
+    return BI_ALPHABITFIELDS_MASKS::insert_channel(rgba[0], self.red) | BI_ALPHABITFIELDS_MASKS::insert_channel(rgba[1], self.green) | BI_ALPHABITFIELDS_MASKS::insert_channel(rgba[2], self.blue) | BI_ALPHABITFIELDS_MASKS::insert_channel(rgba[3], self.alpha);
+  }
+}
+
 #[allow(non_camel_case_types)]
 enum EXTRA_BIT_MASKS {
   BI_BITFIELDS_MASKS(BI_BITFIELDS_MASKS),
   BI_ALPHABITFIELDS_MASKS(BI_ALPHABITFIELDS_MASKS),
 }
 
+/// How new colors are written to images that store pixels as indexes into the color table (1, 2, 4 and 8 bit images).
+/// Set with `BMP::set_palette_policy()`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PalettePolicy {
+  /// Use the closest color that is already in the color table. The color table is never changed. This is the default.
+  Nearest,
+  /// Add colors that are not in the color table to it, until it has the max amount of colors for the bitcount (2^bitcount).
+  /// After that, the closest color is used.
+  Grow,
+}
+
+impl ColorTable {
+  /// Get the colors as red, green, blue.
+  pub fn to_rgb(&self) -> Vec<[u8; 3]> {
+    match self {
+      ColorTable::RGBTRIPLE(vec) => return vec.iter().map(|bgr| [bgr[2], bgr[1], bgr[0]]).collect(),
+      ColorTable::RGBQUAD(vec) => return vec.iter().map(|bgr0| [bgr0[2], bgr0[1], bgr0[0]]).collect(),
+    }
+  }
+}
+
+/// The DIB header version written by `BMP::encode()`, see `EncoderOptions`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeaderVersion {
+  /// BITMAPCOREHEADER (12 bytes). Only uncompressed, bottom up 1, 4, 8 and 24 bit images, and no DPI.
+  Core,
+  /// BITMAPINFOHEADER (40 bytes), the most widely supported.
+  Info,
+  /// BITMAPV4HEADER (108 bytes).
+  V4,
+  /// BITMAPV5HEADER (124 bytes), which is what `BMP::new()` creates.
+  V5,
+}
+
+/// The compression written by `BMP::encode()`, see `EncoderOptions`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EncoderCompression {
+  /// `BI_RGB`, not compressed. 16 bit pixels are 5-5-5, and 32 bit pixels are blue, green, red, alpha.
+  Rgb,
+  /// `BI_RLE8` or `BI_RLE4`, for 8 and 4 bit bottom up images.
+  Rle,
+  /// `BI_BITFIELDS`, for 16 and 32 bit images. 16 bit pixels are 5-6-5, and 32 bit pixels have alpha (except with `HeaderVersion::Info`, which has no alpha mask).
+  Bitfields,
+}
+
+/// How colors that are not in the color table (or that 16 bit pixels cannot store) are reduced, used by `Image::remap_dithered()` and `EncoderOptions`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dither {
+  /// Each pixel becomes the closest color.
+  None,
+  /// Error diffusion, the difference between a pixel and its closest color is spread to the pixels right and below it.
+  FloydSteinberg,
+  /// Error diffusion that only spreads 3/4 of the difference, which keeps more contrast.
+  Atkinson,
+  /// Error diffusion to two rows below (Jarvis, Judice and Ninke), smoother but slower than Floyd-Steinberg.
+  JarvisJudiceNinke,
+  /// Error diffusion to two rows below, with smaller weights than Jarvis-Judice-Ninke.
+  Sierra,
+  /// Ordered dithering with a Bayer threshold matrix of the given size (2, 4, 8 or 16). There is no error to spread, so each pixel only depends on its own color.
+  Bayer(u8),
+}
+
+/// How `BMP::encode()` and `BMP::save_with_options()` write an image. The defaults are the same format that `BMP::new()` creates.
+///
+/// For example, a 24 bit BITMAPINFOHEADER image:
+/// ```
+/// use bmp_rust::bmp::{EncoderOptions, EncoderCompression, HeaderVersion};
+/// let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 24, compression: EncoderCompression::Rgb, ..Default::default() };
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct EncoderOptions {
+  pub header_version: HeaderVersion,
+  /// Bits per pixel: 1, 4, 8, 16, 24 or 32.
+  pub bitcount: u16,
+  pub compression: EncoderCompression,
+  /// Store the rows from top to bottom (negative height), instead of from bottom to top.
+  pub top_down: bool,
+  /// Horizontal and vertical resolution, in dots per inch.
+  pub dpi: [u32; 2],
+  /// The color table for 1, 4 and 8 bit images, each pixel is written as the closest color in it.
+  /// If `None`, the color table of the image being encoded is used if it fits, otherwise a default color table.
+  pub palette: Option<ColorTable>,
+  /// Dithering for 1, 4, 8 and 16 bit images.
+  pub dither: Dither,
+}
+
+impl Default for EncoderOptions {
+  fn default() -> EncoderOptions {
+    return EncoderOptions {
+      header_version: HeaderVersion::V5,
+      bitcount: 32,
+      compression: EncoderCompression::Bitfields,
+      top_down: false,
+      dpi: [96, 96],
+      palette: None,
+      dither: Dither::None,
+    };
+  }
+}
+
+/// An ICC color profile, returned by `BMP::get_color_profile()` and attached with `BMP::set_color_profile()`.
+/// Only BITMAPV5HEADER (124 byte DIB header) images can have one.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ColorProfile {
+  /// The bytes of an ICC profile stored in the file (`PROFILE_EMBEDDED`).
+  Embedded(Vec<u8>),
+  /// The file path of an ICC profile stored outside of the file (`PROFILE_LINKED`).
+  Linked(String),
+}
+
 /// Represents loaded BMP file, the `contents` is a vector of the file bytes.
 /// 
 /// Also contains many useful utility functions.
@@ -366,6 +982,7 @@ enum EXTRA_BIT_MASKS {
 pub struct BMP {
   pub contents: Vec<u8>,
   from_file: bool,
+  palette_policy: PalettePolicy,
   //bitmap_file_header: BITMAPFILEHEADER,
   //dib_header: DIBHEADER,
 }
@@ -380,6 +997,7 @@ impl Clone for BMP {
   fn clone(&self) -> BMP {
     let mut clone_bmp = BMP::new(1, 1, None);
     clone_bmp.contents = self.contents.to_vec();
+    clone_bmp.palette_policy = self.palette_policy;
     return clone_bmp;
   }
 }
@@ -390,7 +1008,7 @@ impl BMP {
     let mut contents = Vec::new();
     //file header
     let offset: u32 = 14+124;
-    let size: u32 = offset+(height.abs() as u32 * width)*4;
+    let size: u32 = offset+(height.unsigned_abs() * width)*4;
     let file_header = BITMAPFILEHEADER {
       bfType: "BM".to_string(),
       bfSize: size,
@@ -420,22 +1038,30 @@ impl BMP {
       GreenMask: Some(65280),
       BlueMask: Some(255),
       AlphaMask: Some(4278190080),
-      //"BGRs" why? I don't know and I will not question
-      CSType: Some("BGRs".to_string()),
-      //I don't know what these numbers mean :)
-      Endpoints: Some([[687194752, 354334816, 32212256], [322122560, 644245120, 107374144], [161061280, 64424508, 848256036]]),
+      CSType: Some(ColorSpaceType::Srgb),
+      //ignored for sRGB, see `set_color_space_type()` for calibrated rgb
+      Endpoints: Some(SRGB_ENDPOINTS),
       GammaRed: Some(0),
       GammaGreen: Some(0),
       GammaBlue: Some(0),
-      Intent: Some("LCS_GM_IMAGES".to_string()),
+      Intent: Some(RenderingIntent::Images),
       ProfileData: Some(0),
       ProfileSize: Some(0),
       Reserved: Some(vec![0, 0, 0, 0]),
+      Units: None,
+      Padding: None,
+      Recording: None,
+      Rendering: None,
+      Size1: None,
+      Size2: None,
+      ColorEncoding: None,
+      Identifier: None,
     };
     contents.extend(dib_header);
     //pixels, turn it into the bytes
     //no rounding is needed, because each row is rounded up to a multiple of u32, which all our pixels are
-    for _pixel_num in 0..(width*height as u32) {
+    //negative height is a top down image, with the same number of pixels
+    for _pixel_num in 0..(width*height.unsigned_abs()) {
       //white by default
       if default_color.is_some() {
         //change rgb to bgr
@@ -448,12 +1074,132 @@ impl BMP {
       }
     }
     //println!("{:?}", contents);
-    return BMP { contents: contents, from_file: false };
+    return BMP { contents: contents, from_file: false, palette_policy: PalettePolicy::Nearest };
   }
   /// Load BMP from file.
   pub fn new_from_file(file_path: &str) -> Result<BMP, ErrorKind> {
-    let contents = fs::read(file_path).map_err(|_| ErrorKind::NotFound)?;
-    Ok(BMP { contents: contents, from_file: true })
+    let contents = fs::read(file_path)?;
+    let bmp = BMP { contents: contents, from_file: true, palette_policy: PalettePolicy::Nearest };
+    bmp.validate()?;
+    return Ok(bmp);
+  }
+  /// Load a BMP file from anything that can be read from, like a network stream or an archive entry.
+  pub fn from_reader(mut reader: impl Read) -> Result<BMP, ErrorKind> {
+    let mut contents: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let bmp = BMP { contents, from_file: false, palette_policy: PalettePolicy::Nearest };
+    bmp.validate()?;
+    return Ok(bmp);
+  }
+  /// Load a BMP file that is already in memory. The bytes are copied.
+  pub fn from_bytes(bytes: &[u8]) -> Result<BMP, ErrorKind> {
+    let bmp = BMP { contents: bytes.to_vec(), from_file: false, palette_policy: PalettePolicy::Nearest };
+    bmp.validate()?;
+    return Ok(bmp);
+  }
+  /// Check that the file is a BMP file, and that the headers are consistent with each other and with the length of the file.
+  /// This is done when loading a file, so that corrupt or malicious files return an error instead of panicking later on.
+  ///
+  /// Returns `ErrorKind::WrongFileType` if the file does not start with "BM",
+  /// and `ErrorKind::CorruptHeader` (with the name and byte offset of the bad field) for anything else that is wrong.
+  ///
+  pub fn validate(&self) -> Result<(), ErrorKind> {
+    let corrupt = |field: &str, offset: usize, reason: String| -> ErrorKind {
+      ErrorKind::CorruptHeader { field: field.to_string(), offset, reason }
+    };
+    let file_length = self.contents.len();
+    if file_length < HEADER_OFFSET {
+      return Err(corrupt("bfType", 0, format!("file is {} bytes, too short for the file header", file_length)));
+    }
+    if &self.contents[..2] != b"BM" {
+      return Err(ErrorKind::WrongFileType);
+    }
+    //get_dib_header checks the DIB header fits in the file and that the compression is a known value
+    let dib_header = self.get_dib_header()?;
+    let header = self.get_header();
+    //BITMAPCOREHEADER has 2 byte width and height, so the fields after them are at different offsets
+    let (height_offset, planes_offset, bitcount_offset) = if dib_header.size == 12 { (20, 22, 24) } else { (22, 26, 28) };
+    if dib_header.width == 0 || dib_header.width > i32::MAX as u32 {
+      return Err(corrupt("width", HEADER_OFFSET+4, format!("invalid width {}", dib_header.width as i32)));
+    }
+    if dib_header.height == 0 || dib_header.height == i32::MIN {
+      return Err(corrupt("height", height_offset, format!("invalid height {}", dib_header.height)));
+    }
+    if dib_header.planes != 1 {
+      return Err(corrupt("planes", planes_offset, format!("planes must be 1, not {}", dib_header.planes)));
+    }
+    let compression: &str = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    //the pixel array of jpeg and png images is a whole jpeg or png file, so the bitcount can be 0
+    let valid_bitcount: bool = match compression {
+      "BI_RGB" => [1, 2, 4, 8, 16, 24, 32].contains(&dib_header.bitcount),
+      "BI_RLE8" => dib_header.bitcount == 8,
+      "BI_RLE4" => dib_header.bitcount == 4,
+      "BI_RLE24" => dib_header.bitcount == 24,
+      "BI_HUFFMAN1D" => dib_header.bitcount == 1,
+      "BI_BITFIELDS" | "BI_ALPHABITFIELDS" => dib_header.bitcount == 16 || dib_header.bitcount == 32,
+      _ => [0, 1, 2, 4, 8, 16, 24, 32].contains(&dib_header.bitcount),
+    };
+    if !valid_bitcount && (compression == "BI_RGB" || compression == "BI_JPEG" || compression == "BI_PNG") {
+      return Err(ErrorKind::UnsupportedBitDepth(dib_header.bitcount));
+    } else if !valid_bitcount {
+      return Err(corrupt("bitcount", bitcount_offset, format!("bitcount {} is not valid for {}", dib_header.bitcount, compression)));
+    }
+    //the color table or extra bit masks go between the DIB header and the pixel array
+    let headers_end: usize = HEADER_OFFSET+dib_header.size as usize;
+    let offset: usize = header.bfOffBits as usize;
+    if offset < headers_end || offset > file_length {
+      return Err(corrupt("bfOffBits", 10, format!("pixel array offset {} is outside of {}..{}", offset, headers_end, file_length)));
+    }
+    if dib_header.size == 40 && (compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS") {
+      let masks_length: usize = if compression == "BI_BITFIELDS" { 12 } else { 16 };
+      if headers_end+masks_length > offset {
+        return Err(corrupt("bfOffBits", 10, format!("no room for the {} bytes of bit masks before the pixel array", masks_length)));
+      }
+    }
+    //the color table goes between the DIB header and the pixel array too
+    if let Some(clr_used) = dib_header.ClrUsed {
+      let max_colors: u64 = 1 << dib_header.bitcount;
+      if u64::from(clr_used) > max_colors {
+        return Err(corrupt("ClrUsed", HEADER_OFFSET+32, format!("{} colors is more than the {} a {} bit image can use", clr_used, max_colors, dib_header.bitcount)));
+      }
+      //headers without ClrUsed (BITMAPCOREHEADER and cut off OS/2 headers) get the color table length from the space before the pixel array instead
+      if (1..=8).contains(&dib_header.bitcount) {
+        let colors: u64 = if clr_used > 0 { u64::from(clr_used) } else { max_colors };
+        let table_end: u64 = headers_end as u64+colors*4;
+        if table_end > offset as u64 {
+          return Err(corrupt("bfOffBits", 10, format!("no room for the {} color table entries before the pixel array", colors)));
+        }
+      }
+    }
+    //the pixel array has to fit in the file
+    let pixel_array_length: u64 = (file_length-offset) as u64;
+    if compression == "BI_RGB" || compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS" {
+      let row_length: u64 = (u64::from(dib_header.bitcount)*u64::from(dib_header.width)).div_ceil(32)*4;
+      let needed: u64 = row_length*u64::from(dib_header.height.unsigned_abs());
+      if needed > pixel_array_length {
+        return Err(corrupt("height", height_offset, format!("pixel array needs {} bytes but only {} are left in the file", needed, pixel_array_length)));
+      }
+    } else {
+      //compressed pixel arrays can decode to much more than is in the file, so they are held to the size of an image `BMP::new()` can make
+      if BMP::check_new_size(dib_header.width, dib_header.height.unsigned_abs()).is_err() {
+        return Err(corrupt("height", height_offset, format!("a {}x{} compressed image is too large to decode", dib_header.width, dib_header.height.unsigned_abs())));
+      }
+      if let Some(sizeimage) = dib_header.sizeimage {
+        if u64::from(sizeimage) > pixel_array_length {
+          return Err(corrupt("sizeimage", HEADER_OFFSET+20, format!("compressed pixel array is {} bytes but only {} are left in the file", sizeimage, pixel_array_length)));
+        }
+      }
+    }
+    //an embedded or linked color profile has to fit in the file
+    let cstype = dib_header.CSType;
+    if dib_header.size == 124 && (cstype == Some(ColorSpaceType::ProfileEmbedded) || cstype == Some(ColorSpaceType::ProfileLinked)) {
+      let profile_data: u64 = u64::from(dib_header.ProfileData.unwrap_or(0));
+      let profile_end: u64 = HEADER_OFFSET as u64+profile_data+u64::from(dib_header.ProfileSize.unwrap_or(0));
+      if profile_data == 0 || profile_end > file_length as u64 {
+        return Err(corrupt("ProfileData", HEADER_OFFSET+112, format!("color profile ends at {} but the file is {} bytes", profile_end, file_length)));
+      }
+    }
+    return Ok(());
   }
   //utilities
   fn bytes_to_int(bytes: [u8; 4]) -> u32 {
@@ -479,14 +1225,6 @@ impl BMP {
     //1024 bytes per kilobyte
     bytes/1024
   }
-  fn vec_to_4u8_array(vector: Vec<u8>) -> [u8; 4] {
-    let mut array: [u8; 4] = [0u8; 4];
-    //vector.len() should be 4
-    for i in 0..vector.len() {
-      array[i] = vector[i];
-    }
-    return array;
-  }
   fn vec_to_2u8_array(vector: Vec<u8>) -> [u8; 2] {
     let mut array: [u8; 2] = [0u8; 2];
     //vector.len() should be 2
@@ -503,7 +1241,16 @@ impl BMP {
     }
     return array;
   }
-  fn int_to_compression(int: u32) -> String {
+  //OS/2 2.x headers use 3 and 4 for different compressions than windows
+  fn int_to_compression(int: u32, dib_size: u32) -> Result<String, ErrorKind> {
+    if DIBHEADER::is_os2_size(dib_size) {
+      match int {
+        3 => return Ok("BI_HUFFMAN1D".to_string()),
+        4 => return Ok("BI_RLE24".to_string()),
+        5 | 6 => return Err(ErrorKind::CorruptHeader { field: "compression".to_string(), offset: HEADER_OFFSET+16, reason: format!("compression {} is not valid in an OS/2 header", int) }),
+        _ => {},
+      }
+    }
     let compression_table: HashMap<u32, String> = HashMap::from([
       (0, "BI_RGB".to_string()),
       (1, "BI_RLE8".to_string()),
@@ -513,28 +1260,41 @@ impl BMP {
       (5, "BI_PNG".to_string()),
       (6, "BI_ALPHABITFIELDS".to_string())
     ]);
-    return compression_table.get(&int).unwrap().to_string();
-  }
-  fn int_to_intent(int: u32) -> String {
-    let intent_table: HashMap<u32, String> = HashMap::from([
-      (0, "LCS_GM_ABS_COLORIMETRIC".to_string()),
-      (1, "LCS_GM_BUSINESS".to_string()),
-      (2, "LCS_WINDOWS_COLOR_SPACE".to_string()),
-      (3, "LCS_GM_GRAPHICS".to_string()),
-      (4, "LCS_GM_IMAGES".to_string())
-    ]);
-    return intent_table.get(&int).unwrap().to_string();
+    match compression_table.get(&int) {
+      Some(compression) => return Ok(compression.to_string()),
+      None => return Err(ErrorKind::CorruptHeader { field: "compression".to_string(), offset: HEADER_OFFSET+16, reason: format!("unknown compression {}", int) }),
+    }
+  }
+  //check a 32 bit image like `BMP::new()` makes can be this size, since the file size has to fit in 4 bytes
+  fn check_new_size(width: u32, height: u32) -> Result<(), ErrorKind> {
+    if width == 0 || height == 0 {
+      return Err(ErrorKind::InvalidParameter("width and height must be more than 0".to_string()));
+    }
+    //the height is stored signed, and the whole file has to fit in the 4 byte file size
+    if height > i32::MAX as u32 || u64::from(width)*u64::from(height)*4+14+124 > u64::from(u32::MAX) {
+      return Err(ErrorKind::InvalidParameter(format!("a {}x{} image is too large", width, height)));
+    }
+    return Ok(());
+  }
+  //copy pixel `source_x` of a row of pixels smaller than a byte to pixel `x` of another row, they are packed starting from the highest bits
+  fn copy_packed_pixel(source_row: &[u8], source_x: usize, row: &mut [u8], x: usize, bitcount: usize) {
+    let mask: u8 = ((1u16 << bitcount)-1) as u8;
+    let (source_bit, bit) = (source_x*bitcount, x*bitcount);
+    let index = (source_row[source_bit/8] >> (8-bitcount-source_bit % 8)) & mask;
+    let shift = 8-bitcount-bit % 8;
+    let byte = &mut row[bit/8];
+    *byte = (*byte & !(mask << shift)) | (index << shift);
   }
   //coordinate utilities
   fn deg_to_rad(deg: f64) -> f64 {
     return deg/180.0*PI;
   }
-  fn rotate_point(rad: f64, point: [u16; 2], center: [u16; 2]) -> (i32, i32) {
+  fn rotate_point(rad: f64, point: [f64; 2], center: [f64; 2]) -> [f64; 2] {
     //x2 = x cos - y sin
-    let x2: i32 = ((f64::from(point[0])-f64::from(center[0])) * rad.cos() - (f64::from(point[1])-f64::from(center[1])) * rad.sin()).round() as i32 + i32::from(center[0]);
+    let x2: f64 = (point[0]-center[0]) * rad.cos() - (point[1]-center[1]) * rad.sin() + center[0];
     //y2 = y cos + x sin
-    let y2: i32 = ((f64::from(point[1])-f64::from(center[1])) * rad.cos() + (f64::from(point[0])-f64::from(center[0])) * rad.sin()).round() as i32 + i32::from(center[1]);
-    return (x2, y2);
+    let y2: f64 = (point[1]-center[1]) * rad.cos() + (point[0]-center[0]) * rad.sin() + center[1];
+    return [x2, y2];
   }
   /// Check if a point is in an enclosure of points, using the even-odd rule.
   pub fn point_in_enclosure(point: [u16; 2], enclosure: &Vec<[u16; 2]>) -> bool {
@@ -729,57 +1489,18 @@ impl BMP {
   /// Returns the difference between two loaded BMP files. Works even if the two files are different height and width.
   pub fn diff(bmp1: &BMP, bmp2: &BMP) -> Result<ImageDiff, ErrorKind> {
     //compare two bmps, should work even if the two bmps are different sizes
+    let image1 = bmp1.to_image()?;
+    let image2 = bmp2.to_image()?;
     //find the largest height and width of the two bmps
-    let dib_header1 = bmp1.get_dib_header();
-    let dib_header1 = match dib_header1 {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let dib_header2 = bmp2.get_dib_header();
-    let dib_header2 = match dib_header2 {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let pixel_data1 = bmp1.get_pixel_data();
-    let pixel_data1 = match pixel_data1 {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let pixel_data2 = bmp2.get_pixel_data();
-    let pixel_data2 = match pixel_data2 {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let largest_height: u32;
-    if dib_header1.height.abs() > dib_header2.height.abs() {
-      largest_height = dib_header1.height.abs() as u32;
-    } else {
-      //will trigger not only if bmp2 has more height, but also if both are same height
-      //but if both are same height, doesn't matter which we pick
-      largest_height = dib_header2.height.abs() as u32;
-    }
-    let largest_width: u32;
-    if dib_header1.width > dib_header2.width {
-      largest_width = dib_header1.width;
-    } else {
-      largest_width = dib_header2.width;
-    }
+    let largest_height: u32 = image1.get_height().max(image2.get_height());
+    let largest_width: u32 = image1.get_width().max(image2.get_width());
     //find the different pixels
     let mut diff_pixels: Vec<PixelDiff> = Vec::new();
     for y in 0..largest_height {
       for x in 0..largest_width {
-        let color1: Option<[u8; 4]>;
-        let color2: Option<[u8; 4]>;
-        if x >= dib_header1.width || y >= dib_header1.height.abs() as u32 {
-          color1 = None;
-        } else {
-          color1 = Some(bmp1.get_color_of_px_efficient(x as usize, y as usize, &dib_header1, &pixel_data1).unwrap());
-        }
-        if x >= dib_header2.width || y >= dib_header2.height.abs() as u32 {
-          color2 = None;
-        } else {
-          color2 = Some(bmp2.get_color_of_px_efficient(x as usize, y as usize, &dib_header2, &pixel_data2).unwrap());
-        }
+        //out of bounds if the image is smaller
+        let color1: Option<[u8; 4]> = image1.get_color_of_pixel(x as usize, y as usize).ok();
+        let color2: Option<[u8; 4]> = image2.get_color_of_pixel(x as usize, y as usize).ok();
         if color1 != color2 {
           diff_pixels.push(PixelDiff {
             coord: [x as u16, y as u16],
@@ -790,8 +1511,8 @@ impl BMP {
       }
     }
     return Ok(ImageDiff {
-      image1_size: [dib_header1.width, dib_header1.height.abs() as u32],
-      image2_size: [dib_header2.width, dib_header2.height.abs() as u32],
+      image1_size: [image1.get_width(), image1.get_height()],
+      image2_size: [image2.get_width(), image2.get_height()],
       diff: diff_pixels,
     });
   }
@@ -800,6 +1521,14 @@ impl BMP {
   pub fn is_from_file(&self) -> bool {
     self.from_file
   }
+  /// Set how colors are written to images with a color table (1, 2, 4 and 8 bit images). See `PalettePolicy`.
+  pub fn set_palette_policy(&mut self, policy: PalettePolicy) {
+    self.palette_policy = policy;
+  }
+  /// Get how colors are written to images with a color table.
+  pub fn get_palette_policy(&self) -> PalettePolicy {
+    self.palette_policy
+  }
   //dib header related
   /// Get the DIB header for the BMP file. There are several different versions of the DIB header, documented [here](https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-header-types).
   /// 
@@ -809,7 +1538,13 @@ impl BMP {
     //this will not work because there may be other data besides the DIB header
     //let dib_size: i32 = self.get_offset()-14;
     //instead we will read the first 4 bytes after the header, which *should* specify the DIB header size, so we can figure out what kind of header it is
+    if self.contents.len() < HEADER_OFFSET+4 {
+      return Err(ErrorKind::CorruptHeader { field: "size".to_string(), offset: HEADER_OFFSET, reason: "file ends before the DIB header".to_string() });
+    }
     let dib_size: u32 = BMP::bytes_to_int(self.contents[HEADER_OFFSET..HEADER_OFFSET+4].try_into().unwrap());
+    if self.contents.len() < HEADER_OFFSET+dib_size as usize {
+      return Err(ErrorKind::CorruptHeader { field: "size".to_string(), offset: HEADER_OFFSET, reason: format!("DIB header is {} bytes but the file ends after {}", dib_size, self.contents.len()-HEADER_OFFSET) });
+    }
     let dib_header: DIBHEADER;
     match dib_size {
       12 => {
@@ -839,99 +1574,82 @@ impl BMP {
           ProfileData: None,
           ProfileSize: None,
           Reserved: None,
+          Units: None,
+          Padding: None,
+          Recording: None,
+          Rendering: None,
+          Size1: None,
+          Size2: None,
+          ColorEncoding: None,
+          Identifier: None,
         };
       },
-      40 => {
-        //"BITMAPINFOHEADER"
-        dib_header = DIBHEADER {
-          size: dib_size,
-          width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
-          height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
-          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
-          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
-          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()))),
-          sizeimage: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+20..HEADER_OFFSET+24].try_into().unwrap())),
-          XPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+24..HEADER_OFFSET+28].try_into().unwrap())),
-          YPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+28..HEADER_OFFSET+32].try_into().unwrap())),
-          ClrUsed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+32..HEADER_OFFSET+36].try_into().unwrap())),
-          ClrImportant: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+36..HEADER_OFFSET+40].try_into().unwrap())),
-          RedMask: None,
-          GreenMask: None,
-          BlueMask: None,
-          AlphaMask: None,
-          CSType: None,
-          Endpoints: None,
-          GammaRed: None,
-          GammaGreen: None,
-          GammaBlue: None,
-          Intent: None,
-          ProfileData: None,
-          ProfileSize: None,
-          Reserved: None,
+      16..=64 | 108 | 124 => {
+        //"BITMAPINFOHEADER", "BITMAPV2INFOHEADER", "BITMAPV3INFOHEADER", "BITMAPV4HEADER", "BITMAPV5HEADER"
+        //each version adds fields to the end of the previous version
+        //"OS22XBITMAPHEADER" is 64 bytes, with the same first 40 bytes as BITMAPINFOHEADER, and can be cut off after any field from the bitcount on
+        let is_os2: bool = DIBHEADER::is_os2_size(dib_size);
+        //dword 4 bytes
+        //long 4 bytes
+        //CIEXYZTRIPLE 36 bytes
+        let read_u32 = |offset: usize| -> u32 {
+          BMP::bytes_to_int(self.contents[HEADER_OFFSET+offset..HEADER_OFFSET+offset+4].try_into().unwrap())
         };
-      },
-      108 => {
-        //"BITMAPV4HEADER"
+        let read_i32 = |offset: usize| -> i32 {
+          BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+offset..HEADER_OFFSET+offset+4].try_into().unwrap())
+        };
+        //only read the field if this version of the header has it
+        let read_u32_from = |min_size: u32, offset: usize| -> Option<u32> {
+          if dib_size >= min_size && !is_os2 { Some(read_u32(offset)) } else { None }
+        };
+        //only read the field if this is an OS/2 header that is not cut off before it
+        let read_os2_u32 = |offset: usize| -> Option<u32> {
+          if is_os2 && dib_size as usize >= offset+4 { Some(read_u32(offset)) } else { None }
+        };
+        let read_os2_u16 = |offset: usize| -> Option<u16> {
+          if is_os2 && dib_size as usize >= offset+2 { Some(BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+offset..HEADER_OFFSET+offset+2].try_into().unwrap())) } else { None }
+        };
+        //OS/2 headers can be cut off before any of these
+        let read_info_u32 = |offset: usize| -> Option<u32> {
+          if dib_size as usize >= offset+4 { Some(read_u32(offset)) } else { None }
+        };
+        let compression: Option<String> = if dib_size >= 20 { Some(BMP::int_to_compression(read_u32(16), dib_size)?) } else { None };
+        let intent: Option<RenderingIntent> = if dib_size >= 124 && !is_os2 { Some(RenderingIntent::from_int(read_u32(108))) } else { None };
         dib_header = DIBHEADER {
           size: dib_size,
-          width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
-          height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
+          width: read_u32(4),
+          height: read_i32(8),
           planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
           bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
-          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()))),
-          sizeimage: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+20..HEADER_OFFSET+24].try_into().unwrap())),
-          XPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+24..HEADER_OFFSET+28].try_into().unwrap())),
-          YPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+28..HEADER_OFFSET+32].try_into().unwrap())),
-          ClrUsed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+32..HEADER_OFFSET+36].try_into().unwrap())),
-          ClrImportant: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+36..HEADER_OFFSET+40].try_into().unwrap())),
-          RedMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+40..HEADER_OFFSET+44].try_into().unwrap())),
-          GreenMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+44..HEADER_OFFSET+48].try_into().unwrap())),
-          BlueMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+48..HEADER_OFFSET+52].try_into().unwrap())),
-          AlphaMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+52..HEADER_OFFSET+56].try_into().unwrap())),
-          CSType: Some(BMP::bytes_to_string(&self.contents[HEADER_OFFSET+56..HEADER_OFFSET+60])),
+          compression,
+          sizeimage: read_info_u32(20),
+          XPelsPerMeter: read_info_u32(24),
+          YPelsPerMeter: read_info_u32(28),
+          ClrUsed: read_info_u32(32),
+          ClrImportant: read_info_u32(36),
+          RedMask: read_u32_from(52, 40),
+          GreenMask: read_u32_from(52, 44),
+          BlueMask: read_u32_from(52, 48),
+          AlphaMask: read_u32_from(56, 52),
+          //each byte is one char, so that any value can be written back exactly
+          CSType: if dib_size >= 108 && !is_os2 { Some(ColorSpaceType::from_int(read_u32(56))) } else { None },
           //rgb
-          Endpoints: Some([[BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+60..HEADER_OFFSET+64].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+64..HEADER_OFFSET+68].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+68..HEADER_OFFSET+72].try_into().unwrap())], [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+72..HEADER_OFFSET+76].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+76..HEADER_OFFSET+80].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+80..HEADER_OFFSET+84].try_into().unwrap())], [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+84..HEADER_OFFSET+88].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+88..HEADER_OFFSET+92].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+92..HEADER_OFFSET+96].try_into().unwrap())]]),
-          GammaRed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+96..HEADER_OFFSET+100].try_into().unwrap())),
-          GammaGreen: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+100..HEADER_OFFSET+104].try_into().unwrap())),
-          GammaBlue: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+104..HEADER_OFFSET+108].try_into().unwrap())),
-          Intent: None,
-          ProfileData: None,
-          ProfileSize: None,
-          Reserved: None,
-        };
-      },
-      124 => {
-        //"BITMAPV5HEADER"
-        //dword 4 bytes
-        //long 4 bytes
-        //CIEXYZTRIPLE 36 bytes
-        dib_header = DIBHEADER {
-          size: dib_size,
-          width: BMP::bytes_to_int(self.contents[HEADER_OFFSET+4..HEADER_OFFSET+8].try_into().unwrap()),
-          height: BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+8..HEADER_OFFSET+12].try_into().unwrap()),
-          planes: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+12..HEADER_OFFSET+14].try_into().unwrap()),
-          bitcount: BMP::two_bytes_to_int(self.contents[HEADER_OFFSET+14..HEADER_OFFSET+16].try_into().unwrap()),
-          compression: Some(BMP::int_to_compression(BMP::bytes_to_int(self.contents[HEADER_OFFSET+16..HEADER_OFFSET+20].try_into().unwrap()))),
-          sizeimage: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+20..HEADER_OFFSET+24].try_into().unwrap())),
-          XPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+24..HEADER_OFFSET+28].try_into().unwrap())),
-          YPelsPerMeter: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+28..HEADER_OFFSET+32].try_into().unwrap())),
-          ClrUsed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+32..HEADER_OFFSET+36].try_into().unwrap())),
-          ClrImportant: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+36..HEADER_OFFSET+40].try_into().unwrap())),
-          RedMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+40..HEADER_OFFSET+44].try_into().unwrap())),
-          GreenMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+44..HEADER_OFFSET+48].try_into().unwrap())),
-          BlueMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+48..HEADER_OFFSET+52].try_into().unwrap())),
-          AlphaMask: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+52..HEADER_OFFSET+56].try_into().unwrap())),
-          CSType: Some(BMP::bytes_to_string(&self.contents[HEADER_OFFSET+56..HEADER_OFFSET+60])),
-          //rgb
-          Endpoints: Some([[BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+60..HEADER_OFFSET+64].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+64..HEADER_OFFSET+68].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+68..HEADER_OFFSET+72].try_into().unwrap())],  [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+72..HEADER_OFFSET+76].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+76..HEADER_OFFSET+80].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+80..HEADER_OFFSET+84].try_into().unwrap())], [BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+84..HEADER_OFFSET+88].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+88..HEADER_OFFSET+92].try_into().unwrap()), BMP::bytes_to_signed_int(self.contents[HEADER_OFFSET+92..HEADER_OFFSET+96].try_into().unwrap())]]),
-          GammaRed: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+96..HEADER_OFFSET+100].try_into().unwrap())),
-          GammaGreen: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+100..HEADER_OFFSET+104].try_into().unwrap())),
-          GammaBlue: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+104..HEADER_OFFSET+108].try_into().unwrap())),
-          Intent: Some(BMP::int_to_intent(BMP::bytes_to_int(self.contents[HEADER_OFFSET+108..HEADER_OFFSET+112].try_into().unwrap()))),
-          //Some(BMP::bytes_to_string(&self.contents[HEADER_OFFSET+108..HEADER_OFFSET+112]))
-          ProfileData: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+112..HEADER_OFFSET+116].try_into().unwrap()) as u16),
-          ProfileSize: Some(BMP::bytes_to_int(self.contents[HEADER_OFFSET+116..HEADER_OFFSET+120].try_into().unwrap()) as u16),
-          Reserved: Some(self.contents[HEADER_OFFSET+120..HEADER_OFFSET+124].try_into().unwrap()),
+          Endpoints: if dib_size >= 108 && !is_os2 { Some([[read_i32(60), read_i32(64), read_i32(68)], [read_i32(72), read_i32(76), read_i32(80)], [read_i32(84), read_i32(88), read_i32(92)]]) } else { None },
+          GammaRed: read_u32_from(108, 96),
+          GammaGreen: read_u32_from(108, 100),
+          GammaBlue: read_u32_from(108, 104),
+          Intent: intent,
+          ProfileData: read_u32_from(124, 112),
+          ProfileSize: read_u32_from(124, 116),
+          Reserved: if dib_size >= 124 { Some(self.contents[HEADER_OFFSET+120..HEADER_OFFSET+124].to_vec()) } else { None },
+          Units: read_os2_u16(40),
+          Padding: read_os2_u16(42),
+          Recording: read_os2_u16(44),
+          Rendering: read_os2_u16(46),
+          Size1: read_os2_u32(48),
+          Size2: read_os2_u32(52),
+          ColorEncoding: read_os2_u32(56),
+          Identifier: read_os2_u32(60),
         };
       },
       _ => {
@@ -941,20 +1659,102 @@ impl BMP {
     }
     return Ok(dib_header);
   }
+  /// Replace the DIB header of the file. The new DIB header must be the same version (`size`) as the current one.
+  pub fn set_dib_header(&mut self, dib_header: DIBHEADER) -> Result<(), ErrorKind> {
+    let current_dib_header = self.get_dib_header()?;
+    if dib_header.size != current_dib_header.size {
+      return Err(ErrorKind::InvalidParameter("DIB header must be the same size as the current DIB header".to_string()));
+    }
+    let bytes: Vec<u8> = dib_header.to_bytes()?;
+    self.contents[HEADER_OFFSET..HEADER_OFFSET+bytes.len()].copy_from_slice(&bytes);
+    return Ok(());
+  }
+  //orientation
+  /// See if the rows are stored from top to bottom (negative height), instead of from bottom to top. Either way, `(0, 0)` is the upper left corner of the image.
+  pub fn is_top_down(&self) -> Result<bool, ErrorKind> {
+    return Ok(self.get_dib_header()?.height < 0);
+  }
+  /// Change whether the rows are stored from top to bottom or from bottom to top, by reversing the rows of the pixel array and negating the height. The image looks the same.
+  ///
+  /// Only uncompressed images with a BITMAPINFOHEADER or later can be top down.
+  ///
+  pub fn set_top_down(&mut self, top_down: bool) -> Result<(), ErrorKind> {
+    let mut dib_header = self.get_dib_header()?;
+    if (dib_header.height < 0) == top_down {
+      return Ok(());
+    }
+    if dib_header.size < 40 || dib_header.is_os2() {
+      return Err(ErrorKind::InvalidParameter("BITMAPCOREHEADER and OS/2 images are always bottom up".to_string()));
+    }
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    if compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS" {
+      return Err(ErrorKind::UnsupportedCompression(compression.to_string()));
+    }
+    let row_length = (u32::from(dib_header.bitcount)*dib_header.width).div_ceil(32) as usize*4;
+    let height = dib_header.height.unsigned_abs() as usize;
+    let start = self.get_offset() as usize;
+    let end = start+row_length*height;
+    if end > self.contents.len() {
+      return Err(ErrorKind::CorruptPixelData("pixel array is shorter than the image".to_string()));
+    }
+    let rows: Vec<u8> = self.contents[start..end].chunks_exact(row_length.max(1)).rev().flatten().copied().collect();
+    self.contents[start..end].copy_from_slice(&rows);
+    dib_header.height = -dib_header.height;
+    return self.set_dib_header(dib_header);
+  }
+  //color space, only in BITMAPV4HEADER and BITMAPV5HEADER
+  fn get_color_space_dib_header(&self, min_size: u32) -> Result<DIBHEADER, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    if dib_header.size < min_size {
+      let version = if min_size == 124 { "BITMAPV5HEADER" } else { "BITMAPV4HEADER or BITMAPV5HEADER" };
+      return Err(ErrorKind::InvalidParameter(format!("the DIB header must be a {}", version)));
+    }
+    return Ok(dib_header);
+  }
+  /// Change the color space of the image. For `ColorSpaceType::CalibratedRgb`, also set the endpoints and gamma with `set_endpoints()` and `set_gamma()`.
+  /// Color profiles are attached with `set_color_profile()` instead.
+  pub fn set_color_space_type(&mut self, color_space_type: ColorSpaceType) -> Result<(), ErrorKind> {
+    if color_space_type == ColorSpaceType::ProfileEmbedded || color_space_type == ColorSpaceType::ProfileLinked {
+      return Err(ErrorKind::InvalidParameter("use set_color_profile() to attach a color profile".to_string()));
+    }
+    let mut dib_header = self.get_color_space_dib_header(108)?;
+    dib_header.CSType = Some(color_space_type);
+    return self.set_dib_header(dib_header);
+  }
+  /// Change the red, green and blue endpoints (CIEXYZ, each as x, y, z), which are stored as 2.30 fixed point, so must be in the range -2 to 2.
+  pub fn set_endpoints(&mut self, endpoints: [[f64; 3]; 3]) -> Result<(), ErrorKind> {
+    let mut dib_header = self.get_color_space_dib_header(108)?;
+    if endpoints.iter().flatten().any(|value| !(-2.0..2.0).contains(value)) {
+      return Err(ErrorKind::InvalidParameter("endpoints must be between -2 and 2".to_string()));
+    }
+    dib_header.Endpoints = Some(endpoints.map(|endpoint| endpoint.map(|value| (value*f64::from(1 << 30)).round() as i32)));
+    return self.set_dib_header(dib_header);
+  }
+  /// Change the red, green and blue gamma, which are stored as 16.16 fixed point, so must be in the range 0 to 65536.
+  pub fn set_gamma(&mut self, gamma: [f64; 3]) -> Result<(), ErrorKind> {
+    let mut dib_header = self.get_color_space_dib_header(108)?;
+    if gamma.iter().any(|value| !(0.0..65536.0).contains(value)) {
+      return Err(ErrorKind::InvalidParameter("gamma must be between 0 and 65536".to_string()));
+    }
+    let [red, green, blue] = gamma.map(|value| (value*65536.0).round() as u32);
+    dib_header.GammaRed = Some(red);
+    dib_header.GammaGreen = Some(green);
+    dib_header.GammaBlue = Some(blue);
+    return self.set_dib_header(dib_header);
+  }
+  /// Change the rendering intent. Only BITMAPV5HEADER has one.
+  pub fn set_intent(&mut self, intent: RenderingIntent) -> Result<(), ErrorKind> {
+    let mut dib_header = self.get_color_space_dib_header(124)?;
+    dib_header.Intent = Some(intent);
+    return self.set_dib_header(dib_header);
+  }
   //extra bit masks
-  fn get_extra_bit_masks(&self) -> Result<EXTRA_BIT_MASKS, ErrorKind> {
-    //should be mutable instead of redefined, maybe
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
+  fn get_extra_bit_masks(&self, dib_header: &DIBHEADER) -> Result<EXTRA_BIT_MASKS, ErrorKind> {
     match dib_header.size {
       40 => {
-        //see previous comment, should be mutable instead of redefined, maybe
         //offset should be 14+40
         const TOTAL_OFFSET: usize = 54;
-        let compression = dib_header.compression.unwrap();
+        let compression = dib_header.compression.as_ref().unwrap();
         if compression == "BI_BITFIELDS" {
           return Ok(EXTRA_BIT_MASKS::BI_BITFIELDS_MASKS(BI_BITFIELDS_MASKS {
             red: BMP::bytes_to_int(self.contents[TOTAL_OFFSET..TOTAL_OFFSET+4].try_into().unwrap()),
@@ -975,6 +1775,65 @@ impl BMP {
       _ => return Err(ErrorKind::DoesNotExist),
     }
   }
+  /// Get the masks used to read and write the red, green, blue and alpha channels of 16 and 32 bit pixels.
+  ///
+  /// For `BI_BITFIELDS` and `BI_ALPHABITFIELDS` images, these are the masks in the DIB header (BITMAPV4HEADER and newer),
+  /// or the extra bit masks that follow a BITMAPINFOHEADER. Uncompressed (`BI_RGB`) images use the default layouts:
+  /// X1R5G5B5 for 16 bit and BGRA for 32 bit.
+  ///
+  pub fn get_bit_masks(&self) -> Result<BI_ALPHABITFIELDS_MASKS, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    return self.get_bit_masks_efficient(&dib_header);
+  }
+  /// More efficient version of `get_bit_masks` that accepts the DIB header as a reference.
+  pub fn get_bit_masks_efficient(&self, dib_header: &DIBHEADER) -> Result<BI_ALPHABITFIELDS_MASKS, ErrorKind> {
+    let compression: &str = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    match compression {
+      "BI_BITFIELDS" | "BI_ALPHABITFIELDS" => {
+        if dib_header.size == 40 {
+          match self.get_extra_bit_masks(dib_header)? {
+            EXTRA_BIT_MASKS::BI_BITFIELDS_MASKS(masks) => {
+              return Ok(BI_ALPHABITFIELDS_MASKS {
+                red: masks.red,
+                green: masks.green,
+                blue: masks.blue,
+                alpha: 0,
+              });
+            },
+            EXTRA_BIT_MASKS::BI_ALPHABITFIELDS_MASKS(masks) => return Ok(masks),
+          }
+        }
+        if let (Some(red), Some(green), Some(blue)) = (dib_header.RedMask, dib_header.GreenMask, dib_header.BlueMask) {
+          return Ok(BI_ALPHABITFIELDS_MASKS {
+            red,
+            green,
+            blue,
+            alpha: dib_header.AlphaMask.unwrap_or(0),
+          });
+        }
+        return Err(ErrorKind::DoesNotExist);
+      },
+      "BI_RGB" if dib_header.bitcount == 16 => {
+        //X1R5G5B5, 5 bits for each of r, g, b and the highest bit unused
+        return Ok(BI_ALPHABITFIELDS_MASKS {
+          red: 0x7C00,
+          green: 0x03E0,
+          blue: 0x001F,
+          alpha: 0,
+        });
+      },
+      "BI_RGB" if dib_header.bitcount == 32 => {
+        //stored as blue, green, red, alpha
+        return Ok(BI_ALPHABITFIELDS_MASKS {
+          red: 0x00FF0000,
+          green: 0x0000FF00,
+          blue: 0x000000FF,
+          alpha: 0xFF000000,
+        });
+      },
+      _ => return Err(ErrorKind::DoesNotExist),
+    }
+  }
   //color table
   //in between pixel array and everything else, I guess?
   //update: use the dib header's 'size' attribute - the actual size
@@ -992,7 +1851,7 @@ impl BMP {
     let end: u32;
     //either rgbtriple or masks or 
     let data_type: &str;
-    //12, 40, 108, 124
+    //12, 16, 40, 52, 56, 64, 108, 124
     match dib_header.size {
       /*DIBHEADER::BITMAPCOREHEADER(b) | DIBHEADER::BITMAPINFOHEADER(b) | DIBHEADER::BITMAPV4HEADER(b) | DIBHEADER::BITMAPV5HEADER(b) => {
         size = b.size;
@@ -1004,14 +1863,15 @@ impl BMP {
         //RGBTRIPLE, 3 bytes
         data_type = "rgbtriple";
       },
-      40 | 108 | 124 => {
+      16..=64 | 108 | 124 => {
         //16 bit array instead of rgbquad is possible, but should not be used if file is "stored in a file or transferred to another application" https://www.digicamsoft.com/bmp/bmp.html
         offset += dib_header.size;
         end = self.get_header().bfOffBits;
         //https://docs.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-bitmapinfo
         //if compression is BI_RGB, using RGBQUAD 
         //size of array is biClrUsed
-        let compression = dib_header.compression.unwrap();
+        //OS/2 headers can be cut off before the compression field
+        let compression: &str = dib_header.compression.as_deref().unwrap_or("BI_RGB");
         if compression == "BI_BITFIELDS" && (dib_header.bitcount == 16 || dib_header.bitcount == 32) {
           //extra bit masks, not color table. return error, or maybe extra bit masks? hmm
           return Err(ErrorKind::UseExtraBitMasks);
@@ -1027,30 +1887,109 @@ impl BMP {
         return Err(ErrorKind::DoesNotExist);
       },
     };
+    //3 bytes for rgbtriple, 4 bytes for rgbquad
+    let entry_size: u32 = if data_type == "rgbtriple" { 3 } else { 4 };
+    let entry_num: u32 = BMP::get_color_table_length(&dib_header, (end-offset)/entry_size);
     let color_table: ColorTable;
     if data_type == "rgbtriple" {
       let mut color_table_vec: Vec::<[u8; 3]> = Vec::new();
       //3 bytes
-      for i in 0..(f64::from((end-offset)/3).floor() as i64) {
-        let ii = i as u32;
-        color_table_vec.push([BMP::byte_to_int(self.contents[(offset+ii*3) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*3+1) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*3+2) as usize]) as u8]);
+      for i in 0..entry_num {
+        color_table_vec.push([BMP::byte_to_int(self.contents[(offset+i*3) as usize]), BMP::byte_to_int(self.contents[(offset+i*3+1) as usize]), BMP::byte_to_int(self.contents[(offset+i*3+2) as usize])]);
       }
       color_table = ColorTable::RGBTRIPLE(color_table_vec);
     } else /*if "rgbquad" == data_type*/ {
       let mut color_table_vec: Vec::<[u8; 4]> = Vec::new();
       //4 bytes
-      for i in 0..(f64::from((end-offset)/4).floor() as i64) {
-        let ii = i as u32;
-        color_table_vec.push([BMP::byte_to_int(self.contents[(offset+ii*4) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*4+1) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*4+2) as usize]) as u8, BMP::byte_to_int(self.contents[(offset+ii*4+3) as usize]) as u8]);
+      for i in 0..entry_num {
+        color_table_vec.push([BMP::byte_to_int(self.contents[(offset+i*4) as usize]), BMP::byte_to_int(self.contents[(offset+i*4+1) as usize]), BMP::byte_to_int(self.contents[(offset+i*4+2) as usize]), BMP::byte_to_int(self.contents[(offset+i*4+3) as usize])]);
       }
       color_table = ColorTable::RGBQUAD(color_table_vec);
     }
     return Ok(color_table);
   }
+  //the amount of colors in the color table, given how many entries fit before the pixel array
+  fn get_color_table_length(dib_header: &DIBHEADER, entries_in_file: u32) -> u32 {
+    if dib_header.bitcount > 8 {
+      return entries_in_file;
+    }
+    //size of array is biClrUsed, or if that is 0, the max amount of colors for the bitcount
+    let max_colors: u32 = match dib_header.ClrUsed {
+      Some(clr_used) if clr_used > 0 => clr_used,
+      _ => 1 << dib_header.bitcount,
+    };
+    return entries_in_file.min(max_colors);
+  }
+  //the color table as rgba colors
+  fn get_palette(&self) -> Result<Vec<[u8; 4]>, ErrorKind> {
+    match self.get_color_table()? {
+      //stored as blue, green, red. there is no alpha, so it is 255 (opaque)
+      ColorTable::RGBTRIPLE(vec) => return Ok(vec.iter().map(|bgr| [bgr[2], bgr[1], bgr[0], 255]).collect()),
+      //stored as blue, green, red, reserved. the reserved byte is not alpha, so it is 255 (opaque)
+      ColorTable::RGBQUAD(vec) => return Ok(vec.iter().map(|bgr0| [bgr0[2], bgr0[1], bgr0[0], 255]).collect()),
+    }
+  }
+  //find the color table index to use for a color, following the palette policy
+  fn get_palette_index(&mut self, color: [u8; 4], dib_header: &DIBHEADER) -> Result<u8, ErrorKind> {
+    let palette: Vec<[u8; 3]> = self.get_palette()?.iter().map(|rgba| [rgba[0], rgba[1], rgba[2]]).collect();
+    let rgb: [u8; 3] = [color[0], color[1], color[2]];
+    if let Some(index) = palette.iter().position(|entry| *entry == rgb) {
+      return Ok(index as u8);
+    }
+    if self.palette_policy == PalettePolicy::Grow && palette.len() < (1 << dib_header.bitcount) {
+      //BITMAPCOREHEADER uses rgbtriple
+      let entry_size: u32 = if dib_header.size == 12 { 3 } else { 4 };
+      self.add_color_table_entry(rgb, palette.len() as u32, entry_size, dib_header)?;
+      return Ok(palette.len() as u8);
+    }
+    //closest color (smallest distance between the rgb values)
+    let distance = |entry: &[u8; 3]| -> u32 {
+      (0..3).map(|i| (i32::from(entry[i])-i32::from(rgb[i])).pow(2) as u32).sum()
+    };
+    let closest = palette.iter().enumerate().min_by_key(|(_, entry)| distance(entry));
+    match closest {
+      Some((index, _)) => return Ok(index as u8),
+      None => return Err(ErrorKind::DoesNotExist),
+    }
+  }
+  //add a color to the end of the color table
+  fn add_color_table_entry(&mut self, rgb: [u8; 3], entry_num: u32, entry_size: u32, dib_header: &DIBHEADER) -> Result<(), ErrorKind> {
+    let header = self.get_header();
+    let position: u32 = 14+dib_header.size+entry_num*entry_size;
+    let mut entry: Vec<u8> = vec![rgb[2], rgb[1], rgb[0], 0];
+    entry.truncate(entry_size as usize);
+    if position+entry_size <= header.bfOffBits {
+      //there is unused space before the pixel array
+      self.contents[position as usize..(position+entry_size) as usize].copy_from_slice(&entry);
+    } else {
+      //make space, moving the pixel array (and anything after it) forward
+      self.contents.splice(position as usize..position as usize, entry);
+      self.contents[2..6].copy_from_slice(&(header.bfSize+entry_size).to_le_bytes());
+      self.contents[10..14].copy_from_slice(&(header.bfOffBits+entry_size).to_le_bytes());
+      //embedded profile data is an offset from the start of the DIB header, so it moves too
+      if let Some(profile_data) = dib_header.ProfileData {
+        if profile_data > 0 {
+          let profile_data_offset: usize = HEADER_OFFSET+112;
+          let profile_data = BMP::bytes_to_int(self.contents[profile_data_offset..profile_data_offset+4].try_into().unwrap());
+          self.contents[profile_data_offset..profile_data_offset+4].copy_from_slice(&(profile_data+entry_size).to_le_bytes());
+        }
+      }
+    }
+    //BITMAPCOREHEADER (and cut off OS/2 headers) have no ClrUsed, the color table length comes from the space before the pixel array
+    if dib_header.ClrUsed.is_some() {
+      self.contents[HEADER_OFFSET+32..HEADER_OFFSET+36].copy_from_slice(&(entry_num+1).to_le_bytes());
+    }
+    return Ok(());
+  }
   //pixel array
   /// Get the pixel array.
   /// Returned as a `VecDeque<Vec<Vec<u8>>`.
   /// The `VecDeque` stores rows of pixels. The outer `Vec` contains one row of pixels, and the inner `Vec` represents the actual pixel color.
+  ///
+  /// RLE compressed (`BI_RLE8` and `BI_RLE4`) pixel arrays are decompressed, so each pixel is a single byte index into the color table.
+  /// The same goes for OS/2 `BI_HUFFMAN1D` pixel arrays, while OS/2 `BI_RLE24` pixel arrays are decompressed to 3 byte BGR pixels.
+  /// Embedded PNG (`BI_PNG`) and JPEG (`BI_JPEG`, needs the `jpeg` feature) pixel arrays are decoded, so each pixel is rgba.
+  ///
   pub fn get_pixel_data(&self) -> Result<VecDeque<Vec<Vec<u8>>>, ErrorKind> {
     //figure out if top down or bottom up
     //let it panic if it is an error
@@ -1059,10 +1998,24 @@ impl BMP {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
+    if let Some(compression) = &dib_header.compression {
+      if compression == "BI_RLE8" || compression == "BI_RLE4" || compression == "BI_RLE24" {
+        let width = dib_header.width as usize;
+        let pixel_size: usize = if compression == "BI_RLE24" { 3 } else { 1 };
+        let pixels = self.decode_rle(&dib_header)?;
+        return Ok(pixels.chunks_exact(width*pixel_size).map(|row| row.chunks_exact(pixel_size).map(|pixel| pixel.to_vec()).collect()).collect());
+      } else if compression == "BI_HUFFMAN1D" {
+        return self.decode_huffman_1d(&dib_header);
+      } else if compression == "BI_JPEG" || compression == "BI_PNG" {
+        //the pixel array is a whole jpeg or png file
+        let width = dib_header.width as usize;
+        let pixels = self.decode_embedded(&dib_header)?;
+        return Ok(pixels.chunks_exact(width*4).map(|row| row.chunks_exact(4).map(|rgba| rgba.to_vec()).collect()).collect());
+      }
+    }
     //figure out row size and image height
     //figure out pixel format
     //figure out is padded
-    //monochrome is 1 bit per pixel. lets not support that for now
     //Vec<[[u8; dib_header.bitcount/4]; dib_header.width]>
     //then change to array (must change to vector first since array size cannot be dynamic)
     let mut rows: VecDeque<Vec<Vec<u8>>> = VecDeque::new();
@@ -1072,9 +2025,9 @@ impl BMP {
       //add rows as normal, to the back of vector
       //header.bfOffBits
       //https://en.wikipedia.org/wiki/BMP_file_format#Pixel_storage
-      let row_length = f64::from(dib_header.bitcount*dib_header.width as u16/32).ceil() as u32 * 4;
-      //this may not work if there is profile data or other stuff after image?
-      let rows_num = (self.contents.len() as u32-header.bfOffBits)/row_length;
+      let row_length = (u32::from(dib_header.bitcount)*dib_header.width).div_ceil(32)*4;
+      //there may be profile data or other stuff after image, so there are never more rows than the height
+      let rows_num = ((self.contents.len() as u32-header.bfOffBits)/row_length).min(dib_header.height.unsigned_abs());
       for row_num in 0..rows_num {
         //let row: Vec<[u8; dib_header.bitcount/4]> = Vec::new();
         let mut row: Vec<Vec<u8>> = Vec::new();
@@ -1084,7 +2037,8 @@ impl BMP {
             row.push(self.contents[start as usize..(start+(dib_header.bitcount/8) as u32) as usize].to_vec());
           } else {
             //we need to do bitwise operators if the pixels are smaller than 1 byte size (1 bit, 2 bit, 4 bit)
-            let start: u32 = (header.bfOffBits)+(row_num)*row_length+(pixel)*(((dib_header.bitcount/8) as f64).ceil() as u32);
+            //several pixels share a byte, so the byte is the pixel's bit position divided by 8
+            let start: u32 = (header.bfOffBits)+row_num*row_length+pixel*u32::from(dib_header.bitcount)/8;
             let byte: u8 = self.contents[start as usize];
             if dib_header.bitcount == 1 {
               let split_bits: [u8; 8] = [byte >> 7, (byte & 0b01000000) >> 6, (byte & 0b00100000) >> 5, (byte & 0b00010000) >> 4, (byte & 0b00001000) >> 3, (byte & 0b00000100) >> 2, (byte & 0b00000010) >> 1, byte & 0b00000001];
@@ -1105,8 +2059,8 @@ impl BMP {
       //bottom up (starts from lower left)
       //add rows to front of vector
       //let start: u32 = header.bfOffBits+row_num*row_length+pixel*((dib_header.bitcount/8) as u32);
-      let row_length = f64::from(dib_header.bitcount*dib_header.width as u16/32).ceil() as u32 * 4;
-      let rows_num: u32 = (self.contents.len() as u32-header.bfOffBits)/row_length;
+      let row_length = (u32::from(dib_header.bitcount)*dib_header.width).div_ceil(32)*4;
+      let rows_num: u32 = ((self.contents.len() as u32-header.bfOffBits)/row_length).min(dib_header.height.unsigned_abs());
       for row_num in 0..rows_num {
         let mut row: Vec<Vec<u8>> = Vec::new();
         for pixel in 0..dib_header.width {
@@ -1115,7 +2069,8 @@ impl BMP {
             row.push(self.contents[start as usize..(start+(dib_header.bitcount/8) as u32) as usize].to_vec());
           } else {
             //we need to do bitwise operators if the pixels are smaller than 1 byte size (1 bit, 2 bit, 4 bit)
-            let start: u32 = (header.bfOffBits)+row_num*row_length+pixel*(((dib_header.bitcount/8) as f64).ceil() as u32);
+            //several pixels share a byte, so the byte is the pixel's bit position divided by 8
+            let start: u32 = (header.bfOffBits)+row_num*row_length+pixel*u32::from(dib_header.bitcount)/8;
             let byte: u8 = self.contents[start as usize];
             if dib_header.bitcount == 1 {
               let split_bits: [u8; 8] = [byte >> 7, (byte & 0b01000000) >> 6, (byte & 0b00100000) >> 5, (byte & 0b00010000) >> 4, (byte & 0b00001000) >> 3, (byte & 0b00000100) >> 2, (byte & 0b00000010) >> 1, byte & 0b00000001];
@@ -1134,34 +2089,233 @@ impl BMP {
     }
     return Ok(rows);
   }
-  //location here is told
-  //ICC color profile
-  fn get_color_profile(&self) -> Result<Vec<u8>, ErrorKind> {
-    //https://en.wikipedia.org/wiki/Color_management
-    //CIEXYZTRIPLE ?
-    //https://www.color.org/ICC_Minor_Revision_for_Web.pdf
-    //seems pretty complex, and niche. We'll check if it exists, if so, return raw bytes data, otherwise return error
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
+  //decode the png or jpeg file that is the pixel array of BI_PNG and BI_JPEG images, into top down rows of rgba
+  fn decode_embedded(&self, dib_header: &DIBHEADER) -> Result<Vec<u8>, ErrorKind> {
+    let header = self.get_header();
+    let start = header.bfOffBits as usize;
+    //sizeimage is the length of the png or jpeg file, but can be 0
+    let end = match dib_header.sizeimage {
+      Some(sizeimage) if sizeimage > 0 => (start+sizeimage as usize).min(self.contents.len()),
+      _ => self.contents.len(),
     };
-    match dib_header.size {
-      124 => {
-        let cstype = dib_header.CSType.unwrap();
-        if cstype == "PROFILE_EMBEDDED" || cstype == "PROFILE_LINKED" {
-          return Ok(self.contents[dib_header.ProfileData.unwrap() as usize..].to_vec());
-          //dib_header.ProfileData.unwrap()..dib_header.ProfileData.unwrap()+dib_header.ProfileSize.unwrap()
-        } else {
-          return Err(ErrorKind::DoesNotExist);
+    //the decoders check the embedded image is the size in the header before decoding it
+    let (width, height) = (dib_header.width, dib_header.height.unsigned_abs());
+    match dib_header.compression.as_deref() {
+      Some("BI_PNG") => return super::png::decode_png(&self.contents[start..end], width, height),
+      #[cfg(feature = "jpeg")]
+      Some("BI_JPEG") => return super::jpeg::decode_jpeg(&self.contents[start..end], width, height),
+      Some(compression) => return Err(ErrorKind::UnsupportedCompression(compression.to_string())),
+      None => return Err(ErrorKind::UnsupportedCompression("BI_RGB".to_string())),
+    }
+  }
+  //decompress OS/2 BI_HUFFMAN1D pixel arrays into color table indexes
+  fn decode_huffman_1d(&self, dib_header: &DIBHEADER) -> Result<VecDeque<Vec<Vec<u8>>>, ErrorKind> {
+    if dib_header.bitcount != 1 {
+      return Err(ErrorKind::UnsupportedBitDepth(dib_header.bitcount));
+    }
+    let start = self.get_offset() as usize;
+    let mut end = self.contents.len();
+    if let Some(sizeimage) = dib_header.sizeimage {
+      if sizeimage > 0 && start+(sizeimage as usize) < end {
+        end = start+sizeimage as usize;
+      }
+    }
+    //white runs are index 0 and black runs are index 1
+    let lines = super::ccitt::decode_huffman_1d(&self.contents[start..end], dib_header.width as usize, dib_header.height.unsigned_abs() as usize)?;
+    let mut rows: VecDeque<Vec<Vec<u8>>> = VecDeque::new();
+    for line in lines {
+      let row: Vec<Vec<u8>> = line.iter().map(|index| vec![*index]).collect();
+      //bottom up images store the bottom row first
+      if dib_header.height > 0 {
+        rows.push_front(row);
+      } else {
+        rows.push_back(row);
+      }
+    }
+    return Ok(rows);
+  }
+  //decompress BI_RLE8 and BI_RLE4 pixel arrays into color table indexes, and OS/2 BI_RLE24 pixel arrays into BGR pixels, as top down rows
+  //https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-compression
+  fn decode_rle(&self, dib_header: &DIBHEADER) -> Result<Vec<u8>, ErrorKind> {
+    let is_rle8: bool = dib_header.compression.as_deref() == Some("BI_RLE8");
+    let is_rle24: bool = dib_header.compression.as_deref() == Some("BI_RLE24");
+    let expected_bitcount: u16 = if is_rle8 { 8 } else if is_rle24 { 24 } else { 4 };
+    if dib_header.bitcount != expected_bitcount {
+      return Err(ErrorKind::UnsupportedBitDepth(dib_header.bitcount));
+    }
+    let width = dib_header.width as usize;
+    let height = dib_header.height.unsigned_abs() as usize;
+    //pixels skipped by deltas or never written (end of line/bitmap reached early) are left as index 0 (or black for RLE24)
+    let pixel_size: usize = if is_rle24 { 3 } else { 1 };
+    let mut pixels: Vec<u8> = Vec::new();
+    if pixels.try_reserve_exact(width*height*pixel_size).is_err() {
+      return Err(ErrorKind::CorruptPixelData(format!("not enough memory to decode a {}x{} image", width, height)));
+    }
+    pixels.resize(width*height*pixel_size, 0);
+    let start = self.get_offset() as usize;
+    let mut end = self.contents.len();
+    if let Some(sizeimage) = dib_header.sizeimage {
+      if sizeimage > 0 && start+(sizeimage as usize) < end {
+        end = start+sizeimage as usize;
+      }
+    }
+    //RLE bitmaps are bottom up, so line 0 is the bottom row
+    let mut set_pixel = |x: usize, line: usize, pixel: &[u8]| {
+      if x < width && line < height {
+        let row_num = if dib_header.height > 0 { height-line-1 } else { line };
+        let offset = (row_num*width+x)*pixel_size;
+        pixels[offset..offset+pixel_size].copy_from_slice(pixel);
+      }
+    };
+    let mut x: usize = 0;
+    let mut line: usize = 0;
+    let mut i: usize = start;
+    while i+1 < end {
+      let count = self.contents[i];
+      let value = self.contents[i+1];
+      i += 2;
+      if count > 0 && is_rle24 {
+        //encoded mode, repeat the BGR color (the value and next two bytes) count times
+        if i+1 >= end {
+          break;
         }
+        let bgr: [u8; 3] = [value, self.contents[i], self.contents[i+1]];
+        i += 2;
+        for _ in 0..count {
+          set_pixel(x, line, &bgr);
+          x += 1;
+        }
+        continue;
+      } else if count > 0 {
+        //encoded mode, repeat the index (or for RLE4, the two alternating indexes) count times
+        for n in 0..count {
+          let index = if is_rle8 {
+            value
+          } else if n % 2 == 0 {
+            value >> 4
+          } else {
+            value & 0b00001111
+          };
+          set_pixel(x, line, &[index]);
+          x += 1;
+        }
+        continue;
+      }
+      match value {
+        0 => {
+          //end of line
+          x = 0;
+          line += 1;
+        },
+        1 => {
+          //end of bitmap
+          break;
+        },
+        2 => {
+          //delta, the next two bytes are the horizontal and vertical offsets
+          if i+1 >= end {
+            break;
+          }
+          x += self.contents[i] as usize;
+          line += self.contents[i+1] as usize;
+          i += 2;
+        },
+        _ => {
+          //absolute mode, `value` is the number of literal indexes (or BGR colors) that follow, padded to a 2 byte boundary
+          let byte_num: usize = if is_rle8 { value as usize } else if is_rle24 { value as usize*3 } else { (value as usize).div_ceil(2) };
+          if i+byte_num > end {
+            break;
+          }
+          for n in 0..value as usize {
+            if is_rle24 {
+              set_pixel(x, line, &self.contents[i+n*3..i+n*3+3]);
+              x += 1;
+              continue;
+            }
+            let index = if is_rle8 {
+              self.contents[i+n]
+            } else if n % 2 == 0 {
+              self.contents[i+n/2] >> 4
+            } else {
+              self.contents[i+n/2] & 0b00001111
+            };
+            set_pixel(x, line, &[index]);
+            x += 1;
+          }
+          i += byte_num+byte_num % 2;
+        },
+      }
+    }
+    return Ok(pixels);
+  }
+  //ICC color profile
+  //https://learn.microsoft.com/en-us/windows/win32/wcs/using-structures-in-wcs-1-0
+  /// Get the embedded or linked ICC color profile. Returns `ErrorKind::DoesNotExist` if there is no profile.
+  /// 
+  /// `ProfileData` is the offset of the profile from the start of the DIB header, and `ProfileSize` is its length in bytes.
+  /// For linked profiles, the profile data is the (null terminated) file path of the profile.
+  /// 
+  pub fn get_color_profile(&self) -> Result<ColorProfile, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    if dib_header.size != 124 {
+      return Err(ErrorKind::DoesNotExist);
+    }
+    let cstype = dib_header.CSType;
+    if cstype != Some(ColorSpaceType::ProfileEmbedded) && cstype != Some(ColorSpaceType::ProfileLinked) {
+      return Err(ErrorKind::DoesNotExist);
+    }
+    let start: usize = HEADER_OFFSET+dib_header.ProfileData.unwrap_or(0) as usize;
+    let end: usize = start+dib_header.ProfileSize.unwrap_or(0) as usize;
+    if dib_header.ProfileData.unwrap_or(0) == 0 || end > self.contents.len() {
+      return Err(ErrorKind::CorruptHeader { field: "ProfileData".to_string(), offset: HEADER_OFFSET+112, reason: format!("profile at {}..{} is outside of the file", start, end) });
+    }
+    let profile = &self.contents[start..end];
+    if cstype == Some(ColorSpaceType::ProfileEmbedded) {
+      return Ok(ColorProfile::Embedded(profile.to_vec()));
+    }
+    //the path is in the windows-1252 code page, which is close enough to latin-1
+    return Ok(ColorProfile::Linked(profile.iter().take_while(|byte| **byte != 0).map(|byte| *byte as char).collect()));
+  }
+  /// Attach an ICC color profile, which is added to the end of the file. Only BITMAPV5HEADER (124 byte DIB header) images can have one.
+  /// 
+  /// Updates `CSType`, `ProfileData`, `ProfileSize` and the file size. If the old profile was at the end of the file, it is replaced.
+  /// 
+  pub fn set_color_profile(&mut self, profile: ColorProfile) -> Result<(), ErrorKind> {
+    let mut dib_header = self.get_dib_header()?;
+    if dib_header.size != 124 {
+      return Err(ErrorKind::InvalidParameter("color profiles need a BITMAPV5HEADER".to_string()));
+    }
+    let (cstype, mut profile_bytes): (ColorSpaceType, Vec<u8>) = match profile {
+      ColorProfile::Embedded(bytes) => (ColorSpaceType::ProfileEmbedded, bytes),
+      ColorProfile::Linked(path) => {
+        if path.chars().any(|c| c == '\0' || c as u32 > 255) {
+          return Err(ErrorKind::InvalidParameter("linked profile paths must be latin-1 with no null characters".to_string()));
+        }
+        (ColorSpaceType::ProfileLinked, path.chars().map(|c| c as u8).collect())
       },
-      _ => return Err(ErrorKind::DoesNotExist),
+    };
+    if cstype == ColorSpaceType::ProfileLinked {
+      profile_bytes.push(0);
+    }
+    //remove the old profile, if nothing comes after it
+    let old_cstype = dib_header.CSType;
+    if old_cstype == Some(ColorSpaceType::ProfileEmbedded) || old_cstype == Some(ColorSpaceType::ProfileLinked) {
+      let old_start: usize = HEADER_OFFSET+dib_header.ProfileData.unwrap_or(0) as usize;
+      let old_end: usize = old_start+dib_header.ProfileSize.unwrap_or(0) as usize;
+      let header = self.get_header();
+      if old_end == self.contents.len() && old_start >= header.bfOffBits as usize {
+        self.contents.truncate(old_start);
+      }
     }
-    //Intent: String,
-    //ProfileData: u16,
-    //ProfileSize: u16,
-    //CSType: String
+    let start = self.contents.len();
+    self.contents.extend(&profile_bytes);
+    dib_header.CSType = Some(cstype);
+    dib_header.ProfileData = Some((start-HEADER_OFFSET) as u32);
+    dib_header.ProfileSize = Some(profile_bytes.len() as u32);
+    self.set_dib_header(dib_header)?;
+    let file_size = self.contents.len() as u32;
+    self.contents[2..6].copy_from_slice(&file_size.to_le_bytes());
+    return Ok(());
   }
   //interpret color data
   //returns an array rgba (4 u8)
@@ -1172,7 +2326,8 @@ impl BMP {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
-    if x >= (dib_header.width as usize) || y >= (dib_header.height as usize) {
+    //negative height is a top down image
+    if x >= (dib_header.width as usize) || y >= (dib_header.height.unsigned_abs() as usize) {
       return Err(ErrorKind::OutOfBounds);
     }
     //need to check if error
@@ -1181,220 +2336,56 @@ impl BMP {
       Ok(returned_pixel_data) => returned_pixel_data,
       Err(e) => return Err(e),
     };
-    let pixel: &Vec<u8> = &pixel_data[y][x];
-    let pixel: Vec<u8> = pixel.to_vec();
-    //TODO: incorporate masks
-    //if more than 12 bytes dib header, there are masks
-    //RedMask, GreenMask, BlueMask, AlphaMask
-    //if BI_BITFIELDS and 16 or 24 bits
-    //also for smaller dib header (info), check to see if there are extra bit masks
-    if dib_header.bitcount == 16 {
-      let compression = dib_header.compression.unwrap();
-      if compression == "BI_BITFIELDS" && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        let rgba: [u8; 4];
-        //these should be from extra bit masks!
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        //let green_mask: u32 = dib_header.GreenMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        if red_mask < blue_mask {
-          //assume rgb
-          rgba = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), 255];
-        } else {
-          //assume brg
-          rgba = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), 255];
-        }
-        return Ok(rgba);
-      } else {
-        //compression is "BI_RGB"
-        //5 for each r,g,b (15 bits + 1 per pixel)
-        //currently placeholder
-        return Ok([0, 0, 0, 255]);
-      }
-    } else if dib_header.bitcount == 24 {
-      //if 24 bit, no need to look at color table because it is rgb.
-      //there is no alpha value, so it is 100 (nontransparent/opaque)
-      //order is BGR not RGB
-      let rgba: [u8; 4] = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), 255];
-      return Ok(rgba);
-    } else if dib_header.bitcount == 32 {
-      //32 means rgba
-      let compression = dib_header.compression.unwrap();
-      if (compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS") && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        //placeholder
-        //determine if alpha is in front or back. determine is rgb or brg
-        let rgba: [u8; 4];
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        //let green_mask: u32 = dib_header.GreenMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-        if alpha_mask < red_mask {
-          //alpha is in front
-          if red_mask < blue_mask {
-            //argb
-            rgba = [BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3]), BMP::byte_to_int(pixel[0])];
-          } else {
-            //abgr
-            rgba = [BMP::byte_to_int(pixel[3]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0])];
-          }
-        } else {
-          //alpha is in back
-          if red_mask < blue_mask {
-            //rgba
-            rgba = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3])];
-          } else {
-            //bgra
-            rgba = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[3])];
-          }
-        }
-        return Ok(rgba);
-      } else {
-        let rgba: [u8; 4] = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3])];
-        return Ok(rgba);
-      }
-    } else {
-      //otherwise look at color table for corresponding color. The bit (s) in the pixel data are indexes. We look up the index in the color table to find the color
-      let color_table = self.get_color_table();
-      let color_table = match color_table {
-        Ok(returned_color_table) => returned_color_table,
-        Err(e) => return Err(e),
-      };
-      //1, 2, 4 (half byte), 8 (1 bytes), 16 (2 bytes)
-      let index;
-      if dib_header.bitcount == 16 {
-        index = BMP::two_bytes_to_int(BMP::vec_to_2u8_array(pixel));
-      } else {
-        index = u16::from(BMP::byte_to_int(pixel[0]));
-      }
-      let rgba: [u8; 4];
-      match color_table {
-        ColorTable::RGBTRIPLE(vec) => {
-          let rgb: [u8; 3] = vec[index as usize];
-          //the array is fixed size [u8; 3] we want to turn it into [u8; 4] with the 4th being 255
-          let mut rgb = rgb.to_vec();
-          rgb.push(255);
-          rgba = BMP::vec_to_4u8_array(rgb);
-        },
-        ColorTable::RGBQUAD(vec) => {
-          rgba = vec[index as usize];
-        }
-      }
-      return Ok(rgba);
-    }
+    return self.get_color_of_pixel_efficient(x, y, &dib_header, &pixel_data);
   }
   pub fn get_color_of_px(&self, x: usize, y: usize) -> Result<[u8; 4], ErrorKind> {
     self.get_color_of_pixel(x, y)
   }
   /// More efficient version of `get_color_of_pixel` that accepts the DIB header and pixel array as references to prevent unnecessary and slow copying of data.
   pub fn get_color_of_pixel_efficient(&self, x: usize, y: usize, dib_header: &DIBHEADER, pixel_data: &VecDeque<Vec<Vec<u8>>>) -> Result<[u8; 4], ErrorKind> {
-    if x >= (dib_header.width as usize) || y >= (dib_header.height as usize) {
+    //negative height is a top down image
+    if x >= (dib_header.width as usize) || y >= (dib_header.height.unsigned_abs() as usize) {
       return Err(ErrorKind::OutOfBounds);
     }
     let pixel: &Vec<u8> = &pixel_data[y][x];
-    let pixel: Vec<u8> = pixel.to_vec();
-    //TODO: incorporate masks
-    //if more than 12 bytes dib header, there are masks
-    //RedMask, GreenMask, BlueMask, AlphaMask
-    //if BI_BITFIELDS and 16 or 24 bits
-    //also for smaller dib header (info), check to see if there are extra bit masks
-    if dib_header.bitcount == 16 {
-      let compression = dib_header.compression.as_ref().unwrap();
-      if compression == "BI_BITFIELDS" && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        let rgba: [u8; 4];
-        //these should be from extra bit masks!
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        //let green_mask: u32 = dib_header.GreenMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        if red_mask < blue_mask {
-          //assume rgb
-          rgba = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), 255];
-        } else {
-          //assume brg
-          rgba = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), 255];
-        }
-        return Ok(rgba);
+    //embedded png and jpeg pixels are already decoded to rgba
+    if dib_header.compression.as_deref() == Some("BI_PNG") || dib_header.compression.as_deref() == Some("BI_JPEG") {
+      return Ok([pixel[0], pixel[1], pixel[2], pixel[3]]);
+    }
+    //16 and 32 bit pixels need the masks, 1, 2, 4 and 8 bit pixels are indexes into the color table
+    let masks: Option<BI_ALPHABITFIELDS_MASKS> = if dib_header.bitcount == 16 || dib_header.bitcount == 32 {
+      Some(self.get_bit_masks_efficient(dib_header)?)
+    } else {
+      None
+    };
+    let palette: Vec<[u8; 4]> = if dib_header.bitcount <= 8 {
+      self.get_palette()?
+    } else {
+      Vec::new()
+    };
+    return BMP::decode_pixel(pixel, dib_header.bitcount, masks.as_ref(), &palette);
+  }
+  //turn the bytes of a pixel (or the color table index, for 1, 2, 4 and 8 bit) into rgba
+  fn decode_pixel(pixel: &[u8], bitcount: u16, masks: Option<&BI_ALPHABITFIELDS_MASKS>, palette: &[[u8; 4]]) -> Result<[u8; 4], ErrorKind> {
+    if let Some(masks) = masks {
+      //the masks say where each channel is in the pixel, and how many bits it has
+      let value: u32 = if bitcount == 16 {
+        u32::from(BMP::two_bytes_to_int([pixel[0], pixel[1]]))
       } else {
-        //compression is "BI_RGB"
-        //5 for each r,g,b (15 bits + 1 per pixel)
-        //currently placeholder
-        return Ok([0, 0, 0, 255]);
-      }
-    } else if dib_header.bitcount == 24 {
+        BMP::bytes_to_int([pixel[0], pixel[1], pixel[2], pixel[3]])
+      };
+      return Ok(masks.decode(value));
+    } else if bitcount == 24 {
       //if 24 bit, no need to look at color table because it is rgb.
       //there is no alpha value, so it is 100 (nontransparent/opaque)
       //order is BGR not RGB
-      let rgba: [u8; 4] = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), 255];
-      return Ok(rgba);
-    } else if dib_header.bitcount == 32 {
-      //32 means rgba
-      let compression = dib_header.compression.as_ref().unwrap();
-      if (compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS") && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        //placeholder
-        //determine if alpha is in front or back. determine is rgb or brg
-        let rgba: [u8; 4];
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        //let green_mask: u32 = dib_header.GreenMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-        if alpha_mask < red_mask {
-          //alpha is in front
-          if red_mask < blue_mask {
-            //argb
-            rgba = [BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3]), BMP::byte_to_int(pixel[0])];
-          } else {
-            //abgr
-            rgba = [BMP::byte_to_int(pixel[3]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0])];
-          }
-        } else {
-          //alpha is in back
-          if red_mask < blue_mask {
-            //rgba
-            rgba = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3])];
-          } else {
-            //bgra
-            rgba = [BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[3])];
-          }
-        }
-        return Ok(rgba);
-      } else {
-        let rgba: [u8; 4] = [BMP::byte_to_int(pixel[0]), BMP::byte_to_int(pixel[1]), BMP::byte_to_int(pixel[2]), BMP::byte_to_int(pixel[3])];
-        return Ok(rgba);
-      }
+      return Ok([pixel[2], pixel[1], pixel[0], 255]);
     } else {
       //otherwise look at color table for corresponding color. The bit (s) in the pixel data are indexes. We look up the index in the color table to find the color
-      let color_table = self.get_color_table();
-      let color_table = match color_table {
-        Ok(returned_color_table) => returned_color_table,
-        Err(e) => return Err(e),
-      };
-      //1, 2, 4 (half byte), 8 (1 bytes), 16 (2 bytes)
-      let index;
-      if dib_header.bitcount == 16 {
-        index = BMP::two_bytes_to_int(BMP::vec_to_2u8_array(pixel));
-      } else {
-        index = u16::from(BMP::byte_to_int(pixel[0]));
-      }
-      let rgba: [u8; 4];
-      match color_table {
-        ColorTable::RGBTRIPLE(vec) => {
-          let rgb: [u8; 3] = vec[index as usize];
-          //the array is fixed size [u8; 3] we want to turn it into [u8; 4] with the 4th being 255
-          let mut rgb = rgb.to_vec();
-          rgb.push(255);
-          rgba = BMP::vec_to_4u8_array(rgb);
-        },
-        ColorTable::RGBQUAD(vec) => {
-          rgba = vec[index as usize];
-        }
+      match palette.get(pixel[0] as usize) {
+        Some(rgba) => return Ok(*rgba),
+        None => return Err(ErrorKind::DoesNotExist),
       }
-      return Ok(rgba);
     }
   }
   pub fn get_color_of_px_efficient(&self, x: usize, y: usize, dib_header: &DIBHEADER, pixel_data: &VecDeque<Vec<Vec<u8>>>) -> Result<[u8; 4], ErrorKind> {
@@ -1402,308 +2393,1440 @@ impl BMP {
   }
   //rgba, bgra, etc
   /// See what color format the pixels are stored in.
-  /// Will return one of the following: rgb, bgr, rgba, bgra, argb, abgr, color table.
+  /// Will return one of the following: rgb, brg, bgr, rgba, bgra, argb, abgr, color table, png, jpeg.
+  ///
+  /// For 16 and 32 bit images with bit masks, this is worked out from the masks, uncompressed 16 and 32 bit images are always rgb and rgba.
+  /// Use `get_bit_masks()` to see exactly where each channel is stored.
+  ///
   pub fn get_format(&self) -> String {
     let dib_header = self.get_dib_header().unwrap();
-    if dib_header.bitcount == 16 {
-      let compression = dib_header.compression.unwrap();
-      if compression == "BI_BITFIELDS" && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        //check masks
-        //due to complexity we dont actually use the masks, we convert them into integer, and then compare size. Bigger it is, the more the one is to the left
-        //these should be from extra bit masks!
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        //let green_mask: u32 = dib_header.GreenMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        if red_mask < blue_mask {
-          //assume rgb
-          return "rgb".to_string();
-        } else {
-          //assume brg
-          return "brg".to_string();
-        }
+    let compression: &str = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    if compression == "BI_PNG" {
+      return "png".to_string();
+    } else if compression == "BI_JPEG" {
+      return "jpeg".to_string();
+    } else if dib_header.bitcount == 16 || dib_header.bitcount == 32 {
+      let masks = match self.get_bit_masks_efficient(&dib_header) {
+        Ok(masks) if compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS" => masks,
+        _ if dib_header.bitcount == 16 => return "rgb".to_string(),
+        _ => return "rgba".to_string(),
+      };
+      //compare the masks as integers, the bigger one is further to the left
+      if dib_header.bitcount == 16 {
+        return if masks.red < masks.blue { "rgb" } else { "brg" }.to_string();
+      } else if masks.alpha < masks.red {
+        //alpha is in front
+        return if masks.red < masks.blue { "argb" } else { "abgr" }.to_string();
       } else {
-        //compression is "BI_RGB"
-        //5 for each r,g,b (15 bits + 1 per pixel)
-        //currently placeholder
-        return "rgb".to_string();
+        //alpha is in back
+        return if masks.red < masks.blue { "rgba" } else { "bgra" }.to_string();
       }
     } else if dib_header.bitcount == 24 {
       return "bgr".to_string();
-    } else if dib_header.bitcount == 32 {
-      //32 means rgba
-      let compression = dib_header.compression.unwrap();
-      if (compression == "BI_BITFIELDS" || compression == "BI_ALPHABITFIELDS") && (dib_header.RedMask.is_some() && dib_header.GreenMask.is_some() && dib_header.BlueMask.is_some()) {
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        //let green_mask: u32 = dib_header.GreenMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-        if alpha_mask < red_mask {
-          //alpha is in front
-          if red_mask < blue_mask {
-            //argb
-            return "argb".to_string();
-          } else {
-            //abgr
-            return "abgr".to_string();
-          }
-        } else {
-          //alpha is in back
-          if red_mask < blue_mask {
-            //rgba
-            return "rgba".to_string();
-          } else {
-            //bgra
-            return "bgra".to_string();
-          }
-        }
-      } else {
-        return "rgba".to_string();
-      }
     } else {
       return "color table".to_string();
     }
   }
-  //edit color pixels, only supports 24 and 32 bit
+  //edit color pixels
   /// Given x and y coordinates (`(0, 0)` is the upper left corner of the image), change the RGB/RGBA color at that location.
   /// 
-  /// Note: Currently only supports files where pixel color is stored as 24 or 32 bits, which should be most BMP files.
+  /// For images with a color table (1, 2, 4 and 8 bit), the color is written as an index into the color table, see `PalettePolicy`.
+  /// Compressed images cannot be changed.
   /// 
-  pub fn change_color_of_pixel(&mut self, x: u16, mut y: u16, new_color: [u8; 4]) -> Result<(), ErrorKind> {
-    //NEW_COLOR IS FLIPPED! See get color from pixel and get the correct order like in get_color_of_px
-    //todo: top down or bottom down?
+  pub fn change_color_of_pixel(&mut self, x: u16, y: u16, new_color: [u8; 4]) -> Result<(), ErrorKind> {
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
-    if u32::from(x) >= dib_header.width || i32::from(y) >= dib_header.height {
+    let header = self.get_header();
+    return self.change_color_of_pixel_efficient(x, y, new_color, &dib_header, &header);
+  }
+  /// More efficient version of `change_color_of_pixel` that accepts the DIB header and file header as references to prevent unnecessary and slow copying of data.
+  pub fn change_color_of_pixel_efficient(&mut self, x: u16, y: u16, new_color: [u8; 4], dib_header: &DIBHEADER, header: &BITMAPFILEHEADER) -> Result<(), ErrorKind> {
+    //negative height is a top down image
+    if u32::from(x) >= dib_header.width || u32::from(y) >= dib_header.height.unsigned_abs() {
       return Err(ErrorKind::OutOfBounds);
     }
-    let header = self.get_header();
+    //compressed pixel arrays would need to be encoded again
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    if compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS" {
+      return Err(ErrorKind::UnsupportedCompression(compression.to_string()));
+    }
     //bits per pixel
     let bitcount = dib_header.bitcount;
-    //only 24 and 32 bit
-    if bitcount != 24 && bitcount != 32 {
+    if bitcount == 1 || bitcount == 2 || bitcount == 4 || bitcount == 8 {
+      return self.change_index_of_pixel(x, y, new_color, dib_header);
+    }
+    //only 16, 24 and 32 bit
+    if bitcount != 16 && bitcount != 24 && bitcount != 32 {
       //return error
-      return Err(ErrorKind::Unsupported);
+      return Err(ErrorKind::UnsupportedBitDepth(bitcount));
     }
+    //16 and 32 bit pixels are packed using the bit masks
+    let masks: Option<BI_ALPHABITFIELDS_MASKS> = if bitcount == 24 {
+      None
+    } else {
+      match self.get_bit_masks_efficient(dib_header) {
+        Ok(returned_masks) => Some(returned_masks),
+        Err(_) => return Err(ErrorKind::Unsupported),
+      }
+    };
     //depending on if top down or bottom up, adjust  y
-    if dib_header.height > 0 {
+    let y: u32 = if dib_header.height > 0 {
       //bottom up
-      y = dib_header.height as u16 - y - 1;
-    }
-    //calculate row width (bytes)
-    let row_length = (f64::from((bitcount/8) as u16*dib_header.width as u16/4).ceil() as u32 * 4) as u16;
+      dib_header.height as u32-u32::from(y)-1
+    } else {
+      u32::from(y)
+    };
+    //calculate row width (bytes), rows are padded to a multiple of 4 bytes
+    let row_length: u32 = (u32::from(bitcount)*dib_header.width).div_ceil(32)*4;
     //amount of rows in front = y
     //add offset bits: header.bfOffBits (actually bytes)
-    let start = u32::from(y)*u32::from(row_length)+header.bfOffBits+(bitcount/8) as u32*u32::from(x);
-    //get indexes to change
-    //self.contents
+    let start = (y*row_length+header.bfOffBits+u32::from(bitcount/8)*u32::from(x)) as usize;
     //change the contents
-    if bitcount == 24 {
+    if let Some(masks) = masks {
+      //little endian, so only the first 2 bytes are used for 16 bit
+      let value_bytes: [u8; 4] = masks.encode(new_color).to_le_bytes();
+      let byte_num = (bitcount/8) as usize;
+      self.contents[start..start+byte_num].copy_from_slice(&value_bytes[..byte_num]);
+    } else {
       //order is BGR not RGB
       //3 bytes
-      self.contents[start as usize] = new_color[2];
-      self.contents[(start+1) as usize] = new_color[1];
-      self.contents[(start+2) as usize] = new_color[0];
-    } else if bitcount == 32 {
-      let red_mask: u32 = dib_header.RedMask.unwrap();
-      //let green_mask: u32 = dib_header.RedMask.unwrap();
-      let blue_mask: u32 = dib_header.BlueMask.unwrap();
-      let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-      //4 bytes
-      if alpha_mask < red_mask {
-        //alpha in front
-        if red_mask < blue_mask {
-          //argb
-          self.contents[start as usize] = new_color[3];
-          self.contents[(start+1) as usize] = new_color[0];
-          self.contents[(start+2) as usize] = new_color[1];
-          self.contents[(start+3) as usize] = new_color[2];
-        } else {
-          //abgr
-          self.contents[start as usize] = new_color[3];
-          self.contents[(start+1) as usize] = new_color[2];
-          self.contents[(start+2) as usize] = new_color[1];
-          self.contents[(start+3) as usize] = new_color[0];
-        }
-      } else {
-        //alpha in back
-        if red_mask < blue_mask {
-          //rgba
-          self.contents[start as usize] = new_color[0];
-          self.contents[(start+1) as usize] = new_color[1];
-          self.contents[(start+2) as usize] = new_color[2];
-          self.contents[(start+3) as usize] = new_color[3];
-        } else {
-          //bgra
-          self.contents[start as usize] = new_color[2];
-          self.contents[(start+1) as usize] = new_color[1];
-          self.contents[(start+2) as usize] = new_color[0];
-          self.contents[(start+3) as usize] = new_color[3];
-        }
-      }
+      self.contents[start] = new_color[2];
+      self.contents[start+1] = new_color[1];
+      self.contents[start+2] = new_color[0];
     }
     return Ok(());
   }
-  /// More efficient version of `change_color_of_pixel` that accepts the DIB header and file header as references to prevent unnecessary and slow copying of data.
-  pub fn change_color_of_pixel_efficient(&mut self, x: u16, mut y: u16, new_color: [u8; 4], dib_header: &DIBHEADER, header: &BITMAPFILEHEADER) -> Result<(), ErrorKind> {
-    if u32::from(x) >= dib_header.width || i32::from(y) >= dib_header.height {
-      return Err(ErrorKind::OutOfBounds);
-    }
-    //bits per pixel
-    let bitcount = dib_header.bitcount;
-    //only 24 and 32 bit
-    if bitcount != 24 && bitcount != 32 {
-      //return error
-      return Err(ErrorKind::Unsupported);
+  //write the color table index for a color into 1, 2, 4 or 8 bit pixels
+  fn change_index_of_pixel(&mut self, x: u16, y: u16, new_color: [u8; 4], dib_header: &DIBHEADER) -> Result<(), ErrorKind> {
+    //compressed pixel arrays cannot be changed in place
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    if compression != "BI_RGB" {
+      return Err(ErrorKind::UnsupportedCompression(compression.to_string()));
     }
-    //depending on if top down or bottom up, adjust  y
-    if dib_header.height > 0 {
+    let index: u8 = self.get_palette_index(new_color, dib_header)?;
+    return self.change_index_of_pixel_efficient(x, y, index, dib_header);
+  }
+  fn change_index_of_pixel_efficient(&mut self, x: u16, y: u16, index: u8, dib_header: &DIBHEADER) -> Result<(), ErrorKind> {
+    //the color table may have grown, which moves the pixel array, so get a fresh offset
+    let offset: u32 = self.get_offset();
+    let y: u32 = if dib_header.height > 0 {
       //bottom up
-      y = dib_header.height as u16 - y - 1;
-    }
-    //calculate row width (bytes)
-    let row_length = (f64::from((bitcount/8) as u16*dib_header.width as u16/4).ceil() as u32 * 4) as u16;
-    //amount of rows in front = y
-    //add offset bits: header.bfOffBits (actually bytes)
-    let start = u32::from(y)*u32::from(row_length)+header.bfOffBits+(bitcount/8) as u32*x as u32;
-    //get indexes to change
-    //self.contents
-    //change the contents
-    if bitcount == 24 {
-      //order is BGR not RGB
-      //3 bytes
-      self.contents[start as usize] = new_color[2];
-      self.contents[(start+1) as usize] = new_color[1];
-      self.contents[(start+2) as usize] = new_color[0];
-    } else if bitcount == 32 {
-      let red_mask: u32 = dib_header.RedMask.unwrap();
-      //let green_mask: u32 = dib_header.RedMask.unwrap();
-      let blue_mask: u32 = dib_header.BlueMask.unwrap();
-      let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-      //4 bytes
-      if alpha_mask < red_mask {
-        //alpha in front
-        if red_mask < blue_mask {
-          //argb
-          self.contents[start as usize] = new_color[3];
-          self.contents[(start+1) as usize] = new_color[0];
-          self.contents[(start+2) as usize] = new_color[1];
-          self.contents[(start+3) as usize] = new_color[2];
-        } else {
-          //abgr
-          self.contents[start as usize] = new_color[3];
-          self.contents[(start+1) as usize] = new_color[2];
-          self.contents[(start+2) as usize] = new_color[1];
-          self.contents[(start+3) as usize] = new_color[0];
-        }
-      } else {
-        //alpha in back
-        if red_mask < blue_mask {
-          //rgba
-          self.contents[start as usize] = new_color[0];
-          self.contents[(start+1) as usize] = new_color[1];
-          self.contents[(start+2) as usize] = new_color[2];
-          self.contents[(start+3) as usize] = new_color[3];
-        } else {
-          //bgra
-          self.contents[start as usize] = new_color[2];
-          self.contents[(start+1) as usize] = new_color[1];
-          self.contents[(start+2) as usize] = new_color[0];
-          self.contents[(start+3) as usize] = new_color[3];
-        }
-      }
-    }
+      dib_header.height as u32-u32::from(y)-1
+    } else {
+      u32::from(y)
+    };
+    let bitcount = u32::from(dib_header.bitcount);
+    let row_length: u32 = (bitcount*dib_header.width).div_ceil(32)*4;
+    //pixels smaller than a byte are packed starting from the highest bits
+    let bit_position: u32 = u32::from(x)*bitcount;
+    let byte_index = (offset+y*row_length+bit_position/8) as usize;
+    let shift: u32 = 8-bitcount-bit_position % 8;
+    let mask: u8 = (((1u16 << bitcount)-1) << shift) as u8;
+    self.contents[byte_index] = (self.contents[byte_index] & !mask) | ((index << shift) & mask);
     return Ok(());
   }
   /// Batch change color of pixels. Pass in a vector of coordinates, and a new color.
   /// Useful if wanting to changing many pixels to the same color.
   pub fn change_color_of_pixels(&mut self, pixels: Vec<[u16; 2]>, new_color: [u8; 4]) -> Result<(), ErrorKind> {
-    //same as change_color_of_pixel but more efficient
-    //see comments in that function for explanations
+    //same as change_color_of_pixel but more efficient, since headers are only read once
     let dib_header = self.get_dib_header();
     let dib_header = match dib_header {
       Ok(returned_dib_header) => returned_dib_header,
       Err(e) => return Err(e),
     };
     let header = self.get_header();
-    let bitcount = dib_header.bitcount;
-    if bitcount != 24 && bitcount != 32 {
-      return Err(ErrorKind::Unsupported);
-    }
-    let row_length = (f64::from((bitcount/8) as u16*dib_header.width as u16/4).ceil() as u32 * 4) as u16;
     for pixel in pixels {
-      let x = pixel[0];
-      let mut y = pixel[1];
-      if dib_header.height > 0 {
-        y = dib_header.height as u16 - y - 1;
-      }
-      let start = y*row_length+header.bfOffBits as u16+(bitcount/8)*x;
-      if bitcount == 24 {
-        self.contents[start as usize] = new_color[2];
-        self.contents[(start+1) as usize] = new_color[1];
-        self.contents[(start+2) as usize] = new_color[0];
-      } else if bitcount == 32 {
-        let red_mask: u32 = dib_header.RedMask.unwrap();
-        let blue_mask: u32 = dib_header.BlueMask.unwrap();
-        let alpha_mask: u32 = dib_header.AlphaMask.unwrap();
-        if alpha_mask < red_mask {
-          if red_mask < blue_mask {
-            //argb
-            self.contents[start as usize] = new_color[3];
-            self.contents[(start+1) as usize] = new_color[0];
-            self.contents[(start+2) as usize] = new_color[1];
-            self.contents[(start+3) as usize] = new_color[2];
-          } else {
-            //abgr
-            self.contents[start as usize] = new_color[3];
-            self.contents[(start+1) as usize] = new_color[2];
-            self.contents[(start+2) as usize] = new_color[1];
-            self.contents[(start+3) as usize] = new_color[0];
-          }
-        } else {
-          if red_mask < blue_mask {
-            //rgba
-            self.contents[start as usize] = new_color[0];
-            self.contents[(start+1) as usize] = new_color[1];
-            self.contents[(start+2) as usize] = new_color[2];
-            self.contents[(start+3) as usize] = new_color[3];
-          } else {
-            //bgra
-            self.contents[start as usize] = new_color[2];
-            self.contents[(start+1) as usize] = new_color[1];
-            self.contents[(start+2) as usize] = new_color[0];
-            self.contents[(start+3) as usize] = new_color[3];
-          }
-        }
-      }
+      self.change_color_of_pixel_efficient(pixel[0], pixel[1], new_color, &dib_header, &header)?;
     }
     return Ok(());
   }
   //image editing functions
-  /// Draw another loaded BMP file on the current BMP file, with upper left corner of the drawn on file at the given x and y coordinates.
-  pub fn draw_image(&mut self, x: u16, y: u16, bmp2: BMP)  -> Result<(), ErrorKind> {
-    let dib_header = self.get_dib_header().unwrap();
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    //get height and width
-    let bmp2_dib_header = bmp2.get_dib_header().unwrap();
-    let bmp2_height = (bmp2_dib_header.height).abs();
-    let bmp2_width = bmp2_dib_header.width;
-    let bmp2_pixel_data = bmp2.get_pixel_data();
-    let bmp2_pixel_data = match bmp2_pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
+  /// Decode the image into an `Image`, which stores the pixels as a flat RGBA buffer. See `Image`.
+  pub fn to_image(&self) -> Result<Image, ErrorKind> {
+    return Image::from_bmp(self);
+  }
+  //an empty image of a different size, in the same format (headers, bit masks and color table), with the size fields rewritten.
+  //compressed images cannot be written, so they get the format `BMP::new()` creates instead
+  fn new_canvas(&self, width: u32, height: u32) -> Result<BMP, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    let offset = self.get_offset() as usize;
+    let pixel_array_length: u64 = (u64::from(dib_header.bitcount)*u64::from(dib_header.width)).div_ceil(32)*4*u64::from(dib_header.height.unsigned_abs());
+    let tail_start = (offset as u64+pixel_array_length).min(self.contents.len() as u64) as usize;
+    return BMP::assemble_canvas(&self.contents[..offset], &self.contents[tail_start..], tail_start, dib_header, width, height, self.palette_policy);
+  }
+  //the canvas for `new_canvas()`, from the bytes of the file before the pixel array (`head`) and after it (`tail`, which started at `tail_start`)
+  fn assemble_canvas(head: &[u8], tail: &[u8], tail_start: usize, mut dib_header: DIBHEADER, width: u32, height: u32, palette_policy: PalettePolicy) -> Result<BMP, ErrorKind> {
+    if width == 0 || height == 0 || height > i32::MAX as u32 {
+      return Err(ErrorKind::InvalidParameter("width and height must be more than 0".to_string()));
+    }
+    let compression = dib_header.compression.clone().unwrap_or("BI_RGB".to_string());
+    if compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS" {
+      BMP::check_new_size(width, height)?;
+      return Ok(BMP::new(height as i32, width, Some([0, 0, 0, 0])));
+    }
+    if dib_header.size == 12 && (width > u32::from(u16::MAX) || height > i16::MAX as u32) {
+      return Err(ErrorKind::InvalidParameter("BITMAPCOREHEADER images must be smaller than 65536x32768".to_string()));
+    }
+    let offset = head.len();
+    //in u64, since the size can overflow before it is checked
+    let new_length: u64 = (u64::from(dib_header.bitcount)*u64::from(width)).div_ceil(32)*4*u64::from(height);
+    if offset as u64+new_length+tail.len() as u64 > u64::from(u32::MAX) {
+      return Err(ErrorKind::InvalidParameter(format!("a {}x{} image is too large", width, height)));
+    }
+    let new_length = new_length as usize;
+    let mut contents: Vec<u8> = head.to_vec();
+    contents.resize(offset+new_length, 0);
+    contents.extend_from_slice(tail);
+    //an embedded color profile after the pixel array has to move with the end of it
+    if let Some(profile_data) = dib_header.ProfileData {
+      let profile_start = HEADER_OFFSET+profile_data as usize;
+      if profile_data > 0 && profile_start >= tail_start {
+        dib_header.ProfileData = Some((profile_start-tail_start+offset+new_length-HEADER_OFFSET) as u32);
+      }
+    }
+    dib_header.width = width;
+    dib_header.height = if dib_header.height < 0 { -(height as i32) } else { height as i32 };
+    if dib_header.sizeimage.is_some() {
+      dib_header.sizeimage = Some(new_length as u32);
+    }
+    let dib_header_bytes: Vec<u8> = dib_header.to_bytes()?;
+    contents[HEADER_OFFSET..HEADER_OFFSET+dib_header_bytes.len()].copy_from_slice(&dib_header_bytes);
+    let file_size = contents.len() as u32;
+    contents[2..6].copy_from_slice(&file_size.to_le_bytes());
+    let mut canvas = BMP::from_bytes(&contents)?;
+    canvas.palette_policy = palette_policy;
+    return Ok(canvas);
+  }
+  //copy the pixels into a new image of the given size (see `new_canvas()`), `source_of` gives the coordinates of the pixel each new pixel is copied from.
+  //the stored bits are copied without being decoded, so nothing about the pixels changes
+  fn rearrange_pixels(&self, width: u32, height: u32, source_of: impl Fn(u32, u32) -> (u32, u32)) -> Result<BMP, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    let bitcount = usize::from(dib_header.bitcount);
+    let source_height = dib_header.height.unsigned_abs() as usize;
+    let source_row_length = (bitcount*dib_header.width as usize).div_ceil(32)*4;
+    let source_offset = self.get_offset() as usize;
+    //compressed and cut off pixel arrays are decoded instead
+    if (compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS") || source_offset+source_row_length*source_height > self.contents.len() {
+      return self.to_image()?.rearrange_pixels(width, height, source_of)?.to_bmp();
+    }
+    let mut canvas = self.new_canvas(width, height)?;
+    let row_length = (bitcount*width as usize).div_ceil(32)*4;
+    let offset = canvas.get_offset() as usize;
+    //bottom up images store the bottom row first
+    let source_row_start = |y: usize| -> usize { source_offset+source_row_length*if dib_header.height > 0 { source_height-y-1 } else { y } };
+    let row_start = |y: usize| -> usize { offset+row_length*if dib_header.height > 0 { height as usize-y-1 } else { y } };
+    for y in 0..height {
+      for x in 0..width {
+        let (source_x, source_y) = source_of(x, y);
+        let source_start = source_row_start(source_y as usize);
+        let start = row_start(y as usize);
+        if bitcount >= 8 {
+          let byte_num = bitcount/8;
+          let (source_x, x) = (source_x as usize*byte_num, x as usize*byte_num);
+          canvas.contents[start+x..start+x+byte_num].copy_from_slice(&self.contents[source_start+source_x..source_start+source_x+byte_num]);
+        } else {
+          BMP::copy_packed_pixel(&self.contents[source_start..], source_x as usize, &mut canvas.contents[start..], x as usize, bitcount);
+        }
+      }
+    }
+    return Ok(canvas);
+  }
+  //copy the pixels into a new canvas of the same size, mirrored left to right and/or top to bottom, a whole row at a time
+  fn flip_pixels(&self, horizontal: bool, vertical: bool) -> Result<BMP, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    let bitcount = usize::from(dib_header.bitcount);
+    let (width, height) = (dib_header.width as usize, dib_header.height.unsigned_abs() as usize);
+    let row_length = (bitcount*width).div_ceil(32)*4;
+    let source_offset = self.get_offset() as usize;
+    //compressed and cut off pixel arrays are decoded instead
+    if (compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS") || source_offset+row_length*height > self.contents.len() {
+      return self.to_image()?.flip_pixels(horizontal, vertical)?.to_bmp();
+    }
+    let mut canvas = self.new_canvas(width as u32, height as u32)?;
+    let offset = canvas.get_offset() as usize;
+    //flipping the stored rows flips the image whether it is bottom up or top down
+    for y in 0..height {
+      let source_y = if vertical { height-y-1 } else { y };
+      let source_row = &self.contents[source_offset+source_y*row_length..source_offset+(source_y+1)*row_length];
+      let row = &mut canvas.contents[offset+y*row_length..offset+(y+1)*row_length];
+      if !horizontal {
+        row.copy_from_slice(source_row);
+      } else if bitcount >= 8 {
+        let byte_num = bitcount/8;
+        for (pixel, source_pixel) in row[..width*byte_num].chunks_exact_mut(byte_num).zip(source_row[..width*byte_num].chunks_exact(byte_num).rev()) {
+          pixel.copy_from_slice(source_pixel);
+        }
+      } else {
+        for x in 0..width {
+          BMP::copy_packed_pixel(source_row, width-x-1, row, x, bitcount);
+        }
+      }
+    }
+    return Ok(canvas);
+  }
+  //swap the horizontal and vertical resolution, for images rotated by 90 or 270 degrees
+  fn swap_resolution(&mut self) -> Result<(), ErrorKind> {
+    let mut dib_header = self.get_dib_header()?;
+    if dib_header.XPelsPerMeter.is_none() {
+      return Ok(());
+    }
+    std::mem::swap(&mut dib_header.XPelsPerMeter, &mut dib_header.YPelsPerMeter);
+    return self.set_dib_header(dib_header);
+  }
+  //decode the image, edit it, and write the changed pixels back
+  fn edit_image<T>(&mut self, edit: impl FnOnce(&mut Image) -> Result<T, ErrorKind>) -> Result<T, ErrorKind> {
+    let mut image = self.to_image()?;
+    let returned = edit(&mut image)?;
+    image.write_changes(self, false)?;
+    return Ok(returned);
+  }
+  /// Draw another loaded BMP file on the current BMP file, with upper left corner of the drawn on file at the given x and y coordinates.
+  pub fn draw_image(&mut self, x: u16, y: u16, bmp2: BMP)  -> Result<(), ErrorKind> {
+    let image2 = bmp2.to_image()?;
+    return self.edit_image(|image| image.draw_image(x, y, &image2));
+  }
+  /// Change opacity of the whole image.
+  pub fn change_opacity(&mut self, opacity: u8) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.change_opacity(opacity));
+  }
+  /// Invert the colors of the image.
+  pub fn invert(&mut self, invert_alpha: Option<bool>) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.invert(invert_alpha));
+  }
+  //translate, rotate
+  /// Translate the image in the horizontal and/or vertical directions.
+  pub fn translate(&mut self, x: i16, y: i16) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.translate(x, y));
+  }
+  /// Rotate the image clockwise. If no center coordinate provided, defaults to `(0, 0)` (top left corner of image).
+  /// The size stays the same, see `BMP::rotate_with_options()` for interpolation and expanding the canvas.
+  pub fn rotate(&mut self, deg: f64, center_option: Option<[u16; 2]>) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.rotate(deg, center_option));
+  }
+  /// Get a copy of the image rotated clockwise by the given degrees, see `RotateOptions`. The new BMP is 32 bit, like `BMP::new()` creates.
+  pub fn rotate_with_options(&self, deg: f64, options: &RotateOptions) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.rotate_with_options(deg, options)?.to_bmp();
+  }
+  /// Get a copy of the image with an affine or perspective transform applied, see `Image::transform()`. The new BMP is 32 bit, like `BMP::new()` creates.
+  pub fn transform(&self, transform: &Transform, options: &TransformOptions) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.transform(transform, options)?.to_bmp();
+  }
+  /// Get a copy of the image with the four source corners moved to the four destination corners, see `Image::warp_perspective()`. The new BMP is 32 bit, like `BMP::new()` creates.
+  pub fn warp_perspective(&self, source: [[f64; 2]; 4], destination: [[f64; 2]; 4], options: &TransformOptions) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.warp_perspective(source, destination, options)?.to_bmp();
+  }
+  //resizing
+  /// Get a copy of the image resized to the given width and height, see `ResizeFilter`. The new BMP is 32 bit, like `BMP::new()` creates.
+  pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.resize(width, height, filter)?.to_bmp();
+  }
+  /// Get a copy of the image resized to fit inside the given width and height, keeping the aspect ratio, see `Image::scale_to_fit()`.
+  pub fn scale_to_fit(&self, max_width: u32, max_height: u32, filter: ResizeFilter) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.scale_to_fit(max_width, max_height, filter)?.to_bmp();
+  }
+  /// Get a copy of the image resized to fill the given width and height, keeping the aspect ratio, see `Image::scale_to_fill()`.
+  pub fn scale_to_fill(&self, width: u32, height: u32, filter: ResizeFilter) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.scale_to_fill(width, height, filter)?.to_bmp();
+  }
+  //cropping and canvas
+  /// Get the `width` by `height` part of the image with its upper left corner at `(x, y)`, see `Image::crop()`.
+  pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.crop(x, y, width, height)?.to_bmp();
+  }
+  /// Get a copy of the image on a canvas of a different size, see `Image::resize_canvas()`.
+  pub fn resize_canvas(&self, width: u32, height: u32, anchor: Anchor, fill: [u8; 4]) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.resize_canvas(width, height, anchor, fill)?.to_bmp();
+  }
+  /// Get a copy of the image with borders added to each side, see `Image::pad()`.
+  pub fn pad(&self, top: u32, right: u32, bottom: u32, left: u32, fill: [u8; 4]) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.pad(top, right, bottom, left, fill)?.to_bmp();
+  }
+  //flips and right angle rotations
+  /// Get a copy of the image mirrored left to right. The stored pixels are copied as they are, so this is lossless for every format.
+  pub fn flip_horizontal(&self) -> Result<BMP, ErrorKind> {
+    return self.flip_pixels(true, false);
+  }
+  /// Get a copy of the image mirrored top to bottom. Lossless, like `flip_horizontal()`.
+  pub fn flip_vertical(&self) -> Result<BMP, ErrorKind> {
+    return self.flip_pixels(false, true);
+  }
+  /// Get a copy of the image rotated 90 degrees clockwise, so the width and height are swapped. Lossless, like `flip_horizontal()`.
+  pub fn rotate90(&self) -> Result<BMP, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    let height = dib_header.height.unsigned_abs();
+    let mut rotated = self.rearrange_pixels(height, dib_header.width, |x, y| (y, height-x-1))?;
+    rotated.swap_resolution()?;
+    return Ok(rotated);
+  }
+  /// Get a copy of the image rotated 180 degrees. Lossless, like `flip_horizontal()`.
+  pub fn rotate180(&self) -> Result<BMP, ErrorKind> {
+    return self.flip_pixels(true, true);
+  }
+  /// Get a copy of the image rotated 270 degrees clockwise (90 degrees counterclockwise), so the width and height are swapped. Lossless, like `flip_horizontal()`.
+  pub fn rotate270(&self) -> Result<BMP, ErrorKind> {
+    let dib_header = self.get_dib_header()?;
+    let width = dib_header.width;
+    let mut rotated = self.rearrange_pixels(dib_header.height.unsigned_abs(), width, |x, y| (width-y-1, x))?;
+    rotated.swap_resolution()?;
+    return Ok(rotated);
+  }
+  //blurs
+  pub fn separable_blur(&mut self, radius: u8, gen_distribution: impl Fn(u8, u8) -> u16, horizontal: Option<bool>, vertical: Option<bool>) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.separable_blur(radius, gen_distribution, horizontal, vertical));
+  }
+  /// Apply box blur with given radius.
+  /// The larger the radius, the more it will blur, since the radius determines how many neighbouring pixels will be considered.
+  /// 
+  /// A box blur essentially averages the color value of every pixel with the neighbours' color values.
+  /// 
+  /// Since a box blur is a separable filter, unlike the mean filter, but has the same effect, it is recommended to use `box_blur` instead of `mean_filter`.
+  /// 
+  pub fn box_blur(&mut self, radius: u8) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.box_blur(radius));
+  }
+  /// Apply gaussian blur with given radius.
+  /// The larger the radius, the more it will blur, since the radius determines how many neighbouring pixels will be considered.
+  /// 
+  /// Learn more about gaussian blurs [here](https://en.wikipedia.org/wiki/Gaussian_blur).
+  /// 
+  pub fn gaussian_blur(&mut self, radius: u8) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.gaussian_blur(radius));
+  }
+  //filters
+  /// Turn the image into grayscale
+  pub fn grayscale(&mut self) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.grayscale());
+  }
+  pub fn greyscale(&mut self) -> Result<(), ErrorKind> {
+    //just an alias function with 'grey' instead of 'gray'
+    self.grayscale()
+  }
+  /// Only considers the value of a specific channel (red, green, alpha) when turning the image grayscale.
+  pub fn channel_grayscale(&mut self, channel: RGBAChannel) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.channel_grayscale(channel));
+  }
+  pub fn surround_filter(&mut self, radius: u8, get_new_pixel: impl Fn(Vec<[u8; 4]>) -> [u8; 4]) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.surround_filter(radius, get_new_pixel));
+  }
+  /// A median filter changes the color a value of a pixel to the median color value of all of the pixel's neighbours.
+  /// The larger the radius, the more neighbouring pixels will be considered.
+  /// 
+  /// Median filters are great at filtering out noise.
+  /// 
+  pub fn median_filter(&mut self, radius: u8) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.median_filter(radius));
+  }
+  /// A less efficient version of the `box_blur`, use that instead.
+  pub fn mean_filter(&mut self, radius: u8) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.mean_filter(radius));
+  }
+  //shape, line making functions
+  /// Draw a line specifying color fill, start point and end point.
+  pub fn draw_line(&mut self, fill: [u8; 4], p1: [u16; 2], p2: [u16; 2]) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.draw_line(fill, p1, p2));
+  }
+  //p1 is top left, p2 is top right
+  /// Draw rectangle, specifying fill color and/or stroke color, and upper left and lower right corners.
+  pub fn draw_rectangle(&mut self, fill: Option<[u8; 4]>, stroke: Option<[u8; 4]>, p1: [u16; 2], p2: [u16; 2]) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.draw_rectangle(fill, stroke, p1, p2));
+  }
+  /// Draw an ellipse specifying the center coordinates, horizontal radius, vertical radius, stroke color and optionally fill color.
+  /// 
+  /// If `guess` is `true`, the function will guess missing parts of the ellipse, so there are no gaps in the ellipse outline.
+  /// 
+  pub fn draw_ellipse(&mut self, center: [u16; 2], xlength: u16, ylength: u16, stroke: [u8; 4], fill: Option<[u8; 4]>, guess: bool) -> Result<(), ErrorKind> {
+    return self.edit_image(|image| image.draw_ellipse(center, xlength, ylength, stroke, fill, guess));
+  }
+  /// Equivalent to the bucket fill tool in many image editors. Specify the starting coordinates and the new fill color.
+  pub fn fill_bucket(&mut self, fill: [u8; 4], x: usize, y: usize) -> Result<Vec<[u16; 2]>, ErrorKind> {
+    return self.edit_image(|image| image.fill_bucket(fill, x, y));
+  }
+  //save image functions
+  /// Save the image to a new file.
+  pub fn save_to_new(&self, file_path: &str) -> Result<(), ErrorKind> {
+    let new_file = fs::File::create(std::path::Path::new(file_path))?;
+    return self.write_to(new_file);
+  }
+  /// Write the image to anything that can be written to, like a network stream or a `Vec<u8>`.
+  pub fn write_to(&self, mut writer: impl Write) -> Result<(), ErrorKind> {
+    writer.write_all(&self.contents)?;
+    return Ok(());
+  }
+  /// Get the bytes of the BMP file.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    return self.contents.clone();
+  }
+  /// Create a new BMP in the given format, see `BMP::new()` and `EncoderOptions`.
+  pub fn new_with_options(height: i32, width: u32, default_color: Option<[u8; 4]>, options: &EncoderOptions) -> Result<BMP, ErrorKind> {
+    return BMP::new(height, width, default_color).encode(options);
+  }
+  /// Convert the image to another format (DIB header version, bit depth, compression, orientation, DPI), see `EncoderOptions`.
+  pub fn encode(&self, options: &EncoderOptions) -> Result<BMP, ErrorKind> {
+    return self.to_image()?.encode(options);
+  }
+  /// Save the image to a new file, converted to the format given by the options. The BMP itself is not changed.
+  pub fn save_with_options(&self, file_path: &str, options: &EncoderOptions) -> Result<(), ErrorKind> {
+    return self.encode(options)?.save_to_new(file_path);
+  }
+  /// Generate a color table for the image with the median cut algorithm, see `Image::median_cut_palette()`.
+  pub fn median_cut_palette(&self, colors: u16) -> Result<ColorTable, ErrorKind> {
+    return self.to_image()?.median_cut_palette(colors);
+  }
+  /// Generate a color table for the image with an octree, see `Image::octree_palette()`.
+  pub fn octree_palette(&self, colors: u16) -> Result<ColorTable, ErrorKind> {
+    return self.to_image()?.octree_palette(colors);
+  }
+  /// Convert the image to color table indexes, see `Image::remap()`.
+  pub fn remap(&self, palette: &ColorTable, transparent_index: Option<u8>) -> Result<IndexedImage, ErrorKind> {
+    return self.to_image()?.remap(palette, transparent_index);
+  }
+  /// Convert the image to dithered color table indexes, see `Image::remap_dithered()`.
+  pub fn remap_dithered(&self, palette: &ColorTable, transparent_index: Option<u8>, dither: Dither) -> Result<IndexedImage, ErrorKind> {
+    return self.to_image()?.remap_dithered(palette, transparent_index, dither);
+  }
+}
+
+//Decoded image
+/// A decoded BMP. The file header and DIB header are parsed once, and the pixels are stored as a flat, contiguous buffer of RGBA bytes.
+///
+/// Rows go from top to bottom regardless of how the BMP stores them, and every row is `stride` bytes long (4 bytes per pixel, no padding).
+/// The color of pixel `(x, y)` is at `pixels[y*stride+x*4..y*stride+x*4+4]`.
+///
+/// All of the image editing functions work on an `Image`. Convert back with `to_bmp()`, which writes the pixels in the BMP's original format.
+/// Pixels that were not changed are left exactly as they were (only the padding at the end of rows can change, see `to_bmp()`), so converting to an `Image` and back is lossless.
+///
+#[derive(Clone)]
+pub struct Image {
+  header: BITMAPFILEHEADER,
+  dib_header: DIBHEADER,
+  width: u32,
+  height: u32,
+  stride: usize,
+  pixels: Vec<u8>,
+  //one bit for each pixel (a row at a time, top row first), set when the pixel may have changed since decoding
+  dirty: Vec<u64>,
+  //what is needed to write the pixels back in the same format: the bytes of the file before the pixel array (headers, bit masks and color table)
+  //and after it (eg: an embedded color profile), and the bit masks and color table themselves
+  head: Vec<u8>,
+  tail: Vec<u8>,
+  masks: Option<BI_ALPHABITFIELDS_MASKS>,
+  palette: Vec<[u8; 4]>,
+  palette_policy: PalettePolicy,
+  //the pixel array as it is stored, only kept if the pixels can not be encoded back to exactly the same bits
+  //(compressed pixel arrays, channels wider than 8 bits or bits in none of the bit masks, or a color table with the same color more than once)
+  stored_pixels: Option<Vec<u8>>,
+}
+
+/// An image as color table indexes, returned by `Image::remap()`. Indexes are stored a row at a time, top row first.
+#[derive(Clone, Debug)]
+pub struct IndexedImage {
+  width: u32,
+  height: u32,
+  palette: ColorTable,
+  indexes: Vec<u8>,
+  transparent_index: Option<u8>,
+}
+
+impl IndexedImage {
+  /// Encode into a new 1, 4 or 8 bit BMP file, with the indexes and color table as they are. The color table in the options is ignored.
+  pub fn encode(&self, options: &EncoderOptions) -> Result<BMP, ErrorKind> {
+    if options.bitcount > 8 {
+      return Err(ErrorKind::InvalidParameter("indexed images must be 1, 4 or 8 bit".to_string()));
+    }
+    let bitcount = usize::from(options.bitcount);
+    let width = self.width as usize;
+    let height = self.height as usize;
+    return Image::assemble_bmp(self.width, self.height, options, &self.palette.to_rgb(), |compression, _| {
+      if compression == "BI_RLE8" || compression == "BI_RLE4" {
+        //RLE images are always bottom up
+        let rows: Vec<Vec<u8>> = self.indexes.chunks_exact(width.max(1)).rev().map(|row| row.to_vec()).collect();
+        return Ok(Image::encode_rle(&rows, compression == "BI_RLE8"));
+      }
+      let row_length = (bitcount*width).div_ceil(32)*4;
+      let mut pixel_array: Vec<u8> = vec![0; row_length*height];
+      for (row_num, row) in pixel_array.chunks_exact_mut(row_length).enumerate() {
+        //bottom up images store the bottom row first
+        let y = if options.top_down { row_num } else { height-row_num-1 };
+        for x in 0..width {
+          //pixels smaller than a byte are packed starting from the highest bits
+          let bit_position = x*bitcount;
+          row[bit_position/8] |= self.indexes[y*width+x] << (8-bitcount-bit_position % 8);
+        }
+      }
+      return Ok(pixel_array);
+    });
+  }
+  /// Get the width of the image.
+  pub fn get_width(&self) -> u32 {
+    return self.width;
+  }
+  /// Get the height of the image.
+  pub fn get_height(&self) -> u32 {
+    return self.height;
+  }
+  /// Get the color table the indexes are into.
+  pub fn get_palette(&self) -> &ColorTable {
+    return &self.palette;
+  }
+  /// Get the index of every pixel, a row at a time, top row first.
+  pub fn get_indexes(&self) -> &[u8] {
+    return &self.indexes;
+  }
+  /// Get the index that transparent pixels were given, if there is one.
+  pub fn get_transparent_index(&self) -> Option<u8> {
+    return self.transparent_index;
+  }
+  /// Given x and y coordinates (`(0, 0)` is the upper left corner of the image), get the color table index at that location.
+  pub fn get_index(&self, x: usize, y: usize) -> Result<u8, ErrorKind> {
+    if x >= self.width as usize || y >= self.height as usize {
+      return Err(ErrorKind::OutOfBounds);
+    }
+    return Ok(self.indexes[y*self.width as usize+x]);
+  }
+}
+
+impl Image {
+  /// Decode a loaded BMP file.
+  pub fn from_bmp(bmp: &BMP) -> Result<Image, ErrorKind> {
+    let header = bmp.get_header();
+    let dib_header = bmp.get_dib_header()?;
+    let pixels = Image::decode_pixels(bmp, &header, &dib_header)?;
+    let (width, height) = (dib_header.width, dib_header.height.unsigned_abs());
+    let bitcount = dib_header.bitcount;
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    let compressed: bool = compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS";
+    let masks: Option<BI_ALPHABITFIELDS_MASKS> = if !compressed && (bitcount == 16 || bitcount == 32) {
+      Some(bmp.get_bit_masks_efficient(&dib_header)?)
+    } else {
+      None
+    };
+    let palette: Vec<[u8; 4]> = if (1..=8).contains(&bitcount) {
+      bmp.get_palette()?
+    } else {
+      Vec::new()
+    };
+    //compressed pixel arrays go to the end of the file
+    let offset = header.bfOffBits as usize;
+    let end: usize = if compressed {
+      bmp.contents.len()
+    } else {
+      offset+(u32::from(bitcount)*width).div_ceil(32) as usize*4*height as usize
+    };
+    //channels wider than 8 bits lose bits when decoded, and bits in none of the masks are not decoded at all
+    let lossy_masks: bool = masks.as_ref().is_some_and(|masks| {
+      let all_bits: u32 = if bitcount == 16 { 0xFFFF } else { u32::MAX };
+      return [masks.red, masks.green, masks.blue, masks.alpha].iter().any(|mask| mask.count_ones() > 8) || masks.red | masks.green | masks.blue | masks.alpha != all_bits;
+    });
+    let repeated_colors: bool = (1..palette.len()).any(|i| palette[..i].contains(&palette[i]));
+    let stored_pixels: Option<Vec<u8>> = if compressed || lossy_masks || repeated_colors {
+      Some(bmp.contents[offset..end].to_vec())
+    } else {
+      None
+    };
+    return Ok(Image {
+      width,
+      height,
+      stride: width as usize*4,
+      pixels,
+      dirty: vec![0; (width as usize*height as usize).div_ceil(64)],
+      head: bmp.contents[..offset].to_vec(),
+      tail: bmp.contents[end..].to_vec(),
+      masks,
+      palette,
+      palette_policy: bmp.palette_policy,
+      stored_pixels,
+      header,
+      dib_header,
+    });
+  }
+  //turn the pixel array into top down rows of rgba
+  fn decode_pixels(bmp: &BMP, header: &BITMAPFILEHEADER, dib_header: &DIBHEADER) -> Result<Vec<u8>, ErrorKind> {
+    let width = dib_header.width as usize;
+    let height = dib_header.height.unsigned_abs() as usize;
+    let bitcount = dib_header.bitcount;
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    if compression == "BI_PNG" || compression == "BI_JPEG" {
+      return bmp.decode_embedded(dib_header);
+    }
+    if ![1, 2, 4, 8, 16, 24, 32].contains(&bitcount) {
+      return Err(ErrorKind::UnsupportedBitDepth(bitcount));
+    }
+    //masks and color table are only read once, instead of for every pixel
+    let masks: Option<BI_ALPHABITFIELDS_MASKS> = if bitcount == 16 || bitcount == 32 {
+      Some(bmp.get_bit_masks_efficient(dib_header)?)
+    } else {
+      None
+    };
+    let palette: Vec<[u8; 4]> = if bitcount <= 8 {
+      bmp.get_palette()?
+    } else {
+      Vec::new()
+    };
+    let mut pixels: Vec<u8> = Vec::with_capacity(width*height*4);
+    if compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS" {
+      //compressed pixel arrays need to be decompressed first
+      let pixel_data = bmp.get_pixel_data()?;
+      for row in &pixel_data {
+        for pixel in row {
+          pixels.extend(BMP::decode_pixel(pixel, bitcount, masks.as_ref(), &palette)?);
+        }
+      }
+      if pixels.len() != width*height*4 {
+        return Err(ErrorKind::Missing);
+      }
+      return Ok(pixels);
+    }
+    //rows are padded to a multiple of 4 bytes
+    let row_length = (u32::from(bitcount)*dib_header.width).div_ceil(32) as usize*4;
+    let offset = header.bfOffBits as usize;
+    if offset+row_length*height > bmp.contents.len() {
+      return Err(ErrorKind::Missing);
+    }
+    for y in 0..height {
+      //bottom up images store the bottom row first
+      let row_num = if dib_header.height > 0 { height-y-1 } else { y };
+      let row = &bmp.contents[offset+row_num*row_length..offset+(row_num+1)*row_length];
+      for x in 0..width {
+        let color: [u8; 4] = if bitcount < 8 {
+          //pixels smaller than a byte are packed starting from the highest bits
+          let bit_position = x*usize::from(bitcount);
+          let shift = 8-usize::from(bitcount)-bit_position % 8;
+          let index: u8 = (row[bit_position/8] >> shift) & (((1u16 << bitcount)-1) as u8);
+          BMP::decode_pixel(&[index], bitcount, None, &palette)?
+        } else {
+          let byte_num = usize::from(bitcount/8);
+          BMP::decode_pixel(&row[x*byte_num..(x+1)*byte_num], bitcount, masks.as_ref(), &palette)?
+        };
+        pixels.extend(color);
+      }
+    }
+    return Ok(pixels);
+  }
+  /// Encode the image back into a BMP file, in the same format (bit depth, masks, color table, orientation) it was decoded from.
+  ///
+  /// For images with a color table, new colors are written following the BMP's `PalettePolicy`.
+  /// Changing the pixels of a compressed image is not supported.
+  ///
+  /// Unless the pixels can not be encoded back to exactly the same bits, every pixel is encoded again, so the padding at the end of rows is written as 0.
+  ///
+  pub fn to_bmp(&self) -> Result<BMP, ErrorKind> {
+    let mut contents: Vec<u8> = self.head.clone();
+    match &self.stored_pixels {
+      Some(stored_pixels) => contents.extend_from_slice(stored_pixels),
+      None => contents.resize(self.head.len()+(u32::from(self.dib_header.bitcount)*self.width).div_ceil(32) as usize*4*self.height as usize, 0),
+    }
+    contents.extend_from_slice(&self.tail);
+    let mut bmp = BMP { contents, from_file: false, palette_policy: self.palette_policy };
+    self.write_changes(&mut bmp, self.stored_pixels.is_none())?;
+    return Ok(bmp);
+  }
+  //write the pixels that changed since decoding (or every pixel) into a bmp with the pixel array it was decoded from (or an empty one)
+  fn write_changes(&self, bmp: &mut BMP, every_pixel: bool) -> Result<(), ErrorKind> {
+    let dib_header = &self.dib_header;
+    let bitcount = dib_header.bitcount;
+    let compression = dib_header.compression.as_deref().unwrap_or("BI_RGB");
+    if compression != "BI_RGB" && compression != "BI_BITFIELDS" && compression != "BI_ALPHABITFIELDS" {
+      //pixels can be marked as changed without their color changing, so only actual changes are an error
+      if self.dirty.iter().any(|bits| *bits != 0) {
+        let stored = Image::decode_pixels(bmp, &self.header, dib_header)?;
+        if (0..self.width as usize*self.height as usize).any(|i| self.is_dirty(i) && stored[i*4..i*4+4] != self.pixels[i*4..i*4+4]) {
+          return Err(ErrorKind::UnsupportedCompression(compression.to_string()));
+        }
+      }
+      return Ok(());
+    }
+    let masks: Option<&BI_ALPHABITFIELDS_MASKS> = self.masks.as_ref();
+    let row_length = (u32::from(bitcount)*dib_header.width).div_ceil(32) as usize*4;
+    let height = self.height as usize;
+    //remember which color table index each color was given, so the color table is only searched once per color
+    let mut indexes: HashMap<[u8; 4], u8> = HashMap::new();
+    for y in 0..height {
+      for x in 0..self.width as usize {
+        let start = y*self.stride+x*4;
+        let color: [u8; 4] = self.pixels[start..start+4].try_into().unwrap();
+        if !every_pixel && (!self.is_dirty(y*self.width as usize+x) || self.get_stored_color(bmp, x, y, row_length)? == color) {
+          continue;
+        }
+        if bitcount <= 8 {
+          let index: u8 = match indexes.get(&color) {
+            Some(index) => *index,
+            None => {
+              let index = bmp.get_palette_index(color, dib_header)?;
+              indexes.insert(color, index);
+              index
+            },
+          };
+          bmp.change_index_of_pixel_efficient(x as u16, y as u16, index, dib_header)?;
+          continue;
+        }
+        let row_num = if dib_header.height > 0 { height-y-1 } else { y };
+        let byte_num = usize::from(bitcount/8);
+        let pixel_start = self.header.bfOffBits as usize+row_num*row_length+x*byte_num;
+        if let Some(masks) = masks {
+          //little endian, so only the first 2 bytes are used for 16 bit
+          let value_bytes: [u8; 4] = masks.encode(color).to_le_bytes();
+          bmp.contents[pixel_start..pixel_start+byte_num].copy_from_slice(&value_bytes[..byte_num]);
+        } else {
+          //order is BGR not RGB
+          bmp.contents[pixel_start..pixel_start+3].copy_from_slice(&[color[2], color[1], color[0]]);
+        }
+      }
+    }
+    return Ok(());
+  }
+  //the color pixel `(x, y)` has in the uncompressed pixel array of the bmp
+  fn get_stored_color(&self, bmp: &BMP, x: usize, y: usize, row_length: usize) -> Result<[u8; 4], ErrorKind> {
+    let bitcount = self.dib_header.bitcount;
+    let height = self.height as usize;
+    //bottom up images store the bottom row first. new colors can be added to the color table, which moves the pixel array
+    let row_num = if self.dib_header.height > 0 { height-y-1 } else { y };
+    let row_start = bmp.get_offset() as usize+row_num*row_length;
+    let row = &bmp.contents[row_start..row_start+row_length];
+    if bitcount < 8 {
+      //pixels smaller than a byte are packed starting from the highest bits
+      let bit_position = x*usize::from(bitcount);
+      let index: u8 = (row[bit_position/8] >> (8-usize::from(bitcount)-bit_position % 8)) & (((1u16 << bitcount)-1) as u8);
+      return BMP::decode_pixel(&[index], bitcount, None, &self.palette);
+    }
+    let byte_num = usize::from(bitcount/8);
+    return BMP::decode_pixel(&row[x*byte_num..(x+1)*byte_num], bitcount, self.masks.as_ref(), &self.palette);
+  }
+  //remember that pixel `index` (`y*width+x`) may have changed, so it is written back
+  fn mark_dirty(&mut self, index: usize) {
+    self.dirty[index/64] |= 1 << (index % 64);
+  }
+  //remember that every pixel may have changed
+  fn mark_all_dirty(&mut self) {
+    self.dirty.fill(u64::MAX);
+  }
+  fn is_dirty(&self, index: usize) -> bool {
+    return self.dirty[index/64] & (1 << (index % 64)) != 0;
+  }
+  /// Encode the image into a new BMP file, in the format given by the options, instead of the format it was decoded from.
+  ///
+  /// 1, 4 and 8 bit images use the options' color table, or the image's own color table if it fits, or a default color table, see `Image::remap()`.
+  ///
+  pub fn encode(&self, options: &EncoderOptions) -> Result<BMP, ErrorKind> {
+    let mut bmp = if options.bitcount <= 8 {
+      let palette: ColorTable = match &options.palette {
+        Some(palette) => palette.clone(),
+        None => {
+          let source_palette: &[[u8; 4]] = &self.palette;
+          let palette: Vec<[u8; 3]> = if !source_palette.is_empty() && source_palette.len() <= 1 << options.bitcount {
+            source_palette.iter().map(|rgba| [rgba[0], rgba[1], rgba[2]]).collect()
+          } else {
+            Image::default_palette(options.bitcount)
+          };
+          ColorTable::RGBQUAD(palette.iter().map(|rgb| [rgb[2], rgb[1], rgb[0], 0]).collect())
+        },
+      };
+      self.remap_dithered(&palette, None, options.dither)?.encode(options)?
+    } else {
+      Image::assemble_bmp(self.width, self.height, options, &[], |_, masks| self.encode_pixels(options.bitcount, options.top_down, masks, options.dither))?
+    };
+    bmp.palette_policy = self.palette_policy;
+    return Ok(bmp);
+  }
+  //check the options, and write the headers, color table and pixel array (from `encode_pixels`, given the compression and masks) of a new BMP file
+  fn assemble_bmp(width: u32, height: u32, options: &EncoderOptions, palette: &[[u8; 3]], encode_pixels: impl FnOnce(&str, &BI_ALPHABITFIELDS_MASKS) -> Result<Vec<u8>, ErrorKind>) -> Result<BMP, ErrorKind> {
+    let bitcount = options.bitcount;
+    if ![1, 4, 8, 16, 24, 32].contains(&bitcount) {
+      return Err(ErrorKind::UnsupportedBitDepth(bitcount));
+    }
+    let dib_size: u32 = match options.header_version {
+      HeaderVersion::Core => 12,
+      HeaderVersion::Info => 40,
+      HeaderVersion::V4 => 108,
+      HeaderVersion::V5 => 124,
+    };
+    if options.header_version == HeaderVersion::Core {
+      if bitcount == 16 || bitcount == 32 || options.compression != EncoderCompression::Rgb || options.top_down {
+        return Err(ErrorKind::InvalidParameter("BITMAPCOREHEADER images must be uncompressed, bottom up, and 1, 4, 8 or 24 bit".to_string()));
+      }
+      if width > u32::from(u16::MAX) || height > i16::MAX as u32 {
+        return Err(ErrorKind::InvalidParameter("BITMAPCOREHEADER images must be smaller than 65536x32768".to_string()));
+      }
+    }
+    let compression: &str = match options.compression {
+      EncoderCompression::Rgb => "BI_RGB",
+      EncoderCompression::Rle => {
+        if (bitcount != 4 && bitcount != 8) || options.top_down {
+          return Err(ErrorKind::InvalidParameter("RLE compressed images must be 4 or 8 bit, and bottom up".to_string()));
+        }
+        if bitcount == 8 { "BI_RLE8" } else { "BI_RLE4" }
+      },
+      EncoderCompression::Bitfields => {
+        if bitcount != 16 && bitcount != 32 {
+          return Err(ErrorKind::InvalidParameter("BI_BITFIELDS images must be 16 or 32 bit".to_string()));
+        }
+        "BI_BITFIELDS"
+      },
+    };
+    if bitcount <= 8 && (palette.is_empty() || palette.len() > 1 << bitcount) {
+      return Err(ErrorKind::InvalidParameter(format!("color table must have 1 to {} colors", 1 << bitcount)));
+    }
+    //16 and 32 bit pixels are packed with masks, BI_RGB uses the default ones
+    let masks: BI_ALPHABITFIELDS_MASKS = match (bitcount, compression) {
+      (16, "BI_RGB") => BI_ALPHABITFIELDS_MASKS { red: 0x7C00, green: 0x03E0, blue: 0x001F, alpha: 0 },
+      (16, _) => BI_ALPHABITFIELDS_MASKS { red: 0xF800, green: 0x07E0, blue: 0x001F, alpha: 0 },
+      (32, "BI_BITFIELDS") if dib_size == 40 => BI_ALPHABITFIELDS_MASKS { red: 0x00FF0000, green: 0x0000FF00, blue: 0x000000FF, alpha: 0 },
+      _ => BI_ALPHABITFIELDS_MASKS { red: 0x00FF0000, green: 0x0000FF00, blue: 0x000000FF, alpha: 0xFF000000 },
+    };
+    let pixel_array: Vec<u8> = encode_pixels(compression, &masks)?;
+    //BITMAPINFOHEADER has the BI_BITFIELDS masks after it, instead of in it
+    let extra_masks: Vec<u8> = if dib_size == 40 && compression == "BI_BITFIELDS" {
+      [masks.red.to_le_bytes(), masks.green.to_le_bytes(), masks.blue.to_le_bytes()].concat()
+    } else {
+      Vec::new()
+    };
+    //BITMAPCOREHEADER uses rgbtriple
+    let color_table: Vec<u8> = palette.iter().flat_map(|rgb| if dib_size == 12 { vec![rgb[2], rgb[1], rgb[0]] } else { vec![rgb[2], rgb[1], rgb[0], 0] }).collect();
+    let offset: u32 = HEADER_OFFSET as u32+dib_size+extra_masks.len() as u32+color_table.len() as u32;
+    let header = BITMAPFILEHEADER {
+      bfType: "BM".to_string(),
+      bfSize: offset+pixel_array.len() as u32,
+      bfReserved1: vec![0, 0],
+      bfReserved2: vec![0, 0],
+      bfOffBits: offset,
+    };
+    //pixels per meter
+    let resolution = |dpi: u32| -> u32 { (f64::from(dpi)/0.0254).round() as u32 };
+    let info = |value: u32| -> Option<u32> { if dib_size >= 40 { Some(value) } else { None } };
+    let v4 = dib_size >= 108;
+    let v5 = dib_size >= 124;
+    let has_masks = v4 && compression == "BI_BITFIELDS";
+    let dib_header = DIBHEADER {
+      size: dib_size,
+      width: width,
+      height: if options.top_down { -(height as i32) } else { height as i32 },
+      planes: 1,
+      bitcount,
+      compression: if dib_size >= 40 { Some(compression.to_string()) } else { None },
+      sizeimage: info(pixel_array.len() as u32),
+      XPelsPerMeter: info(resolution(options.dpi[0])),
+      YPelsPerMeter: info(resolution(options.dpi[1])),
+      ClrUsed: info(palette.len() as u32),
+      ClrImportant: info(0),
+      RedMask: if v4 { Some(if has_masks { masks.red } else { 0 }) } else { None },
+      GreenMask: if v4 { Some(if has_masks { masks.green } else { 0 }) } else { None },
+      BlueMask: if v4 { Some(if has_masks { masks.blue } else { 0 }) } else { None },
+      AlphaMask: if v4 { Some(if has_masks { masks.alpha } else { 0 }) } else { None },
+      CSType: if v4 { Some(ColorSpaceType::Srgb) } else { None },
+      Endpoints: if v4 { Some(SRGB_ENDPOINTS) } else { None },
+      GammaRed: if v4 { Some(0) } else { None },
+      GammaGreen: if v4 { Some(0) } else { None },
+      GammaBlue: if v4 { Some(0) } else { None },
+      Intent: if v5 { Some(RenderingIntent::Images) } else { None },
+      ProfileData: if v5 { Some(0) } else { None },
+      ProfileSize: if v5 { Some(0) } else { None },
+      Reserved: if v5 { Some(vec![0, 0, 0, 0]) } else { None },
+      Units: None,
+      Padding: None,
+      Recording: None,
+      Rendering: None,
+      Size1: None,
+      Size2: None,
+      ColorEncoding: None,
+      Identifier: None,
+    };
+    let mut contents: Vec<u8> = Vec::with_capacity(header.bfSize as usize);
+    contents.extend(header);
+    contents.extend(dib_header);
+    contents.extend(extra_masks);
+    contents.extend(color_table);
+    contents.extend(pixel_array);
+    return BMP::from_bytes(&contents);
+  }
+  //the 16, 24 or 32 bit pixel array, uncompressed, with each row padded to a multiple of 4 bytes
+  fn encode_pixels(&self, bitcount: u16, top_down: bool, masks: &BI_ALPHABITFIELDS_MASKS, dither: Dither) -> Result<Vec<u8>, ErrorKind> {
+    let width = self.width as usize;
+    let height = self.height as usize;
+    //16 bit pixels can be dithered to the colors the masks can store
+    let dithered: Option<Vec<(u8, [u8; 3])>> = if bitcount == 16 && dither != Dither::None {
+      //the difference between two levels of each channel
+      let spread = [masks.red, masks.green, masks.blue].map(|mask| 255.0/((1u32 << BI_ALPHABITFIELDS_MASKS::shift_and_width(mask).1)-1) as f32);
+      Some(self.dither_pixels(dither, spread, None, |rgb| {
+        let color = masks.decode(masks.encode([rgb[0], rgb[1], rgb[2], 255]));
+        (0, [color[0], color[1], color[2]])
+      })?)
+    } else {
+      None
+    };
+    let row_length = (u32::from(bitcount)*self.width).div_ceil(32) as usize*4;
+    let mut pixel_array: Vec<u8> = vec![0; row_length*height];
+    for (row_num, row) in pixel_array.chunks_exact_mut(row_length).enumerate() {
+      //bottom up images store the bottom row first
+      let y = if top_down { row_num } else { height-row_num-1 };
+      for x in 0..width {
+        let mut color = Image::get_color_from(&self.pixels, self.stride, x, y);
+        if let Some(dithered) = &dithered {
+          color[..3].copy_from_slice(&dithered[y*width+x].1);
+        }
+        if bitcount == 24 {
+          //order is BGR not RGB
+          row[x*3..x*3+3].copy_from_slice(&[color[2], color[1], color[0]]);
+        } else {
+          //little endian, so only the first 2 bytes are used for 16 bit
+          let byte_num = usize::from(bitcount/8);
+          row[x*byte_num..(x+1)*byte_num].copy_from_slice(&masks.encode(color).to_le_bytes()[..byte_num]);
+        }
+      }
+    }
+    return Ok(pixel_array);
+  }
+  /// Convert the image to color table indexes, each pixel becoming the closest color in the color table (see `Image::median_cut_palette()` and `Image::octree_palette()` to generate one).
+  ///
+  /// If `transparent_index` is given, pixels with an alpha below 128 become that index, and no other pixel does.
+  ///
+  pub fn remap(&self, palette: &ColorTable, transparent_index: Option<u8>) -> Result<IndexedImage, ErrorKind> {
+    return self.remap_dithered(palette, transparent_index, Dither::None);
+  }
+  /// Convert the image to color table indexes like `Image::remap()`, but dithered.
+  pub fn remap_dithered(&self, palette: &ColorTable, transparent_index: Option<u8>, dither: Dither) -> Result<IndexedImage, ErrorKind> {
+    let rgb_palette: Vec<[u8; 3]> = palette.to_rgb();
+    if rgb_palette.is_empty() || rgb_palette.len() > 256 {
+      return Err(ErrorKind::InvalidParameter("color table must have 1 to 256 colors".to_string()));
+    }
+    if let Some(transparent_index) = transparent_index {
+      if usize::from(transparent_index) >= rgb_palette.len() || rgb_palette.len() == 1 {
+        return Err(ErrorKind::InvalidParameter("transparent index must be in the color table, and not its only color".to_string()));
+      }
+    }
+    //for ordered dithering, how far apart the colors in the color table are: the average distance (largest difference of any channel) from each color to the closest other color
+    let opaque_colors: Vec<&[u8; 3]> = rgb_palette.iter().enumerate().filter(|(index, _)| Some(*index as u8) != transparent_index).map(|(_, rgb)| rgb).collect();
+    let mut spread: f32 = 0.0;
+    if opaque_colors.len() > 1 {
+      let distances: u32 = opaque_colors.iter().enumerate().map(|(i, rgb)| {
+        opaque_colors.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, other)| (0..3).map(|channel| u32::from(rgb[channel].abs_diff(other[channel]))).max().unwrap()).min().unwrap()
+      }).sum();
+      spread = distances as f32/opaque_colors.len() as f32;
+    }
+    //remember which color table index each color was given, so the color table is only searched once per color
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let pixels = self.dither_pixels(dither, [spread; 3], transparent_index, |rgb| {
+      let index = *cache.entry(rgb).or_insert_with(|| Image::closest_palette_index(&rgb_palette, [rgb[0], rgb[1], rgb[2], 255], transparent_index));
+      (index, rgb_palette[usize::from(index)])
+    })?;
+    return Ok(IndexedImage {
+      width: self.width,
+      height: self.height,
+      palette: palette.clone(),
+      indexes: pixels.into_iter().map(|(index, _)| index).collect(),
+      transparent_index,
+    });
+  }
+  //choose the color table index and color (with `quantize`) of every pixel, top row first, after adding the dithering error or threshold to its color.
+  //`spread` is roughly how far apart the colors that can be chosen are, for each channel, which ordered dithering needs to scale its thresholds.
+  //if `transparent_index` is given, pixels with an alpha below 128 become that index, and keep their color
+  fn dither_pixels(&self, dither: Dither, spread: [f32; 3], transparent_index: Option<u8>, mut quantize: impl FnMut([u8; 3]) -> (u8, [u8; 3])) -> Result<Vec<(u8, [u8; 3])>, ErrorKind> {
+    let width = self.width as usize;
+    let height = self.height as usize;
+    //where the error goes, as (x offset, y offset, weight), and what the weights add up to
+    let (kernel, divisor): (&[(isize, usize, f32)], f32) = match dither {
+      Dither::FloydSteinberg => (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0),
+      //only 6/8 of the error is spread
+      Dither::Atkinson => (&[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)], 8.0),
+      Dither::JarvisJudiceNinke => (&[
+        (1, 0, 7.0), (2, 0, 5.0),
+        (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+        (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+      ], 48.0),
+      Dither::Sierra => (&[
+        (1, 0, 5.0), (2, 0, 3.0),
+        (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+        (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+      ], 32.0),
+      Dither::None | Dither::Bayer(_) => (&[], 1.0),
+    };
+    //bayer matrix, each size is made from the one half its size
+    let mut bayer: Vec<Vec<u32>> = vec![vec![0]];
+    if let Dither::Bayer(size) = dither {
+      if ![2, 4, 8, 16].contains(&size) {
+        return Err(ErrorKind::InvalidParameter("Bayer matrix size must be 2, 4, 8 or 16".to_string()));
+      }
+      while bayer.len() < usize::from(size) {
+        let half = bayer.len();
+        let mut bigger: Vec<Vec<u32>> = vec![vec![0; half*2]; half*2];
+        for (y, row) in bigger.iter_mut().enumerate() {
+          for (x, value) in row.iter_mut().enumerate() {
+            let offset = match (y/half, x/half) {
+              (0, 0) => 0,
+              (0, _) => 2,
+              (_, 0) => 3,
+              _ => 1,
+            };
+            *value = 4*bayer[y % half][x % half]+offset;
+          }
+        }
+        bayer = bigger;
+      }
+    }
+    let bayer_size = bayer.len();
+    let mut errors: Vec<[f32; 3]> = vec![[0.0; 3]; if kernel.is_empty() { 0 } else { width*height }];
+    let mut pixels: Vec<(u8, [u8; 3])> = Vec::with_capacity(width*height);
+    for y in 0..height {
+      for x in 0..width {
+        let color = Image::get_color_from(&self.pixels, self.stride, x, y);
+        if let Some(transparent_index) = transparent_index {
+          if color[3] < 128 {
+            pixels.push((transparent_index, [color[0], color[1], color[2]]));
+            continue;
+          }
+        }
+        let mut wanted: [f32; 3] = [f32::from(color[0]), f32::from(color[1]), f32::from(color[2])];
+        if !kernel.is_empty() {
+          for (channel, value) in wanted.iter_mut().enumerate() {
+            *value += errors[y*width+x][channel];
+          }
+        } else if bayer_size > 1 {
+          //threshold between -0.5 and 0.5
+          let threshold = (bayer[y % bayer_size][x % bayer_size] as f32+0.5)/(bayer_size*bayer_size) as f32-0.5;
+          for (channel, value) in wanted.iter_mut().enumerate() {
+            *value += threshold*spread[channel];
+          }
+        }
+        let rounded: [u8; 3] = wanted.map(|value| value.round().clamp(0.0, 255.0) as u8);
+        let (index, chosen) = quantize(rounded);
+        pixels.push((index, chosen));
+        for (x_offset, y_offset, weight) in kernel {
+          let (error_x, error_y) = (x as isize+x_offset, y+y_offset);
+          if error_x < 0 || error_x >= width as isize || error_y >= height {
+            continue;
+          }
+          let error = &mut errors[error_y*width+error_x as usize];
+          for channel in 0..3 {
+            //the error is from the clamped color, so it does not build up past what can be shown
+            error[channel] += (f32::from(rounded[channel])-f32::from(chosen[channel]))*weight/divisor;
+          }
+        }
+      }
+    }
+    return Ok(pixels);
+  }
+  //the distinct colors of the pixels that are not fully transparent, and how many pixels have each
+  fn color_histogram(&self) -> Vec<([u8; 3], u32)> {
+    let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in self.pixels.chunks_exact(4) {
+      if pixel[3] > 0 {
+        *histogram.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+      }
+    }
+    //sorted, so the color table is the same every time
+    let mut histogram: Vec<([u8; 3], u32)> = histogram.into_iter().collect();
+    histogram.sort_unstable();
+    return histogram;
+  }
+  /// Generate a color table of at most `colors` (1 to 256) colors for the image with the median cut algorithm, which repeatedly splits the box of colors with the largest range in half.
+  /// Fully transparent pixels are ignored, so the color table is empty if every pixel is.
+  pub fn median_cut_palette(&self, colors: u16) -> Result<ColorTable, ErrorKind> {
+    if colors == 0 || colors > 256 {
+      return Err(ErrorKind::InvalidParameter("colors must be 1 to 256".to_string()));
+    }
+    //the channel with the largest range in a box, and that range
+    let widest_channel = |colors: &[([u8; 3], u32)]| -> (usize, u8) {
+      return (0..3).map(|channel| {
+        let min = colors.iter().map(|(color, _)| color[channel]).min().unwrap_or(0);
+        let max = colors.iter().map(|(color, _)| color[channel]).max().unwrap_or(0);
+        (channel, max-min)
+      }).max_by_key(|(channel, range)| (*range, std::cmp::Reverse(*channel))).unwrap();
+    };
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![self.color_histogram()];
+    while boxes.len() < usize::from(colors) {
+      //split the box with the largest range, weighted by how many pixels are in it
+      let to_split = boxes.iter().enumerate().filter(|(_, colors)| colors.len() > 1).max_by_key(|(i, colors)| {
+        let pixel_count: u64 = colors.iter().map(|(_, count)| u64::from(*count)).sum();
+        (u64::from(widest_channel(colors).1)*pixel_count, std::cmp::Reverse(*i))
+      }).map(|(i, _)| i);
+      let to_split = match to_split {
+        Some(to_split) => to_split,
+        //every box is a single color
+        None => break,
+      };
+      let mut colors = boxes.swap_remove(to_split);
+      let channel = widest_channel(&colors).0;
+      colors.sort_unstable_by_key(|(color, _)| (color[channel], *color));
+      //split at the median pixel, keeping at least one color on each side
+      let half: u64 = colors.iter().map(|(_, count)| u64::from(*count)).sum::<u64>()/2;
+      let mut pixel_count: u64 = 0;
+      let mut split: usize = 1;
+      for (i, (_, count)) in colors.iter().enumerate() {
+        pixel_count += u64::from(*count);
+        if pixel_count >= half {
+          split = i+1;
+          break;
+        }
+      }
+      let upper = colors.split_off(split.clamp(1, colors.len()-1));
+      boxes.push(colors);
+      boxes.push(upper);
+    }
+    //each color in the color table is the average color of the pixels in a box
+    let palette: Vec<[u8; 4]> = boxes.iter().filter(|colors| !colors.is_empty()).map(|colors| {
+      let pixel_count: u64 = colors.iter().map(|(_, count)| u64::from(*count)).sum();
+      let average = |channel: usize| -> u8 {
+        let sum: u64 = colors.iter().map(|(color, count)| u64::from(color[channel])*u64::from(*count)).sum();
+        ((sum+pixel_count/2)/pixel_count) as u8
+      };
+      [average(2), average(1), average(0), 0]
+    }).collect();
+    return Ok(ColorTable::RGBQUAD(palette));
+  }
+  /// Generate a color table of at most `colors` (1 to 256) colors for the image with an octree, which merges the least common similar colors (colors that share the most significant bits) until there are few enough.
+  /// Fully transparent pixels are ignored, so the color table is empty if every pixel is.
+  pub fn octree_palette(&self, colors: u16) -> Result<ColorTable, ErrorKind> {
+    if colors == 0 || colors > 256 {
+      return Err(ErrorKind::InvalidParameter("colors must be 1 to 256".to_string()));
+    }
+    //a child of 0 means there is no child, since the root (node 0) is never a child
+    struct OctreeNode {
+      children: [usize; 8],
+      //pixels in this node and all its children
+      pixel_count: u64,
+      //sum of the colors, only for leaves
+      color_sum: [u64; 3],
+    }
+    let mut nodes: Vec<OctreeNode> = vec![OctreeNode { children: [0; 8], pixel_count: 0, color_sum: [0; 3] }];
+    //the nodes with children, at each depth
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); 8];
+    levels[0].push(0);
+    let mut leaf_count: usize = 0;
+    for (color, count) in self.color_histogram() {
+      let mut node: usize = 0;
+      nodes[0].pixel_count += u64::from(count);
+      for depth in 0..8 {
+        //the child is chosen by the bit of each channel at this depth
+        let child_num = (0..3).map(|channel| usize::from((color[channel] >> (7-depth)) & 1) << (2-channel)).sum::<usize>();
+        if nodes[node].children[child_num] == 0 {
+          nodes.push(OctreeNode { children: [0; 8], pixel_count: 0, color_sum: [0; 3] });
+          nodes[node].children[child_num] = nodes.len()-1;
+          if depth < 7 {
+            levels[depth+1].push(nodes.len()-1);
+          } else {
+            leaf_count += 1;
+          }
+        }
+        node = nodes[node].children[child_num];
+        nodes[node].pixel_count += u64::from(count);
+      }
+      for (sum, value) in nodes[node].color_sum.iter_mut().zip(color) {
+        *sum += u64::from(value)*u64::from(count);
+      }
+    }
+    //merge the children of the deepest nodes into them, least pixels first, until there are few enough leaves
+    let mut depth: usize = 7;
+    let mut sorted = false;
+    while leaf_count > usize::from(colors) {
+      if levels[depth].is_empty() {
+        depth -= 1;
+        sorted = false;
+        continue;
+      }
+      if !sorted {
+        levels[depth].sort_unstable_by_key(|node| (std::cmp::Reverse(nodes[*node].pixel_count), *node));
+        sorted = true;
+      }
+      let node = levels[depth].pop().unwrap();
+      let children: Vec<usize> = nodes[node].children.iter().copied().filter(|child| *child != 0).collect();
+      for child in &children {
+        for channel in 0..3 {
+          nodes[node].color_sum[channel] += nodes[*child].color_sum[channel];
+        }
+      }
+      nodes[node].children = [0; 8];
+      leaf_count -= children.len()-1;
+    }
+    //each leaf is a color in the color table, the average of its pixels
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut stack: Vec<usize> = vec![0];
+    while let Some(node) = stack.pop() {
+      let node = &nodes[node];
+      if node.children.iter().all(|child| *child == 0) {
+        if node.pixel_count > 0 {
+          let average = |channel: usize| -> u8 { ((node.color_sum[channel]+node.pixel_count/2)/node.pixel_count) as u8 };
+          palette.push([average(2), average(1), average(0), 0]);
+        }
+        continue;
+      }
+      stack.extend(node.children.iter().rev().filter(|child| **child != 0));
+    }
+    return Ok(ColorTable::RGBQUAD(palette));
+  }
+  //compress rows of color table indexes (in the order they are stored) with BI_RLE8 or BI_RLE4
+  //https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-compression
+  fn encode_rle(rows: &[Vec<u8>], is_rle8: bool) -> Vec<u8> {
+    let mut pixel_array: Vec<u8> = Vec::new();
+    for (row_num, row) in rows.iter().enumerate() {
+      Image::encode_rle_row(row, is_rle8, &mut pixel_array);
+      if row_num < rows.len()-1 {
+        //end of line
+        pixel_array.extend([0, 0]);
+      }
+    }
+    //end of bitmap
+    pixel_array.extend([0, 1]);
+    return pixel_array;
+  }
+  //a row is split into encoded runs (a count and the index, or for RLE4 two alternating indexes) and absolute runs (0, a count of 3 or more, then the indexes padded to 2 bytes),
+  //choosing the split with the fewest bytes
+  fn encode_rle_row(row: &[u8], is_rle8: bool, pixel_array: &mut Vec<u8>) {
+    let width = row.len();
+    //the fewest bytes (then the fewest runs, on a tie) needed to encode row[i..], and the run to start it with (the length, and whether it is absolute)
+    let mut cost: Vec<(usize, usize)> = vec![(0, 0); width+1];
+    let mut choice: Vec<(usize, bool)> = vec![(0, false); width];
+    for i in (0..width).rev() {
+      //longest encoded run starting here
+      let first = row[i];
+      let second = if is_rle8 { first } else { *row.get(i+1).unwrap_or(&first) };
+      let mut max_length: usize = 1;
+      while max_length < 255 && i+max_length < width && row[i+max_length] == [first, second][max_length % 2] {
+        max_length += 1;
+      }
+      cost[i] = (usize::MAX, usize::MAX);
+      //if it is still a tie, the longest encoded run is used
+      for length in (1..=max_length).rev() {
+        let run_cost = (2+cost[i+length].0, 1+cost[i+length].1);
+        if run_cost < cost[i] {
+          cost[i] = run_cost;
+          choice[i] = (length, false);
+        }
+      }
+      for length in 3..=255.min(width-i) {
+        let byte_num: usize = if is_rle8 { length } else { length.div_ceil(2) };
+        let run_cost = (2+byte_num+byte_num % 2+cost[i+length].0, 1+cost[i+length].1);
+        if run_cost < cost[i] {
+          cost[i] = run_cost;
+          choice[i] = (length, true);
+        }
+      }
+    }
+    let mut i: usize = 0;
+    while i < width {
+      let (length, absolute) = choice[i];
+      let run = &row[i..i+length];
+      if absolute {
+        pixel_array.extend([0, length as u8]);
+        let start = pixel_array.len();
+        if is_rle8 {
+          pixel_array.extend(run);
+        } else {
+          pixel_array.extend(run.chunks(2).map(|pair| (pair[0] << 4) | pair.get(1).unwrap_or(&0)));
+        }
+        if (pixel_array.len()-start) % 2 == 1 {
+          pixel_array.push(0);
+        }
+      } else if is_rle8 {
+        pixel_array.extend([length as u8, run[0]]);
+      } else {
+        pixel_array.extend([length as u8, (run[0] << 4) | run.get(1).unwrap_or(&0)]);
+      }
+      i += length;
+    }
+  }
+  //index of the closest color (smallest distance between the rgb values) in the color table, other than the skipped index
+  fn closest_palette_index(palette: &[[u8; 3]], color: [u8; 4], skip: Option<u8>) -> u8 {
+    let distance = |entry: &[u8; 3]| -> u32 {
+      (0..3).map(|i| (i32::from(entry[i])-i32::from(color[i])).pow(2) as u32).sum()
     };
-    for i in 0..bmp2_height {
-      for j in 0..bmp2_width {
-        let new_pixel = [x+j as u16, y+i as u16];
-        let old_color = self.get_color_of_px_efficient(i as usize, j as usize, &dib_header, &pixel_data).unwrap();
-        let new_color = bmp2.get_color_of_px_efficient(i as usize, j as usize, &bmp2_dib_header, &bmp2_pixel_data).unwrap();
+    return palette.iter().enumerate().filter(|(index, _)| Some(*index as u8) != skip).min_by_key(|(_, entry)| distance(entry)).map(|(index, _)| index as u8).unwrap_or(0);
+  }
+  //color table used when encoding to 1, 4 or 8 bit, and there is no other color table to use
+  fn default_palette(bitcount: u16) -> Vec<[u8; 3]> {
+    match bitcount {
+      1 => return vec![[0, 0, 0], [255, 255, 255]],
+      //the 16 windows/vga colors
+      4 => return vec![
+        [0, 0, 0], [128, 0, 0], [0, 128, 0], [128, 128, 0], [0, 0, 128], [128, 0, 128], [0, 128, 128], [192, 192, 192],
+        [128, 128, 128], [255, 0, 0], [0, 255, 0], [255, 255, 0], [0, 0, 255], [255, 0, 255], [0, 255, 255], [255, 255, 255],
+      ],
+      _ => {
+        //6x6x6 color cube, then 40 grays between black and white
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        for r in 0..6 {
+          for g in 0..6 {
+            for b in 0..6 {
+              palette.push([r*51, g*51, b*51]);
+            }
+          }
+        }
+        for i in 1..=40u32 {
+          let gray = (i*255/41) as u8;
+          palette.push([gray, gray, gray]);
+        }
+        return palette;
+      },
+    }
+  }
+  /// Get the file header the image was decoded from.
+  pub fn get_header(&self) -> &BITMAPFILEHEADER {
+    &self.header
+  }
+  /// Get the DIB header the image was decoded from.
+  pub fn get_dib_header(&self) -> &DIBHEADER {
+    &self.dib_header
+  }
+  /// Get the width of the image in pixels.
+  pub fn get_width(&self) -> u32 {
+    self.width
+  }
+  /// Get the height of the image in pixels. Unlike the DIB header's height, this is never negative.
+  pub fn get_height(&self) -> u32 {
+    self.height
+  }
+  /// Get the length of a row in bytes.
+  pub fn get_stride(&self) -> usize {
+    self.stride
+  }
+  /// Get the RGBA pixel buffer.
+  pub fn get_pixels(&self) -> &[u8] {
+    &self.pixels
+  }
+  /// Get the RGBA pixel buffer, to change pixels directly.
+  pub fn get_pixels_mut(&mut self) -> &mut [u8] {
+    self.mark_all_dirty();
+    &mut self.pixels
+  }
+  //read a color out of a pixel buffer, used to read the old pixels while changing the image
+  fn get_color_from(pixels: &[u8], stride: usize, x: usize, y: usize) -> [u8; 4] {
+    let start = y*stride+x*4;
+    return [pixels[start], pixels[start+1], pixels[start+2], pixels[start+3]];
+  }
+  /// Given x and y coordinates (`(0, 0)` is the upper left corner of the image), get the RGBA color at that location.
+  pub fn get_color_of_pixel(&self, x: usize, y: usize) -> Result<[u8; 4], ErrorKind> {
+    if x >= self.width as usize || y >= self.height as usize {
+      return Err(ErrorKind::OutOfBounds);
+    }
+    return Ok(Image::get_color_from(&self.pixels, self.stride, x, y));
+  }
+  /// Given x and y coordinates (`(0, 0)` is the upper left corner of the image), change the RGBA color at that location.
+  pub fn change_color_of_pixel(&mut self, x: u16, y: u16, new_color: [u8; 4]) -> Result<(), ErrorKind> {
+    if u32::from(x) >= self.width || u32::from(y) >= self.height {
+      return Err(ErrorKind::OutOfBounds);
+    }
+    let start = usize::from(y)*self.stride+usize::from(x)*4;
+    self.pixels[start..start+4].copy_from_slice(&new_color);
+    self.mark_dirty(usize::from(y)*self.width as usize+usize::from(x));
+    return Ok(());
+  }
+  /// Batch change color of pixels. Pass in a vector of coordinates, and a new color.
+  pub fn change_color_of_pixels(&mut self, pixels: Vec<[u16; 2]>, new_color: [u8; 4]) -> Result<(), ErrorKind> {
+    for pixel in pixels {
+      self.change_color_of_pixel(pixel[0], pixel[1], new_color)?;
+    }
+    return Ok(());
+  }
+  //image editing functions
+  /// Draw another image on this image, with upper left corner of the drawn on image at the given x and y coordinates.
+  pub fn draw_image(&mut self, x: u16, y: u16, image2: &Image) -> Result<(), ErrorKind> {
+    for i in 0..image2.height as u16 {
+      for j in 0..image2.width as u16 {
+        let new_pixel = [x+j, y+i];
+        let old_color = self.get_color_of_pixel(new_pixel[0] as usize, new_pixel[1] as usize)?;
+        let new_color = Image::get_color_from(&image2.pixels, image2.stride, j as usize, i as usize);
         if old_color[3] == 255 && new_color[3] == 255 {
           self.change_color_of_pixel(new_pixel[0], new_pixel[1], new_color)?;
         } else {
@@ -1715,332 +3838,525 @@ impl BMP {
   }
   /// Change opacity of the whole image.
   pub fn change_opacity(&mut self, opacity: u8) -> Result<(), ErrorKind> {
-    //todo: option to not change opacity if the alpha channel is 0, and color is 255, 255, 255 or 0, 0, 0
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let height: u16 = dib_header.height.abs() as u16;
-    let width: u16 = dib_header.width as u16;
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    //change every pixel
-    for y in 0..height {
-      for x in 0..width {
-        //get pixel color
-        let old_color = self.get_color_of_px_efficient(x as usize, y as usize, &dib_header, &pixel_data);
-        let old_color: [u8; 4] = match old_color {
-          Ok(returned_color) => returned_color,
-          Err(e) => return Err(e),
-        };
-        let new_fill: [u8; 4] = [old_color[0], old_color[1], old_color[2], opacity];
-        //change pixel color
-        self.change_color_of_pixel(x, y, new_fill)?;
-      }
+    self.mark_all_dirty();
+    for pixel in self.pixels.chunks_exact_mut(4) {
+      pixel[3] = opacity;
     }
     return Ok(());
   }
   /// Invert the colors of the image.
   pub fn invert(&mut self, invert_alpha: Option<bool>) -> Result<(), ErrorKind> {
-    //invert colors of image
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let height: u16 = dib_header.height.abs() as u16;
-    let width: u16 = dib_header.width as u16;
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let header = self.get_header();
-    //change every pixel
-    for y in 0..height {
-      for x in 0..width {
-        //get pixel color
-        //although we are actively changing the pixel data, because we query the color before it is changed, we can use the old one for efficiency
-        let old_color = self.get_color_of_px_efficient(x as usize, y as usize, &dib_header, &pixel_data);
-        let old_color: [u8; 4] = match old_color {
-          Ok(returned_color) => returned_color,
-          Err(e) => return Err(e),
-        };
-        let mut new_fill: [u8; 4] = [255 - old_color[0], 255 - old_color[1], 255 - old_color[2], old_color[3]];
-        if invert_alpha.is_some() {
-          let invert_alpha_unwrapped = invert_alpha.unwrap();
-          if invert_alpha_unwrapped {
-            new_fill = [255 - old_color[0], 255 - old_color[1], 255 - old_color[2], 255 - old_color[3]];
-          }
-        }
-        //change pixel color
-        self.change_color_of_pixel_efficient(x, y, new_fill, &dib_header, &header)?;
+    let invert_alpha: bool = invert_alpha.unwrap_or(false);
+    self.mark_all_dirty();
+    for pixel in self.pixels.chunks_exact_mut(4) {
+      pixel[0] = 255-pixel[0];
+      pixel[1] = 255-pixel[1];
+      pixel[2] = 255-pixel[2];
+      if invert_alpha {
+        pixel[3] = 255-pixel[3];
       }
     }
     return Ok(());
   }
   //translate, rotate
-  /// Translate the image in the horizontal and/or vertical directions.
+  /// Translate the image in the horizontal and/or vertical directions. Uncovered pixels become transparent white.
   pub fn translate(&mut self, x: i16, y: i16) -> Result<(), ErrorKind> {
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    //copy self, to get original colors, iterate through and put new colors
-    let og_bmp: BMP = self.clone();
-    let height = dib_header.height.abs() as i16;
-    let width = dib_header.width as i16;
-    let og_pixel_data = self.get_pixel_data();
-    let og_pixel_data = match og_pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
+    //copy pixels, to get original colors, iterate through and put new colors
+    let og_pixels: Vec<u8> = self.pixels.clone();
+    let height = self.height as i32;
+    let width = self.width as i32;
     //empty self
-    self.contents = BMP::new(dib_header.height, dib_header.width, Some([255, 255, 255, 0])).contents;
+    self.mark_all_dirty();
+    for pixel in self.pixels.chunks_exact_mut(4) {
+      pixel.copy_from_slice(&[255, 255, 255, 0]);
+    }
     for row in 0..height {
       for column in 0..width {
-        let temp_x: i16 = column+x;
-        let temp_y: i16 = row+y;
+        let temp_x: i32 = column+i32::from(x);
+        let temp_y: i32 = row+i32::from(y);
         if temp_y < 0 || temp_y >= height || temp_x < 0 || temp_x >= width {
           continue;
         }
-        let color = og_bmp.get_color_of_px_efficient(column as usize, row as usize, &dib_header, &og_pixel_data).unwrap();
+        let color = Image::get_color_from(&og_pixels, self.stride, column as usize, row as usize);
         self.change_color_of_pixel(temp_x as u16, temp_y as u16, color)?;
       }
     }
     return Ok(());
   }
-  /// Rotate the image. If no center coordinate provided, defaults to `(0, 0)` (top left corner of image). 
+  /// Rotate the image clockwise. If no center coordinate provided, defaults to `(0, 0)` (top left corner of image).
+  /// Each pixel becomes the pixel that rotates onto it, and uncovered pixels become transparent white. The size stays the same, see `Image::rotate_with_options()` for more.
   pub fn rotate(&mut self, deg: f64, center_option: Option<[u16; 2]>) -> Result<(), ErrorKind> {
-    let center: [u16; 2];
-    if center_option.is_none() {
-      center = [0, 0];
+    let center: [u16; 2] = center_option.unwrap_or([0, 0]);
+    let options = RotateOptions {
+      //rotate around the center of the pixel
+      center: Some([f64::from(center[0])+0.5, f64::from(center[1])+0.5]),
+      filter: ResizeFilter::Nearest,
+      expand: false,
+      background: [255, 255, 255, 0],
+    };
+    self.pixels = self.rotate_with_options(deg, &options)?.pixels;
+    self.mark_all_dirty();
+    return Ok(());
+  }
+  /// Get a copy of the image rotated clockwise by the given degrees, see `RotateOptions`.
+  ///
+  /// Every new pixel is interpolated from where it rotates back to in the image (inverse mapping), so there are no gaps. Like `Image::resize()`, colors are weighted by their alpha while interpolating,
+  /// and the background color is blended into the edges of the image.
+  ///
+  pub fn rotate_with_options(&self, deg: f64, options: &RotateOptions) -> Result<Image, ErrorKind> {
+    let rad: f64 = BMP::deg_to_rad(deg);
+    let (width, height) = (f64::from(self.width), f64::from(self.height));
+    let center: [f64; 2] = options.center.unwrap_or([width/2.0, height/2.0]);
+    //where the top left corner of the new canvas is, in the coordinates of the image
+    let (origin, new_width, new_height): ([f64; 2], u32, u32) = if options.expand {
+      let corners = [[0.0, 0.0], [width, 0.0], [0.0, height], [width, height]].map(|corner| BMP::rotate_point(rad, corner, center));
+      let min = |axis: usize| -> f64 { corners.iter().map(|corner| corner[axis]).fold(f64::MAX, f64::min) };
+      let max = |axis: usize| -> f64 { corners.iter().map(|corner| corner[axis]).fold(f64::MIN, f64::max) };
+      //round the size up (ignoring floating point error), and center the rotated image in it
+      let size = |axis: usize| -> u32 { ((max(axis)-min(axis)-1e-6).ceil() as u32).max(1) };
+      let (new_width, new_height) = (size(0), size(1));
+      let origin = [min(0)-(f64::from(new_width)-max(0)+min(0))/2.0, min(1)-(f64::from(new_height)-max(1)+min(1))/2.0];
+      (origin, new_width, new_height)
     } else {
-      center = center_option.unwrap();
+      ([0.0, 0.0], self.width, self.height)
+    };
+    //move the new canvas back to the origin, and rotate back
+    let inverse = Transform::translation(origin[0], origin[1]).then(&Transform::rotation(-deg, center));
+    return self.warp(&inverse, new_width, new_height, options.filter, EdgeMode::Transparent, options.background);
+  }
+  /// Get a copy of the image with an affine or perspective transform applied, see `Transform` and `TransformOptions`.
+  ///
+  /// Like `Image::rotate_with_options()`, every new pixel is interpolated from where the inverse of the transform maps it back to in the image.
+  /// Parts of the new image that are not mapped from the image are sampled according to `TransformOptions::edge`.
+  ///
+  pub fn transform(&self, transform: &Transform, options: &TransformOptions) -> Result<Image, ErrorKind> {
+    let [width, height] = options.size.unwrap_or([self.width, self.height]);
+    return self.warp(&transform.inverse()?, width, height, options.filter, options.edge, [0, 0, 0, 0]);
+  }
+  /// Get a copy of the image with the four source corners moved to the four destination corners, and everything between them following in perspective, see `Transform::perspective_from_points()`.
+  ///
+  /// To straighten a photographed document, give its corners as the source, and the corners of the new image as the destination:
+  ///
+  /// ```rust
+  /// use bmp_rust::bmp::{BMP, TransformOptions};
+  ///
+  /// let photo = BMP::new(60, 80, Some([255, 255, 255, 255])).to_image().unwrap();
+  /// let corners = [[12.0, 5.0], [70.0, 9.0], [75.0, 55.0], [4.0, 50.0]];
+  /// let page = [[0.0, 0.0], [60.0, 0.0], [60.0, 40.0], [0.0, 40.0]];
+  /// let straightened = photo.warp_perspective(corners, page, &TransformOptions { size: Some([60, 40]), ..TransformOptions::default() }).unwrap();
+  /// ```
+  ///
+  pub fn warp_perspective(&self, source: [[f64; 2]; 4], destination: [[f64; 2]; 4], options: &TransformOptions) -> Result<Image, ErrorKind> {
+    return self.transform(&Transform::perspective_from_points(source, destination)?, options);
+  }
+  //a new image where each pixel is interpolated from where `inverse` maps its center back to in the image, see `Image::sample()`
+  fn warp(&self, inverse: &Transform, width: u32, height: u32, filter: ResizeFilter, edge: EdgeMode, background: [u8; 4]) -> Result<Image, ErrorKind> {
+    let mut warped = Image::blank(width, height)?;
+    let premultiplied: Vec<[f32; 4]> = self.pixels.chunks_exact(4).map(|rgba| Image::premultiply([rgba[0], rgba[1], rgba[2], rgba[3]])).collect();
+    let outside = Image::premultiply(background);
+    let stride = warped.stride;
+    for y in 0..height as usize {
+      for (x, pixel) in warped.pixels[y*stride..(y+1)*stride].chunks_exact_mut(4).enumerate() {
+        let rgba = match inverse.apply([x as f64+0.5, y as f64+0.5]) {
+          Some([source_x, source_y]) => Image::unpremultiply(self.sample(&premultiplied, source_x, source_y, filter, edge, outside)),
+          None => background,
+        };
+        //fully transparent pixels keep the color of a transparent background
+        pixel.copy_from_slice(if rgba[3] == 0 && background[3] == 0 { &background } else { &rgba });
+      }
     }
-    //change deg ro radians
-    let rad: f64 = BMP::deg_to_rad(deg);
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
+    return Ok(warped);
+  }
+  //interpolate the (premultiplied) color at `(x, y)`, where pixel centers are at .5, with pixels outside of the image found by the edge mode (`EdgeMode::Transparent` uses the `outside` color)
+  fn sample(&self, premultiplied: &[[f32; 4]], x: f64, y: f64, filter: ResizeFilter, edge: EdgeMode, outside: [f32; 4]) -> [f32; 4] {
+    //which pixels along one axis are used, and their weights
+    let weights = |position: f64| -> Vec<(i64, f32)> {
+      if filter == ResizeFilter::Nearest {
+        return vec![(position.floor() as i64, 1.0)];
+      }
+      let center = position-0.5;
+      let support = filter.support();
+      let mut pixel_weights: Vec<(i64, f32)> = Vec::new();
+      let mut total: f64 = 0.0;
+      for i in (center-support).ceil() as i64..=(center+support).floor() as i64 {
+        let weight = filter.weight(i as f64-center);
+        if weight == 0.0 {
+          continue;
+        }
+        pixel_weights.push((i, weight as f32));
+        total += weight;
+      }
+      for (_, weight) in pixel_weights.iter_mut() {
+        *weight /= total as f32;
+      }
+      return pixel_weights;
     };
-    let og_bmp: BMP = self.clone();
-    let height = dib_header.height.abs() as u16;
-    let width = dib_header.width as u16;
-    let og_pixel_data = self.get_pixel_data();
-    let og_pixel_data = match og_pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
+    //the pixel along one axis that is used for a position, if any
+    let index = |i: i64, length: i64| -> Option<i64> {
+      match edge {
+        EdgeMode::Transparent => return if i < 0 || i >= length { None } else { Some(i) },
+        EdgeMode::Clamp => return Some(i.clamp(0, length-1)),
+        EdgeMode::Wrap => return Some(i.rem_euclid(length)),
+        EdgeMode::Mirror => {
+          let i = i.rem_euclid(length*2);
+          return Some(if i >= length { length*2-i-1 } else { i });
+        },
+      }
     };
-    self.contents = BMP::new(dib_header.height, dib_header.width, Some([255, 255, 255, 0])).contents;
-    for row in 0..height {
-      for column in 0..width {
-        let (x2, y2) = BMP::rotate_point(rad, [column, row], center);
-        //check to make sure coords are in bounds
-        if y2 < 0 || y2 >= i32::from(height) || x2 < 0 || x2 >= i32::from(width) {
+    let (width, height) = (i64::from(self.width), i64::from(self.height));
+    let mut color: [f32; 4] = [0.0; 4];
+    for (source_y, weight_y) in weights(y) {
+      for (source_x, weight_x) in weights(x) {
+        let pixel = match (index(source_x, width), index(source_y, height)) {
+          (Some(source_x), Some(source_y)) => premultiplied[(source_y*width+source_x) as usize],
+          _ => outside,
+        };
+        for (channel, value) in color.iter_mut().enumerate() {
+          *value += pixel[channel]*weight_x*weight_y;
+        }
+      }
+    }
+    return color;
+  }
+  //colors weighted by their alpha, so a pixel's color counts as much as it is opaque when interpolating
+  fn premultiply(rgba: [u8; 4]) -> [f32; 4] {
+    let alpha = f32::from(rgba[3])/255.0;
+    return [f32::from(rgba[0])*alpha, f32::from(rgba[1])*alpha, f32::from(rgba[2])*alpha, f32::from(rgba[3])];
+  }
+  fn unpremultiply(color: [f32; 4]) -> [u8; 4] {
+    //bicubic and lanczos filters can overshoot, so clamp
+    let alpha = color[3].clamp(0.0, 255.0);
+    if alpha < 0.5 {
+      return [0, 0, 0, 0];
+    }
+    let unpremultiply = |value: f32| -> u8 { (value*255.0/alpha).round().clamp(0.0, 255.0) as u8 };
+    return [unpremultiply(color[0]), unpremultiply(color[1]), unpremultiply(color[2]), alpha.round() as u8];
+  }
+  //resizing
+  //a new, fully transparent image of the given size, to draw resized or transformed pixels into
+  fn blank(width: u32, height: u32) -> Result<Image, ErrorKind> {
+    BMP::check_new_size(width, height)?;
+    let mut blank = BMP::new(height as i32, width, Some([0, 0, 0, 0])).to_image()?;
+    blank.mark_all_dirty();
+    return Ok(blank);
+  }
+  /// Get a copy of the image resized to the given width and height, see `ResizeFilter`.
+  /// Colors are weighted by their alpha (premultiplied) while interpolating, so transparent pixels do not bleed their color into the edges of opaque ones.
+  pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Result<Image, ErrorKind> {
+    return self.resample([0.0, 0.0, f64::from(self.width), f64::from(self.height)], width, height, filter);
+  }
+  /// Get a copy of the image resized to fit inside the given width and height, keeping the aspect ratio. One side will be the given size, and the other the same or smaller.
+  pub fn scale_to_fit(&self, max_width: u32, max_height: u32, filter: ResizeFilter) -> Result<Image, ErrorKind> {
+    if max_width == 0 || max_height == 0 {
+      return Err(ErrorKind::InvalidParameter("width and height must be more than 0".to_string()));
+    }
+    let scale = (f64::from(max_width)/f64::from(self.width)).min(f64::from(max_height)/f64::from(self.height));
+    let width = ((f64::from(self.width)*scale).round() as u32).clamp(1, max_width);
+    let height = ((f64::from(self.height)*scale).round() as u32).clamp(1, max_height);
+    return self.resize(width, height, filter);
+  }
+  /// Get a copy of the image resized to exactly the given width and height, keeping the aspect ratio. The image is scaled to cover the whole size, and the parts that do not fit are cut off equally from both sides.
+  pub fn scale_to_fill(&self, width: u32, height: u32, filter: ResizeFilter) -> Result<Image, ErrorKind> {
+    if width == 0 || height == 0 {
+      return Err(ErrorKind::InvalidParameter("width and height must be more than 0".to_string()));
+    }
+    let scale = (f64::from(width)/f64::from(self.width)).max(f64::from(height)/f64::from(self.height));
+    //the part of the image that is kept, centered
+    let (kept_width, kept_height) = (f64::from(width)/scale, f64::from(height)/scale);
+    let source = [(f64::from(self.width)-kept_width)/2.0, (f64::from(self.height)-kept_height)/2.0, kept_width, kept_height];
+    return self.resample(source, width, height, filter);
+  }
+  //resize the `[x, y, width, height]` part of the image to the given size, a row then a column at a time
+  fn resample(&self, source: [f64; 4], width: u32, height: u32, filter: ResizeFilter) -> Result<Image, ErrorKind> {
+    let mut resized = Image::blank(width, height)?;
+    //which source pixels each new pixel is made of (clamped to the edges), and their weights
+    let weights = |start: f64, length: f64, source_length: usize, new_length: usize| -> Vec<Vec<(usize, f32)>> {
+      let scale = length/new_length as f64;
+      //when shrinking, the filter is stretched so every source pixel is used
+      let filter_scale = scale.max(1.0);
+      let support = filter.support()*filter_scale;
+      return (0..new_length).map(|i| {
+        let center = start+(i as f64+0.5)*scale;
+        if filter == ResizeFilter::Nearest {
+          return vec![((center.floor().max(0.0) as usize).min(source_length-1), 1.0)];
+        }
+        let mut pixel_weights: Vec<(usize, f32)> = Vec::new();
+        let mut total: f64 = 0.0;
+        for j in (center-support).floor() as i64..=(center+support).ceil() as i64 {
+          let weight = filter.weight((j as f64+0.5-center)/filter_scale);
+          if weight == 0.0 {
+            continue;
+          }
+          pixel_weights.push((j.clamp(0, source_length as i64-1) as usize, weight as f32));
+          total += weight;
+        }
+        //weights add up to 1, so flat colors stay the same
+        for (_, weight) in pixel_weights.iter_mut() {
+          *weight /= total as f32;
+        }
+        pixel_weights
+      }).collect();
+    };
+    let (source_width, source_height) = (self.width as usize, self.height as usize);
+    let (new_width, new_height) = (width as usize, height as usize);
+    let horizontal = weights(source[0], source[2], source_width, new_width);
+    let vertical = weights(source[1], source[3], source_height, new_height);
+    //premultiplied alpha, so a pixel's color counts as much as it is opaque
+    let premultiplied: Vec<[f32; 4]> = self.pixels.chunks_exact(4).map(|rgba| Image::premultiply([rgba[0], rgba[1], rgba[2], rgba[3]])).collect();
+    //the rows of the source that are used, resized horizontally (unused rows are left empty)
+    let mut rows: Vec<Vec<[f32; 4]>> = vec![Vec::new(); source_height];
+    for pixel_weights in &vertical {
+      for (y, _) in pixel_weights {
+        if !rows[*y].is_empty() {
           continue;
         }
-        //round, then color? make sure there are no gaps
-        let color = og_bmp.get_color_of_px_efficient(column as usize, row as usize, &dib_header, &og_pixel_data).unwrap();
-        //also color floor/ceil?
-        self.change_color_of_pixel(x2 as u16, y2 as u16, color)?;
+        rows[*y] = horizontal.iter().map(|pixel_weights| {
+          let mut color: [f32; 4] = [0.0; 4];
+          for (x, weight) in pixel_weights {
+            for (channel, value) in color.iter_mut().enumerate() {
+              *value += premultiplied[y*source_width+x][channel]*weight;
+            }
+          }
+          color
+        }).collect();
+      }
+    }
+    let stride = resized.stride;
+    for (y, pixel_weights) in vertical.iter().enumerate() {
+      for (x, pixel) in resized.pixels[y*stride..y*stride+new_width*4].chunks_exact_mut(4).enumerate() {
+        let mut color: [f32; 4] = [0.0; 4];
+        for (source_y, weight) in pixel_weights {
+          for (channel, value) in color.iter_mut().enumerate() {
+            *value += rows[*source_y][x][channel]*weight;
+          }
+        }
+        pixel.copy_from_slice(&Image::unpremultiply(color));
       }
-      //fill in holes
     }
+    return Ok(resized);
+  }
+  //cropping and canvas
+  /// Get the `width` by `height` part of the image with its upper left corner at `(x, y)`.
+  ///
+  /// Like the other canvas functions, the new image is in the same format as the image it was decoded from (with the width, height and sizes in the headers changed),
+  /// except for compressed images, which become 32 bit like `BMP::new()` creates.
+  ///
+  pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Image, ErrorKind> {
+    if u64::from(x)+u64::from(width) > u64::from(self.width) || u64::from(y)+u64::from(height) > u64::from(self.height) {
+      return Err(ErrorKind::OutOfBounds);
+    }
+    return self.place_on_canvas(width, height, -i64::from(x), -i64::from(y), [0, 0, 0, 0]);
+  }
+  /// Get a copy of the image on a canvas of a different size, placed by the anchor. New area is filled with the fill color, and parts that do not fit are cut off.
+  pub fn resize_canvas(&self, width: u32, height: u32, anchor: Anchor, fill: [u8; 4]) -> Result<Image, ErrorKind> {
+    //how far across the extra (or missing) space the image goes, 0 is left or top, 2 is right or bottom
+    let (horizontal, vertical): (i64, i64) = match anchor {
+      Anchor::TopLeft => (0, 0),
+      Anchor::Top => (1, 0),
+      Anchor::TopRight => (2, 0),
+      Anchor::Left => (0, 1),
+      Anchor::Center => (1, 1),
+      Anchor::Right => (2, 1),
+      Anchor::BottomLeft => (0, 2),
+      Anchor::Bottom => (1, 2),
+      Anchor::BottomRight => (2, 2),
+    };
+    let x = (i64::from(width)-i64::from(self.width))*horizontal/2;
+    let y = (i64::from(height)-i64::from(self.height))*vertical/2;
+    return self.place_on_canvas(width, height, x, y, fill);
+  }
+  /// Get a copy of the image with borders of the given widths (in pixels) added to each side, filled with the fill color.
+  pub fn pad(&self, top: u32, right: u32, bottom: u32, left: u32, fill: [u8; 4]) -> Result<Image, ErrorKind> {
+    let width = self.width.checked_add(left).and_then(|width| width.checked_add(right));
+    let height = self.height.checked_add(top).and_then(|height| height.checked_add(bottom));
+    match (width, height) {
+      (Some(width), Some(height)) => return self.place_on_canvas(width, height, i64::from(left), i64::from(top), fill),
+      _ => return Err(ErrorKind::InvalidParameter("padded image is too big".to_string())),
+    }
+  }
+  //flips and right angle rotations
+  /// Get a copy of the image mirrored left to right.
+  pub fn flip_horizontal(&self) -> Result<Image, ErrorKind> {
+    return self.flip_pixels(true, false);
+  }
+  /// Get a copy of the image mirrored top to bottom.
+  pub fn flip_vertical(&self) -> Result<Image, ErrorKind> {
+    return self.flip_pixels(false, true);
+  }
+  /// Get a copy of the image rotated 90 degrees clockwise, so the width and height are swapped.
+  pub fn rotate90(&self) -> Result<Image, ErrorKind> {
+    let height = self.height;
+    let mut rotated = self.rearrange_pixels(height, self.width, |x, y| (y, height-x-1))?;
+    rotated.swap_resolution()?;
+    return Ok(rotated);
+  }
+  /// Get a copy of the image rotated 180 degrees.
+  pub fn rotate180(&self) -> Result<Image, ErrorKind> {
+    return self.flip_pixels(true, true);
+  }
+  /// Get a copy of the image rotated 270 degrees clockwise (90 degrees counterclockwise), so the width and height are swapped.
+  pub fn rotate270(&self) -> Result<Image, ErrorKind> {
+    let width = self.width;
+    let mut rotated = self.rearrange_pixels(self.height, width, |x, y| (width-y-1, x))?;
+    rotated.swap_resolution()?;
+    return Ok(rotated);
+  }
+  //the horizontal and vertical resolution of the format the image is written in are swapped too
+  fn swap_resolution(&mut self) -> Result<(), ErrorKind> {
+    if self.dib_header.XPelsPerMeter.is_none() {
+      return Ok(());
+    }
+    std::mem::swap(&mut self.dib_header.XPelsPerMeter, &mut self.dib_header.YPelsPerMeter);
+    let dib_header_bytes: Vec<u8> = self.dib_header.to_bytes()?;
+    self.head[HEADER_OFFSET..HEADER_OFFSET+dib_header_bytes.len()].copy_from_slice(&dib_header_bytes);
     return Ok(());
   }
+  //an empty image of a different size in the same format, see `BMP::new_canvas()`. all of its pixels are drawn, so they are all marked as changed
+  fn new_canvas(&self, width: u32, height: u32) -> Result<Image, ErrorKind> {
+    let tail_start = self.head.len()+match &self.stored_pixels {
+      Some(stored_pixels) => stored_pixels.len(),
+      None => (u32::from(self.dib_header.bitcount)*self.width).div_ceil(32) as usize*4*self.height as usize,
+    };
+    let mut canvas = BMP::assemble_canvas(&self.head, &self.tail, tail_start, self.dib_header.clone(), width, height, self.palette_policy)?.to_image()?;
+    canvas.mark_all_dirty();
+    return Ok(canvas);
+  }
+  //copy the pixels into a new canvas (see `BMP::new_canvas()`) of the given size, `source_of` gives the coordinates of the pixel each new pixel is copied from
+  fn rearrange_pixels(&self, width: u32, height: u32, source_of: impl Fn(u32, u32) -> (u32, u32)) -> Result<Image, ErrorKind> {
+    let mut canvas = self.new_canvas(width, height)?;
+    for y in 0..height {
+      for x in 0..width {
+        let (source_x, source_y) = source_of(x, y);
+        let source_start = source_y as usize*self.stride+source_x as usize*4;
+        let start = y as usize*canvas.stride+x as usize*4;
+        canvas.pixels[start..start+4].copy_from_slice(&self.pixels[source_start..source_start+4]);
+      }
+    }
+    return Ok(canvas);
+  }
+  //copy the pixels into a new canvas of the same size, mirrored left to right and/or top to bottom, a whole row at a time
+  fn flip_pixels(&self, horizontal: bool, vertical: bool) -> Result<Image, ErrorKind> {
+    let mut canvas = self.new_canvas(self.width, self.height)?;
+    let height = self.height as usize;
+    for (y, row) in canvas.pixels.chunks_exact_mut(self.stride).enumerate() {
+      let source_y = if vertical { height-y-1 } else { y };
+      let source_row = &self.pixels[source_y*self.stride..(source_y+1)*self.stride];
+      if horizontal {
+        for (pixel, source_pixel) in row.chunks_exact_mut(4).zip(source_row.chunks_exact(4).rev()) {
+          pixel.copy_from_slice(source_pixel);
+        }
+      } else {
+        row.copy_from_slice(source_row);
+      }
+    }
+    return Ok(canvas);
+  }
+  //copy the image onto a new canvas (see `BMP::new_canvas()`) of the given size, with its upper left corner at `(x, y)`
+  fn place_on_canvas(&self, width: u32, height: u32, x: i64, y: i64, fill: [u8; 4]) -> Result<Image, ErrorKind> {
+    let mut canvas = self.new_canvas(width, height)?;
+    for pixel in canvas.pixels.chunks_exact_mut(4) {
+      pixel.copy_from_slice(&fill);
+    }
+    //the rows and columns of the canvas that the image covers
+    let left = x.clamp(0, i64::from(width)) as usize;
+    let right = (x+i64::from(self.width)).clamp(0, i64::from(width)) as usize;
+    let top = y.clamp(0, i64::from(height)) as usize;
+    let bottom = (y+i64::from(self.height)).clamp(0, i64::from(height)) as usize;
+    for canvas_y in top..bottom {
+      let source_y = (canvas_y as i64-y) as usize;
+      let source_start = source_y*self.stride+(left as i64-x) as usize*4;
+      let canvas_start = canvas_y*canvas.stride+left*4;
+      let length = (right-left)*4;
+      canvas.pixels[canvas_start..canvas_start+length].copy_from_slice(&self.pixels[source_start..source_start+length]);
+    }
+    return Ok(canvas);
+  }
   //blurs
   pub fn separable_blur(&mut self, radius: u8, gen_distribution: impl Fn(u8, u8) -> u16, horizontal: Option<bool>, vertical: Option<bool>) -> Result<(), ErrorKind> {
     //a separable blur can be separated into two passes, horizontal and vertical, instead of applying the whole square, meaning much more efficient
     //gen_distribution is a closure that, based on radius and 1d distance from center, spits out a weighting
     //all the weightings will be added up, and the color of that pixel will be multiplied by weighting/all weightings,
     //and added together with all other pixels in row/column, and be the new pixel color
-    let mut do_horizontal: bool = true;
-    let mut do_vertical: bool = true;
-    if horizontal.is_some() {
-      do_horizontal = horizontal.unwrap();
-    }
-    if vertical.is_some() {
-      do_vertical = vertical.unwrap();
-    }
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let height: u16 = dib_header.height.abs() as u16;
-    let width: u16 = dib_header.width as u16;
+    let do_horizontal: bool = horizontal.unwrap_or(true);
+    let do_vertical: bool = vertical.unwrap_or(true);
+    let height: u16 = self.height as u16;
+    let width: u16 = self.width as u16;
     if radius > 16 || radius == 0 {
       return Err(ErrorKind::InvalidParameter("Blur/filter radius is invalid since bigger than 16 or is 0".to_string()));
     }
-    //change every pixel
-    //vertical blur
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
+    //calculate weights
+    let mut weights: Vec<u16> = Vec::new();
+    for l in 0..radius {
+      //calculate weights to the left
+      weights.push(gen_distribution(radius, radius-l));
+    }
+    weights.push(gen_distribution(radius, 0));
+    for r in 0..radius {
+      //calculate weights to the right
+      weights.push(gen_distribution(radius, r+1));
+    }
+    let total_weight: u16 = weights.iter().sum();
+    //each pass reads the pixels from before the pass
+    //[dx, dy] is the direction of the pass
+    let mut passes: Vec<[u16; 2]> = Vec::new();
     if do_horizontal {
-      //calculate weights
-      let mut weights: Vec<u16> = Vec::new();
-      for l in 0..radius {
-        //calculate weights to the left
-        weights.push(gen_distribution(radius, radius-l));
-      }
-      weights.push(gen_distribution(radius, 0));
-      for r in 0..radius {
-        //calculate weights to the right
-        weights.push(gen_distribution(radius, r+1));
-      }
-      for y in 0..height {
-        for x in 0..width {
-          let mut total_weight: u16 = 0;
-          for w in &weights {
-            total_weight += w;
-          }
-          let mut total_weight_h = total_weight;
-          //check if out of bounds, calculate horizontal weights
-          for hl_b in 1..radius+1 {
-            if x < u16::from(hl_b) {
-              //out of bounds
-              total_weight_h -= weights[(radius-hl_b) as usize];
-            }
-          }
-          for hr_b in 1..radius+1 {
-            if x+u16::from(hr_b) >= width {
-              //out of bounds
-              total_weight_h -= weights[(radius+hr_b) as usize];
-            }
-          }
-          //get pixel values, multiply and add them together
-          let mut new_r_h: f64 = 0.0;
-          let mut new_g_h: f64 = 0.0;
-          let mut new_b_h: f64 = 0.0;
-          let mut new_a_h: f64 = 0.0;
-          for hl in 1..radius+1 {
-            if x >= u16::from(hl) {
-              //not out of bounds
-              let hl_color = self.get_color_of_px_efficient((x-u16::from(hl)) as usize, y as usize, &dib_header, &pixel_data).unwrap();
-              new_r_h += hl_color[0] as f64 * (weights[(radius-hl) as usize] as f64) / total_weight_h as f64;
-              new_g_h += hl_color[1] as f64 * (weights[(radius-hl) as usize] as f64) / total_weight_h as f64;
-              new_b_h += hl_color[2] as f64 * (weights[(radius-hl) as usize] as f64) / total_weight_h as f64;
-              new_a_h += hl_color[3] as f64 * (weights[(radius-hl) as usize] as f64) / total_weight_h as f64;
-            }
-          }
-          let center_color = self.get_color_of_px_efficient(x as usize, y as usize, &dib_header, &pixel_data).unwrap();
-          new_r_h += center_color[0] as f64 * (weights[(radius) as usize] as f64) / total_weight_h as f64;
-          new_g_h += center_color[1] as f64 * (weights[(radius) as usize] as f64) / total_weight_h as f64;
-          new_b_h += center_color[2] as f64 * (weights[(radius) as usize] as f64) / total_weight_h as f64;
-          new_a_h += center_color[3] as f64 * (weights[(radius) as usize] as f64) / total_weight_h as f64;
-          for hr in 1..radius+1 {
-            if x+u16::from(hr) < width {
-              //not out of bounds
-              let hr_color = self.get_color_of_px_efficient((x+u16::from(hr)) as usize, y as usize, &dib_header, &pixel_data).unwrap();
-              new_r_h += hr_color[0] as f64 * (weights[(radius-hr) as usize] as f64) / total_weight_h as f64;
-              new_g_h += hr_color[1] as f64 * (weights[(radius-hr) as usize] as f64) / total_weight_h as f64;
-              new_b_h += hr_color[2] as f64 * (weights[(radius-hr) as usize] as f64) / total_weight_h as f64;
-              new_a_h += hr_color[3] as f64 * (weights[(radius-hr) as usize] as f64) / total_weight_h as f64;
-            }
-          }
-          //round values
-          let new_color: [u8; 4] = [new_r_h.round() as u8, new_g_h.round() as u8, new_b_h.round() as u8, new_a_h.round() as u8];
-          //write to pixel
-          self.change_color_of_pixel(x, y, new_color).unwrap();
-        }
-      }
+      passes.push([1, 0]);
     }
-    //repeat for vertical blur
-    //regenerate pixel data since it has changed
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
     if do_vertical {
-      //calculate weights
-      let mut weights: Vec<u16> = Vec::new();
-      for l in 0..radius {
-        //calculate weights to the left
-        weights.push(gen_distribution(radius, radius-l));
-      }
-      weights.push(gen_distribution(radius, 0));
-      for r in 0..radius {
-        //calculate weights to the right
-        weights.push(gen_distribution(radius, r+1));
-      }
+      passes.push([0, 1]);
+    }
+    for [dx, dy] in passes {
+      let og_pixels: Vec<u8> = self.pixels.clone();
       for y in 0..height {
         for x in 0..width {
-          let mut total_weight: u16 = 0;
-          for w in &weights {
-            total_weight += w;
-          }
-          let mut total_weight_v = total_weight;
-          //check if out of bounds, calculate horizontal weights
-          for vu_b in 1..radius+1 {
-            if y < u16::from(vu_b) {
+          //position along the pass direction, and how many pixels are in that direction
+          let (position, length) = if dx == 1 { (x, width) } else { (y, height) };
+          let mut total_weight_d = total_weight;
+          //check if out of bounds
+          for b in 1..radius+1 {
+            if position < u16::from(b) {
               //out of bounds
-              total_weight_v -= weights[(radius-vu_b) as usize];
+              total_weight_d -= weights[(radius-b) as usize];
             }
-          }
-          for vd_b in 1..radius+1 {
-            if y+u16::from(vd_b) >= height {
+            if position+u16::from(b) >= length {
               //out of bounds
-              total_weight_v -= weights[(radius+vd_b) as usize];
+              total_weight_d -= weights[(radius+b) as usize];
             }
           }
           //get pixel values, multiply and add them together
-          let mut new_r_h: f64 = 0.0;
-          let mut new_g_h: f64 = 0.0;
-          let mut new_b_h: f64 = 0.0;
-          let mut new_a_h: f64 = 0.0;
-          for vu in 1..radius+1 {
-            if y >= u16::from(vu) {
+          let mut new_color_f: [f64; 4] = [0.0; 4];
+          let mut add_color = |color: [u8; 4], weight: u16| {
+            for c in 0..4 {
+              new_color_f[c] += f64::from(color[c]) * f64::from(weight) / f64::from(total_weight_d);
+            }
+          };
+          for d in 1..radius+1 {
+            let d = u16::from(d);
+            if position >= d {
               //not out of bounds
-              let vu_color = self.get_color_of_px_efficient(x as usize, (y-u16::from(vu)) as usize, &dib_header, &pixel_data).unwrap();
-              new_r_h += f64::from(vu_color[0]) * f64::from(weights[(radius-vu) as usize]) / f64::from(total_weight_v);
-              new_g_h += f64::from(vu_color[1]) * f64::from(weights[(radius-vu) as usize]) / f64::from(total_weight_v);
-              new_b_h += f64::from(vu_color[2]) * f64::from(weights[(radius-vu) as usize]) / f64::from(total_weight_v);
-              new_a_h += f64::from(vu_color[3]) * f64::from(weights[(radius-vu) as usize]) / f64::from(total_weight_v);
+              let color = Image::get_color_from(&og_pixels, self.stride, (x-d*dx) as usize, (y-d*dy) as usize);
+              add_color(color, weights[(u16::from(radius)-d) as usize]);
             }
           }
-          //
-          let center_color = self.get_color_of_px_efficient(x as usize, y as usize, &dib_header, &pixel_data).unwrap();
-          new_r_h += f64::from(center_color[0]) * f64::from(weights[(radius) as usize]) / f64::from(total_weight_v);
-          new_g_h += f64::from(center_color[1]) * f64::from(weights[(radius) as usize]) / f64::from(total_weight_v);
-          new_b_h += f64::from(center_color[2]) * f64::from(weights[(radius) as usize]) / f64::from(total_weight_v);
-          new_a_h += f64::from(center_color[3]) * f64::from(weights[(radius) as usize]) / f64::from(total_weight_v);
-          for vd in 1..radius+1 {
-            if y+u16::from(vd) < height {
+          add_color(Image::get_color_from(&og_pixels, self.stride, x as usize, y as usize), weights[radius as usize]);
+          for d in 1..radius+1 {
+            let d = u16::from(d);
+            if position+d < length {
               //not out of bounds
-              let vd_color = self.get_color_of_px_efficient(x as usize, (y+u16::from(vd)) as usize, &dib_header, &pixel_data).unwrap();
-              new_r_h += f64::from(vd_color[0]) * f64::from(weights[(radius-vd) as usize]) / f64::from(total_weight_v);
-              new_g_h += f64::from(vd_color[1]) * f64::from(weights[(radius-vd) as usize]) / f64::from(total_weight_v);
-              new_b_h += f64::from(vd_color[2]) * f64::from(weights[(radius-vd) as usize]) / f64::from(total_weight_v);
-              new_a_h += f64::from(vd_color[3]) * f64::from(weights[(radius-vd) as usize]) / f64::from(total_weight_v);
+              let color = Image::get_color_from(&og_pixels, self.stride, (x+d*dx) as usize, (y+d*dy) as usize);
+              add_color(color, weights[(u16::from(radius)-d) as usize]);
             }
           }
           //round values
-          let new_color: [u8; 4] = [new_r_h.round() as u8, new_g_h.round() as u8, new_b_h.round() as u8, new_a_h.round() as u8];
+          let new_color: [u8; 4] = new_color_f.map(|c| c.round() as u8);
           //write to pixel
-          self.change_color_of_pixel(x, y, new_color).unwrap();
+          self.change_color_of_pixel(x, y, new_color)?;
         }
       }
-    }
-    return Ok(());
-  }
-  /// Apply box blur with given radius.
-  /// The larger the radius, the more it will blur, since the radius determines how many neighbouring pixels will be considered.
-  /// 
-  /// A box blur essentially averages the color value of every pixel with the neighbours' color values.
-  /// 
-  /// Since a box blur is a separable filter, unlike the mean filter, but has the same effect, it is recommended to use `box_blur` instead of `mean_filter`.
-  /// 
+    }
+    return Ok(());
+  }
+  /// Apply box blur with given radius. See `BMP::box_blur`.
   pub fn box_blur(&mut self, radius: u8) -> Result<(), ErrorKind> {
     //in box blur the kernel is just the same for all pixels
     let gen_box_distribution = |_radius: u8, _distance: u8| -> u16 {
@@ -2048,11 +4364,7 @@ impl BMP {
     };
     return self.separable_blur(radius, gen_box_distribution, None, None);
   }
-  /// Apply gaussian blur with given radius.
-  /// The larger the radius, the more it will blur, since the radius determines how many neighbouring pixels will be considered.
-  /// 
-  /// Learn more about gaussian blurs [here](https://en.wikipedia.org/wiki/Gaussian_blur).
-  /// 
+  /// Apply gaussian blur with given radius. See `BMP::gaussian_blur`.
   pub fn gaussian_blur(&mut self, radius: u8) -> Result<(), ErrorKind> {
     //cheat and use pascal's triangle for distribution
     let gen_gaussian_distribution = |radius: u8, distance: u8| -> u16 {
@@ -2066,7 +4378,7 @@ impl BMP {
       let k = radius-distance;
       let mut term: f64 = 1.0;
       for i in 1..radius+2 {
-        term = term * ((n+1-i) as f64/f64::from(i));
+        term *= f64::from(n+1-i)/f64::from(i);
         if i == k {
           break;
         }
@@ -2078,32 +4390,11 @@ impl BMP {
   //filters
   /// Turn the image into grayscale
   pub fn grayscale(&mut self) -> Result<(), ErrorKind> {
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let height: u16 = dib_header.height.abs() as u16;
-    let width: u16 = dib_header.width as u16;
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let header = self.get_header();
-    //change every pixel
-    for y in 0..height {
-      for x in 0..width {
-        //get pixel color
-        //although we are actively changing the pixel data, because we query the color before it is changed, we can use the old one for efficiency
-        let old_color = self.get_color_of_px_efficient(x as usize, y as usize, &dib_header, &pixel_data);
-        let old_color: [u8; 4] = match old_color {
-          Ok(returned_color) => returned_color,
-          Err(e) => return Err(e),
-        };
-        //change pixel color, preserving alpha channel
-        self.change_color_of_pixel_efficient(x, y, BMP::rgb_to_grayscale(old_color), &dib_header, &header)?;
-      }
+    self.mark_all_dirty();
+    for pixel in self.pixels.chunks_exact_mut(4) {
+      //preserving alpha channel
+      let gray = BMP::rgb_to_grayscale([pixel[0], pixel[1], pixel[2], pixel[3]]);
+      pixel.copy_from_slice(&gray);
     }
     return Ok(());
   }
@@ -2113,65 +4404,27 @@ impl BMP {
   }
   /// Only considers the value of a specific channel (red, green, alpha) when turning the image grayscale.
   pub fn channel_grayscale(&mut self, channel: RGBAChannel) -> Result<(), ErrorKind> {
-    //use r, g, b, or a channel to turn into gray scale image
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let height: u16 = dib_header.height.abs() as u16;
-    let width: u16 = dib_header.width as u16;
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let header = self.get_header();
-    //change every pixel
-    for y in 0..height {
-      for x in 0..width {
-        //get pixel color
-        //although we are actively changing the pixel data, because we query the color before it is changed, we can use the old one for efficiency
-        let old_color = self.get_color_of_px_efficient(x as usize, y as usize, &dib_header, &pixel_data);
-        let old_color: [u8; 4] = match old_color {
-          Ok(returned_color) => returned_color,
-          Err(e) => return Err(e),
-        };
-        let use_color: u8;
-        match channel {
-          RGBAChannel::Red => use_color = old_color[0],
-          RGBAChannel::Green => use_color = old_color[1],
-          RGBAChannel::Blue => use_color = old_color[2],
-          RGBAChannel::Alpha => {
-            use_color = old_color[3];
-            self.change_color_of_pixel_efficient(x, y, [use_color, use_color, use_color, use_color], &dib_header, &header)?;
-            return Ok(());
-          },
-        }
-        //change pixel color, preserving alpha channel
-        self.change_color_of_pixel_efficient(x, y, [use_color, use_color, use_color, old_color[3]], &dib_header, &header)?;
-      }
+    self.mark_all_dirty();
+    for pixel in self.pixels.chunks_exact_mut(4) {
+      let new_color: [u8; 4] = match channel {
+        //preserving alpha channel
+        RGBAChannel::Red => [pixel[0], pixel[0], pixel[0], pixel[3]],
+        RGBAChannel::Green => [pixel[1], pixel[1], pixel[1], pixel[3]],
+        RGBAChannel::Blue => [pixel[2], pixel[2], pixel[2], pixel[3]],
+        RGBAChannel::Alpha => [pixel[3], pixel[3], pixel[3], pixel[3]],
+      };
+      pixel.copy_from_slice(&new_color);
     }
     return Ok(());
   }
   pub fn surround_filter(&mut self, radius: u8, get_new_pixel: impl Fn(Vec<[u8; 4]>) -> [u8; 4]) -> Result<(), ErrorKind> {
     //non separable filter that requires the surrounding pixels
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let height: u16 = dib_header.height.abs() as u16;
-    let width: u16 = dib_header.width as u16;
+    let height: u16 = self.height as u16;
+    let width: u16 = self.width as u16;
     if radius > 16 || radius == 0 {
       return Err(ErrorKind::InvalidParameter("Blur/filter radius is invalid since bigger than 16 or is 0".to_string()));
     }
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let header = self.get_header();
+    let og_pixels: Vec<u8> = self.pixels.clone();
     for y in 0..height {
       for x in 0..width {
         let mut surrounding_colors: Vec<[u8; 4]> = Vec::new();
@@ -2179,72 +4432,45 @@ impl BMP {
         let rows: i16 = i16::from(radius*2+1);
         for row in 0..rows {
           let row_y = y as i16-(i16::from(radius)-row);
-          if row_y < 0 {
-            continue;
-          } else if row_y as u16 >= height {
+          if row_y < 0 || row_y as u16 >= height {
             continue;
           }
-          //to the left of the center x
-          for left_num in 1..u16::from(radius+1) {
-            if (i32::from(x)-i32::from(left_num)) < 0 {
-              continue;
-            }
-            let left_pixel_color = self.get_color_of_pixel_efficient((x-left_num) as usize, row_y as usize, &dib_header, &pixel_data)?;
-            surrounding_colors.push(left_pixel_color);
-          }
-          //the same column as the center x
-          let center_pixel_color = self.get_color_of_pixel_efficient(x as usize, row_y as usize, &dib_header, &pixel_data)?;
-          surrounding_colors.push(center_pixel_color);
-          //to the right of the center x
-          for right_num in 1..u16::from(radius+1) {
-            if (x+right_num) >= width {
-              continue;
-            }
-            let right_pixel_color = self.get_color_of_pixel_efficient((x+right_num) as usize, row_y as usize, &dib_header, &pixel_data)?;
-            surrounding_colors.push(right_pixel_color);
+          //to the left of the center x, the same column as the center x, and to the right of the center x
+          let left = x.saturating_sub(u16::from(radius));
+          let right = (x+u16::from(radius)).min(width-1);
+          for column in left..right+1 {
+            surrounding_colors.push(Image::get_color_from(&og_pixels, self.stride, column as usize, row_y as usize));
           }
         }
-        self.change_color_of_pixel_efficient(x, y, get_new_pixel(surrounding_colors), &dib_header, &header)?;
+        self.change_color_of_pixel(x, y, get_new_pixel(surrounding_colors))?;
       }
     }
     return Ok(());
   }
-  /// A median filter changes the color a value of a pixel to the median color value of all of the pixel's neighbours.
-  /// The larger the radius, the more neighbouring pixels will be considered.
-  /// 
-  /// Median filters are great at filtering out noise.
-  /// 
+  /// Apply a median filter with given radius. See `BMP::median_filter`.
   pub fn median_filter(&mut self, radius: u8) -> Result<(), ErrorKind> {
     //good for filtering out noise
     let get_median_pixel = |surrounding_pixels: Vec<[u8; 4]>| -> [u8; 4] {
-      let iter_surrounding = (&surrounding_pixels).into_iter();
-      let mut r_vec = iter_surrounding.clone().map(|item| item[0]).collect::<Vec<u8>>();
-      r_vec.sort();
-      let mut g_vec = iter_surrounding.clone().map(|item| item[1]).collect::<Vec<u8>>();
-      g_vec.sort();
-      let mut b_vec = iter_surrounding.clone().map(|item| item[2]).collect::<Vec<u8>>();
-      b_vec.sort();
-      let mut a_vec = iter_surrounding.clone().map(|item| item[3]).collect::<Vec<u8>>();
-      a_vec.sort();
-      //vector[vector.len()/2] will return the middle element in the vector, the length of the vector is odd
-      //length of vector/2 will be a decimal, but rounded down
-      [r_vec[r_vec.len()/2], g_vec[g_vec.len()/2], b_vec[b_vec.len()/2], a_vec[a_vec.len()/2]]
+      let mut median: [u8; 4] = [0; 4];
+      for (c, channel) in median.iter_mut().enumerate() {
+        let mut channel_vec = surrounding_pixels.iter().map(|item| item[c]).collect::<Vec<u8>>();
+        channel_vec.sort_unstable();
+        //vector[vector.len()/2] will return the middle element in the vector
+        *channel = channel_vec[channel_vec.len()/2];
+      }
+      median
     };
     return self.surround_filter(radius, get_median_pixel);
   }
   /// A less efficient version of the `box_blur`, use that instead.
   pub fn mean_filter(&mut self, radius: u8) -> Result<(), ErrorKind> {
     let get_mean_pixel = |surrounding_pixels: Vec<[u8; 4]>| -> [u8; 4] {
-      let iter_surrounding = (&surrounding_pixels).into_iter();
-      let total_r: f64 = f64::from(iter_surrounding.clone().map(|item| u16::from(item[0])).reduce(|prev_total, current| prev_total+current).unwrap());
-      let total_g: f64 = f64::from(iter_surrounding.clone().map(|item| u16::from(item[1])).reduce(|prev_total, current| prev_total+current).unwrap());
-      let total_b: f64 = f64::from(iter_surrounding.clone().map(|item| u16::from(item[2])).reduce(|prev_total, current| prev_total+current).unwrap());
-      let total_a: f64 = f64::from(iter_surrounding.clone().map(|item| u16::from(item[3])).reduce(|prev_total, current| prev_total+current).unwrap());
-      let average_r: u8 = (total_r/(&surrounding_pixels).len() as f64).round() as u8;
-      let average_g: u8 = (total_g/(&surrounding_pixels).len() as f64).round() as u8;
-      let average_b: u8 = (total_b/(&surrounding_pixels).len() as f64).round() as u8;
-      let average_a: u8 = (total_a/(&surrounding_pixels).len() as f64).round() as u8;
-      [average_r, average_g, average_b, average_a]
+      let mut mean: [u8; 4] = [0; 4];
+      for (c, channel) in mean.iter_mut().enumerate() {
+        let total: f64 = surrounding_pixels.iter().map(|item| f64::from(item[c])).sum();
+        *channel = (total/surrounding_pixels.len() as f64).round() as u8;
+      }
+      mean
     };
     return self.surround_filter(radius, get_mean_pixel);
   }
@@ -2253,7 +4479,7 @@ impl BMP {
   pub fn draw_line(&mut self, fill: [u8; 4], p1: [u16; 2], p2: [u16; 2]) -> Result<(), ErrorKind> {
     if p1[0] == p2[0] {
       //x matches x, straight vertical line
-      for ay in 0..(p2[1] as i16 - p1[1] as i16 + 1).abs() as u16 {
+      for ay in 0..=(i32::from(p2[1])-i32::from(p1[1])).unsigned_abs() as u16 {
         //if p1 is below p2
         if p1[1] < p2[1] {
           self.change_color_of_pixel(p1[0], p1[1]+ay, fill)?;
@@ -2263,7 +4489,7 @@ impl BMP {
       }
     } else if p1[1] == p2[1] {
       //y matches y, straight horizontal line
-      for ax in 0..(p2[0] as i16 - p1[0] as i16 + 1).abs() as u16 {
+      for ax in 0..=(i32::from(p2[0])-i32::from(p1[0])).unsigned_abs() as u16 {
         //if p1 is to the left of p2
         if p1[0] < p2[0] {
           self.change_color_of_pixel(p1[0]+ax, p1[1], fill)?;
@@ -2272,8 +4498,8 @@ impl BMP {
         }
       }
     } else {
-      let vertical_diff: u16 = ((p2[1] as i16 - p1[1] as i16).abs() + 1) as u16;
-      let horizontal_diff: u16 = ((p2[0] as i16 - p1[0] as i16).abs() + 1) as u16;
+      let vertical_diff: u16 = (i32::from(p2[1])-i32::from(p1[1])).unsigned_abs() as u16+1;
+      let horizontal_diff: u16 = (i32::from(p2[0])-i32::from(p1[0])).unsigned_abs() as u16+1;
       //get left most point
       let leftmost_p;
       let rightmost_p;
@@ -2407,18 +4633,12 @@ impl BMP {
     }
     //todo: rewrite to not use bucket
     if fill.is_some() {
-      let dib_header = self.get_dib_header();
-      let dib_header = match dib_header {
-        Ok(returned_dib_header) => returned_dib_header,
-        Err(e) => return Err(e),
-      };
-      let header = self.get_header();
       let unwrapped_fill = fill.unwrap();
       let p1_mod = [p1[0]+1, p1[1]+1];
       let p2_mod = [p2[0]-1, p2[1]-1];
       for y in 0..(p2_mod[1]-p1_mod[1]+1) {
         for x in 0..(p2_mod[0]-p1_mod[0]+1) {
-          self.change_color_of_pixel_efficient(p1_mod[0]+x, p1_mod[1]+y, unwrapped_fill, &dib_header, &header)?;
+          self.change_color_of_pixel(p1_mod[0]+x, p1_mod[1]+y, unwrapped_fill)?;
         }
       }
     }
@@ -2511,94 +4731,50 @@ impl BMP {
     return Ok(());
   }
   /// Equivalent to the bucket fill tool in many image editors. Specify the starting coordinates and the new fill color.
+  /// Returns the coordinates of the filled pixels.
   pub fn fill_bucket(&mut self, fill: [u8; 4], x: usize, y: usize) -> Result<Vec<[u16; 2]>, ErrorKind> {
     //todo: have parameter allowing fill if the color only deviates a certain small percentage
     //fill same color connected to the (x,y) with new paint
     //check up, down, left, right. If same color as initial square, add to queue. Iterate through queue, after iterating add to visit and repeat
-    let dib_header = self.get_dib_header();
-    let dib_header = match dib_header {
-      Ok(returned_dib_header) => returned_dib_header,
-      Err(e) => return Err(e),
-    };
-    let pixel_data = self.get_pixel_data();
-    let pixel_data = match pixel_data {
-      Ok(returned_pixel_data) => returned_pixel_data,
-      Err(e) => return Err(e),
-    };
-    let replace_color = self.get_color_of_px(x, y);
-    let replace_color: [u8; 4] = match replace_color {
-      Ok(returned_replace_color) => returned_replace_color,
-      Err(e) => return Err(e),
-    };
+    let replace_color: [u8; 4] = self.get_color_of_pixel(x, y)?;
+    let width = self.width as usize;
+    let height = self.height as usize;
+    //whether a pixel has already been added to the queue, so it is never added twice
+    let mut seen: Vec<bool> = vec![false; width*height];
     let mut visited: Vec<[u16; 2]> = Vec::new();
-    let mut queue: Vec<[u16; 2]> = Vec::new();
-    //let mut i = 0;
-    queue.push([x as u16, y as u16]);
-    while queue.len() > 0 {
-      //i += 1;
-      //println!("{} {}", i, queue.len());
-      let x2: u16 = queue[0][0];
-      let y2: u16 = queue[0][1];
-      //turn current coords into fill color
-      //self.change_color_of_pixel(x2, y2, fill);
-      //check is surrounding (up, down, left, right) are same color
-      //check to make sure these coords exist. (get height, width)
+    let mut queue: VecDeque<[usize; 2]> = VecDeque::new();
+    queue.push_back([x, y]);
+    seen[y*width+x] = true;
+    while let Some([x2, y2]) = queue.pop_front() {
+      //check is surrounding (down, up, left, right) are same color
       //remember, indexes start at 0
-      if y2+1 < dib_header.height as u16 {
-        if !visited.contains(&[x2, y2+1]) && !queue.contains(&[x2, y2+1]) {
-          let down_color = self.get_color_of_px_efficient(x2 as usize, (y2+1) as usize, &dib_header, &pixel_data)?;
-          if down_color == replace_color {
-            queue.push([x2, y2+1]);
-          }
-        }
+      let mut neighbours: Vec<[usize; 2]> = Vec::new();
+      if y2+1 < height {
+        neighbours.push([x2, y2+1]);
       }
       if y2 != 0 {
-        if !visited.contains(&[x2, y2-1]) && !queue.contains(&[x2, y2-1]) {
-          //does not go all the way to up color
-          let up_color = self.get_color_of_px_efficient(x2 as usize, (y2-1) as usize, &dib_header, &pixel_data)?;
-          if up_color == replace_color {
-            queue.push([x2, y2-1]);
-          }
-        }
+        neighbours.push([x2, y2-1]);
       }
       if x2 != 0 {
-        if !visited.contains(&[x2-1, y2]) && !queue.contains(&[x2-1, y2]) {
-          let left_color = self.get_color_of_px_efficient((x2-1) as usize, y2 as usize, &dib_header, &pixel_data)?;
-          if left_color == replace_color {
-            queue.push([x2-1, y2]);
-          }
-        }
+        neighbours.push([x2-1, y2]);
       }
-      if x2+1 < dib_header.width as u16 {
-        if !visited.contains(&[x2+1, y2]) && !queue.contains(&[x2+1, y2]) {
-          let right_color = self.get_color_of_px_efficient((x2+1) as usize, y2 as usize, &dib_header, &pixel_data)?;
-          if right_color == replace_color {
-            queue.push([x2+1, y2]);
-          }
+      if x2+1 < width {
+        neighbours.push([x2+1, y2]);
+      }
+      for [nx, ny] in neighbours {
+        if !seen[ny*width+nx] && Image::get_color_from(&self.pixels, self.stride, nx, ny) == replace_color {
+          seen[ny*width+nx] = true;
+          queue.push_back([nx, ny]);
         }
       }
-      //end
-      visited.push(queue[0]);
-      queue.remove(0);
+      visited.push([x2 as u16, y2 as u16]);
     }
     //loop through visited
-    let header = self.get_header();
     for px in &visited {
-      self.change_color_of_pixel_efficient(px[0], px[1], fill, &dib_header, &header)?;
+      self.change_color_of_pixel(px[0], px[1], fill)?;
     }
-    //&self.save_to_new("src/images/e2.bmp");
     return Ok(visited);
   }
-  //save image functions
-  /// Save the image to a new file.
-  pub fn save_to_new(self, file_path: &str) -> Result<(), ErrorKind> {
-    let mut new_file = fs::File::create(&std::path::Path::new(file_path)).unwrap();
-    let write_op = new_file.write_all(&self.contents);
-    match write_op {
-      Ok(_file) => return Ok(()),
-      Err(_e) => return Err(ErrorKind::FailedToWrite),
-    };
-  }
 }
 
 //https://docs.microsoft.com/en-us/windows/win32/wcs/basic-color-management-concepts
@@ -2607,7 +4783,10 @@ impl BMP {
 
 #[test]
 fn non_existent_file() {
-  assert!(BMP::new_from_file("doesnotexist.bmp") == Err(ErrorKind::NotFound));
+  match BMP::new_from_file("doesnotexist.bmp") {
+    Err(ErrorKind::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+    _ => panic!("expected a not found I/O error"),
+  }
 }
 
 #[test]
@@ -2620,3 +4799,1058 @@ fn out_of_bounds_access() {
   assert!(b.change_color_of_pixel(149, 149, [128, 128, 128, 255]).is_ok());
   assert!(b.change_color_of_pixel(150, 150, [128, 128, 128, 255]).is_err());
 }
+
+#[test]
+fn rle8_decoding() {
+  //encoded runs, a delta, an odd length absolute run and end of line/bitmap markers
+  let b = BMP::new_from_file("example/images/rle8.bmp").unwrap();
+  let black = [0, 0, 0, 255];
+  let red = [255, 0, 0, 255];
+  let green = [0, 255, 0, 255];
+  let blue = [0, 0, 255, 255];
+  let expected = [[blue, blue, blue, blue, blue], [red, green, red, green, black], [red, red, red, black, black]];
+  for y in 0..3 {
+    for x in 0..5 {
+      assert_eq!(b.get_color_of_pixel(x, y).unwrap(), expected[y][x]);
+    }
+  }
+}
+
+#[test]
+fn rle4_decoding() {
+  let b = BMP::new_from_file("example/images/rle4.bmp").unwrap();
+  let pixel_data = b.get_pixel_data().unwrap();
+  assert_eq!(pixel_data.len(), 2);
+  let top: Vec<u8> = pixel_data[0].iter().map(|p| p[0]).collect();
+  let bottom: Vec<u8> = pixel_data[1].iter().map(|p| p[0]).collect();
+  assert_eq!(top, vec![1, 2, 1, 2, 1]);
+  assert_eq!(bottom, vec![3, 0, 3, 3, 1]);
+}
+
+#[test]
+fn palette_byte_order() {
+  //color table entries are stored as blue, green, red, reserved
+  let mut bytes = fs::read("example/images/palette_8bit.bmp").unwrap();
+  bytes[58..62].copy_from_slice(&[10, 20, 30, 0]);
+  let b = BMP::from_bytes(&bytes).unwrap();
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [30, 20, 10, 255]);
+  let dib_header = b.get_dib_header().unwrap();
+  let pixel_data = b.get_pixel_data().unwrap();
+  assert_eq!(b.get_color_of_pixel_efficient(0, 0, &dib_header, &pixel_data).unwrap(), [30, 20, 10, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 0).unwrap(), [0, 0, 255, 255]);
+}
+
+#[test]
+fn bitfields_565() {
+  let mut b = BMP::new_from_file("example/images/bitfields_565.bmp").unwrap();
+  assert_eq!(b.get_format(), "brg".to_string());
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [255, 0, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [0, 255, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 0).unwrap(), [0, 0, 255, 255]);
+  assert_eq!(b.get_color_of_pixel(0, 1).unwrap(), [255, 255, 255, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 1).unwrap(), [0, 0, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 1).unwrap(), [132, 130, 132, 255]);
+  //written colors are rounded to the nearest value the 5 and 6 bit channels can store
+  b.change_color_of_pixel(1, 1, [200, 100, 50, 255]).unwrap();
+  assert_eq!(b.get_color_of_pixel(1, 1).unwrap(), [197, 101, 49, 255]);
+}
+
+#[test]
+fn bitfields_1010102() {
+  let mut b = BMP::new_from_file("example/images/bitfields_1010102.bmp").unwrap();
+  assert_eq!(b.get_format(), "bgra".to_string());
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [255, 0, 128, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [0, 255, 0, 85]);
+  b.change_color_of_pixel(1, 0, [10, 20, 30, 170]).unwrap();
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [10, 20, 30, 170]);
+  let masks = b.get_bit_masks().unwrap();
+  assert_eq!(masks.encode([255, 0, 128, 255]), 0x3FF00000 | 0x00000202 | 0xC0000000);
+}
+
+#[test]
+fn rgb_555() {
+  let mut b = BMP::new_from_file("example/images/rgb_555.bmp").unwrap();
+  assert_eq!(b.get_format(), "rgb".to_string());
+  let dib_header = b.get_dib_header().unwrap();
+  let pixel_data = b.get_pixel_data().unwrap();
+  assert_eq!(b.get_color_of_pixel_efficient(0, 0, &dib_header, &pixel_data).unwrap(), [255, 0, 0, 255]);
+  assert_eq!(b.get_color_of_pixel_efficient(1, 0, &dib_header, &pixel_data).unwrap(), [0, 255, 0, 255]);
+  assert_eq!(b.get_color_of_pixel_efficient(2, 0, &dib_header, &pixel_data).unwrap(), [0, 0, 255, 255]);
+  //unused top bit is ignored
+  assert_eq!(b.get_color_of_pixel_efficient(0, 1, &dib_header, &pixel_data).unwrap(), [255, 255, 255, 255]);
+  assert_eq!(b.get_color_of_pixel_efficient(2, 1, &dib_header, &pixel_data).unwrap(), [132, 132, 132, 255]);
+  let header = b.get_header();
+  b.change_color_of_pixel_efficient(1, 1, [200, 100, 50, 255], &dib_header, &header).unwrap();
+  assert_eq!(b.get_color_of_pixel(1, 1).unwrap(), [197, 99, 49, 255]);
+  //neighbouring pixels in the row are untouched
+  assert_eq!(b.get_color_of_pixel(0, 1).unwrap(), [255, 255, 255, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 1).unwrap(), [132, 132, 132, 255]);
+}
+
+#[test]
+fn palette_nearest_color() {
+  let mut b = BMP::new_from_file("example/images/palette_8bit.bmp").unwrap();
+  assert_eq!(b.get_palette_policy(), PalettePolicy::Nearest);
+  b.change_color_of_pixel(0, 1, [250, 10, 10, 255]).unwrap();
+  b.change_color_of_pixel(1, 1, [10, 10, 200, 255]).unwrap();
+  assert_eq!(b.get_color_of_pixel(0, 1).unwrap(), [255, 0, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 1).unwrap(), [0, 0, 255, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 1).unwrap(), [0, 0, 0, 255]);
+  //color table is unchanged
+  assert_eq!(b.get_size(false), 78);
+}
+
+#[test]
+fn palette_grow() {
+  let mut b = BMP::new_from_file("example/images/palette_8bit.bmp").unwrap();
+  b.set_palette_policy(PalettePolicy::Grow);
+  b.change_color_of_pixel(2, 1, [10, 200, 200, 255]).unwrap();
+  //existing colors are reused
+  b.change_color_of_pixel(0, 1, [0, 255, 0, 255]).unwrap();
+  let dib_header = b.get_dib_header().unwrap();
+  assert_eq!(dib_header.ClrUsed, Some(5));
+  assert_eq!(b.get_offset(), 14+40+5*4);
+  assert_eq!(b.get_size(true), b.get_size(false));
+  assert_eq!(b.get_color_of_pixel(2, 1).unwrap(), [10, 200, 200, 255]);
+  assert_eq!(b.get_color_of_pixel(0, 1).unwrap(), [0, 255, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [0, 255, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 0).unwrap(), [0, 0, 255, 255]);
+}
+
+#[test]
+fn palette_sub_byte_packing() {
+  let mut b = BMP::new_from_file("example/images/palette_4bit.bmp").unwrap();
+  b.set_palette_policy(PalettePolicy::Grow);
+  b.change_color_of_pixel(1, 0, [0, 0, 255, 255]).unwrap();
+  let offset = b.get_offset() as usize;
+  //index 2 is packed into the low half of the first byte, the other pixels are untouched
+  assert_eq!(b.contents[offset..offset+4], [0x02, 0x10, 0x00, 0x00]);
+  b.change_color_of_pixel(2, 0, [0, 0, 0, 255]).unwrap();
+  assert_eq!(b.contents[offset..offset+4], [0x02, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn sub_byte_pixel_data() {
+  //odd widths, so rows end part way through a byte and are padded
+  for (file_path, bitcount, width) in [("example/images/indexed_1bit_odd.bmp", 1u32, 13usize), ("example/images/indexed_2bit_odd.bmp", 2, 7), ("example/images/indexed_4bit_odd.bmp", 4, 5)] {
+    let b = BMP::new_from_file(file_path).unwrap();
+    let pixel_data = b.get_pixel_data().unwrap();
+    assert_eq!(pixel_data.len(), 3);
+    let colors: usize = 1 << bitcount;
+    for y in 0..3 {
+      assert_eq!(pixel_data[y].len(), width);
+      for x in 0..width {
+        let index = (x*3+y) % colors;
+        assert_eq!(pixel_data[y][x], vec![index as u8]);
+        assert_eq!(b.get_color_of_pixel(x, y).unwrap(), [(index*16) as u8, 255-(index*16) as u8, 0, 255]);
+      }
+    }
+  }
+}
+
+#[test]
+fn image_round_trip() {
+  //converting to an image and back, without any changes, gives exactly the same bytes
+  for file in ["example.bmp", "rle8.bmp", "bitfields_565.bmp", "bitfields_1010102.bmp", "rgb_555.bmp", "palette_8bit.bmp", "indexed_1bit_odd.bmp", "indexed_4bit_odd.bmp"] {
+    let b = BMP::new_from_file(&("example/images/".to_owned()+file)).unwrap();
+    let image = b.to_image().unwrap();
+    assert_eq!(image.get_pixels().len(), image.get_stride()*image.get_height() as usize);
+    assert_eq!(image.get_color_of_pixel(0, 0).unwrap(), b.get_color_of_pixel(0, 0).unwrap());
+    assert!(image.to_bmp().unwrap().contents == b.contents);
+  }
+  //pixels that are marked as changed but keep their color are left as they were, even where encoding them again would lose bits
+  for file in ["rle8.bmp", "bitfields_1010102.bmp", "rgb_555.bmp"] {
+    let b = BMP::new_from_file(&("example/images/".to_owned()+file)).unwrap();
+    let mut image = b.to_image().unwrap();
+    image.get_pixels_mut();
+    image.invert(None).unwrap();
+    image.invert(None).unwrap();
+    assert!(image.to_bmp().unwrap().contents == b.contents, "{}", file);
+  }
+  //only pixels of compressed images that actually changed are an error
+  let mut image = BMP::new_from_file("example/images/rle8.bmp").unwrap().to_image().unwrap();
+  image.change_color_of_pixel(0, 0, [1, 2, 3, 255]).unwrap();
+  assert_eq!(image.to_bmp().map(|_| ()), Err(ErrorKind::UnsupportedCompression("BI_RLE8".to_string())));
+}
+
+#[test]
+fn image_edit() {
+  let b = BMP::new_from_file("example/images/bitfields_565.bmp").unwrap();
+  let mut image = b.to_image().unwrap();
+  image.invert(None).unwrap();
+  assert_eq!(image.get_color_of_pixel(0, 0).unwrap(), [0, 255, 255, 255]);
+  let inverted = image.to_bmp().unwrap();
+  assert_eq!(inverted.get_color_of_pixel(0, 0).unwrap(), [0, 255, 255, 255]);
+  assert_eq!(inverted.get_color_of_pixel(1, 1).unwrap(), [255, 255, 255, 255]);
+  //the same edit through the BMP
+  let mut b2 = b.clone();
+  b2.invert(None).unwrap();
+  assert!(b2.contents == inverted.contents);
+}
+
+#[test]
+fn bytes_and_streams() {
+  let bytes = fs::read("example/images/palette_8bit.bmp").unwrap();
+  let b = BMP::from_bytes(&bytes).unwrap();
+  assert!(!b.is_from_file());
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), BMP::new_from_file("example/images/palette_8bit.bmp").unwrap().get_color_of_pixel(0, 0).unwrap());
+  let b2 = BMP::from_reader(&bytes[..]).unwrap();
+  assert_eq!(b2.to_bytes(), bytes);
+  let mut written: Vec<u8> = Vec::new();
+  b2.write_to(&mut written).unwrap();
+  assert_eq!(written, bytes);
+}
+
+#[test]
+fn corrupt_files() {
+  for entry in fs::read_dir("example/images").unwrap() {
+    let path = entry.unwrap().path();
+    assert!(BMP::new_from_file(path.to_str().unwrap()).is_ok(), "{:?}", path);
+  }
+  let bytes = fs::read("example/images/palette_8bit.bmp").unwrap();
+  assert!(BMP::from_bytes(&bytes[..10]).is_err());
+  let mut wrong_type = bytes.clone();
+  wrong_type[0] = b'P';
+  assert!(BMP::from_bytes(&wrong_type) == Err(ErrorKind::WrongFileType));
+  let mut bad_compression = bytes.clone();
+  bad_compression[30] = 9;
+  assert!(matches!(BMP::from_bytes(&bad_compression), Err(ErrorKind::CorruptHeader { offset: 30, .. })));
+  let mut bad_offset = bytes.clone();
+  bad_offset[10..14].copy_from_slice(&10000u32.to_le_bytes());
+  assert!(matches!(BMP::from_bytes(&bad_offset), Err(ErrorKind::CorruptHeader { offset: 10, .. })));
+  //claims to be much taller than the pixel array
+  let mut too_tall = bytes.clone();
+  too_tall[22..26].copy_from_slice(&100000i32.to_le_bytes());
+  match BMP::from_bytes(&too_tall) {
+    Err(ErrorKind::CorruptHeader { field, offset, .. }) => assert_eq!((field.as_str(), offset), ("height", 22)),
+    _ => panic!("expected corrupt height"),
+  }
+  assert!(BMP::from_bytes(&bytes[..bytes.len()-1]).is_err());
+  //compressed images can not claim to be bigger than they could ever be decoded to
+  for (file, width_offset, height_offset) in [("rle8.bmp", 18, 22), ("rle4.bmp", 18, 22), ("os2_rle24.bmp", 18, 22), ("os2_huffman1d.bmp", 18, 22)] {
+    let mut huge = fs::read(format!("example/images/{}", file)).unwrap();
+    huge[width_offset..width_offset+4].copy_from_slice(&60000u32.to_le_bytes());
+    huge[height_offset..height_offset+4].copy_from_slice(&60000i32.to_le_bytes());
+    match BMP::from_bytes(&huge) {
+      Err(ErrorKind::CorruptHeader { field, .. }) => assert!(field == "width" || field == "height", "{}", file),
+      _ => panic!("expected corrupt size for {}", file),
+    }
+  }
+  //the color table has to fit before the pixel array, and can not have more colors than the bitcount allows
+  let mut too_many_colors = bytes.clone();
+  too_many_colors[46..50].copy_from_slice(&257u32.to_le_bytes());
+  match BMP::from_bytes(&too_many_colors) {
+    Err(ErrorKind::CorruptHeader { field, offset, .. }) => assert_eq!((field.as_str(), offset), ("ClrUsed", 46)),
+    _ => panic!("expected corrupt ClrUsed"),
+  }
+  let mut no_room_for_colors = bytes.clone();
+  no_room_for_colors[46..50].copy_from_slice(&5u32.to_le_bytes());
+  assert!(matches!(BMP::from_bytes(&no_room_for_colors), Err(ErrorKind::CorruptHeader { offset: 10, .. })));
+  //ClrUsed of 0 means every color the bitcount allows
+  let mut all_colors = bytes.clone();
+  all_colors[46..50].copy_from_slice(&0u32.to_le_bytes());
+  assert!(matches!(BMP::from_bytes(&all_colors), Err(ErrorKind::CorruptHeader { offset: 10, .. })));
+}
+
+#[test]
+fn error_trait() {
+  fn load(path: &str) -> Result<BMP, Box<dyn std::error::Error>> {
+    let bmp = BMP::new_from_file(path)?;
+    return Ok(bmp);
+  }
+  match load("doesnotexist.bmp") {
+    Err(e) => assert!(e.source().is_some()),
+    Ok(_) => panic!("expected an error"),
+  }
+  assert!(load("example/images/rle8.bmp").is_ok());
+  assert_eq!(format!("{:?}", ErrorKind::UnsupportedBitDepth(7)), "UnsupportedBitDepth(7)");
+  let mut b = BMP::new_from_file("example/images/rle8.bmp").unwrap();
+  assert!(b.change_color_of_pixel(0, 0, [0, 0, 0, 255]) == Err(ErrorKind::UnsupportedCompression("BI_RLE8".to_string())));
+}
+
+#[test]
+fn header_round_trip() {
+  //12, 40, 52, 56, 108 and 124 byte DIB headers
+  for file in ["core_24bit.bmp", "os2_16.bmp", "os2_64.bmp", "os2_huffman1d.bmp", "os2_rle24.bmp", "palette_8bit.bmp", "v2_565.bmp", "v3_argb.bmp", "bitfields_1010102.bmp", "example.bmp", "v5_calibrated.bmp"] {
+    let b = BMP::new_from_file(&("example/images/".to_owned()+file)).unwrap();
+    let dib_header = b.get_dib_header().unwrap();
+    let size = dib_header.size as usize;
+    let header_bytes: Vec<u8> = b.get_header().into_iter().collect();
+    assert_eq!(header_bytes, b.contents[..14].to_vec(), "{}", file);
+    let dib_header_bytes: Vec<u8> = dib_header.clone().into_iter().collect();
+    assert_eq!(dib_header_bytes, b.contents[14..14+size].to_vec(), "{}", file);
+    assert_eq!(dib_header.to_bytes().unwrap(), dib_header_bytes, "{}", file);
+    let mut b2 = b.clone();
+    b2.set_dib_header(dib_header).unwrap();
+    assert!(b2.contents == b.contents);
+  }
+  let b = BMP::new_from_file("example/images/core_24bit.bmp").unwrap();
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [255, 0, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 1).unwrap(), [255, 255, 255, 255]);
+  let b = BMP::new_from_file("example/images/v2_565.bmp").unwrap();
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [255, 0, 0, 255]);
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [0, 0, 255, 255]);
+  let b = BMP::new_from_file("example/images/v3_argb.bmp").unwrap();
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [255, 0, 0, 128]);
+  assert_eq!(b.get_dib_header().unwrap().AlphaMask, Some(0xFF000000));
+  //missing and unknown fields are errors, not panics
+  let mut b = b;
+  let mut dib_header = b.get_dib_header().unwrap();
+  dib_header.compression = None;
+  assert!(matches!(dib_header.to_bytes(), Err(ErrorKind::InvalidParameter(_))));
+  assert!(matches!(b.set_dib_header(dib_header), Err(ErrorKind::InvalidParameter(_))));
+  let mut dib_header = b.get_dib_header().unwrap();
+  dib_header.compression = Some("BI_NOTHING".to_string());
+  assert!(matches!(b.set_dib_header(dib_header), Err(ErrorKind::InvalidParameter(_))));
+  let mut dib_header = b.get_dib_header().unwrap();
+  dib_header.AlphaMask = None;
+  assert!(matches!(b.set_dib_header(dib_header), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn os2_headers() {
+  let b = BMP::new_from_file("example/images/os2_16.bmp").unwrap();
+  let dib_header = b.get_dib_header().unwrap();
+  assert_eq!((dib_header.width, dib_header.height, dib_header.bitcount), (3, 1, 8));
+  assert!(dib_header.compression.is_none() && dib_header.Units.is_none());
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [0, 255, 0, 255]);
+  let b = BMP::new_from_file("example/images/os2_64.bmp").unwrap();
+  let dib_header = b.get_dib_header().unwrap();
+  assert_eq!((dib_header.Units, dib_header.Rendering, dib_header.Size1, dib_header.Size2), (Some(0), Some(1), Some(5), Some(7)));
+  assert_eq!((dib_header.ColorEncoding, dib_header.Identifier), (Some(0), Some(0x1234)));
+  assert!(dib_header.RedMask.is_none() && dib_header.CSType.is_none() && dib_header.Reserved.is_none());
+  assert_eq!(dib_header.Padding, Some(0));
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [0, 0, 255, 255]);
+  //OS/2 headers can be cut off after any field
+  for size in [20u32, 24, 36, 46, 48, 60] {
+    let mut bytes = b.contents[..14].to_vec();
+    bytes.extend(size.to_le_bytes());
+    bytes.extend(&b.contents[18..14+size as usize]);
+    bytes.extend(&b.contents[14+64..]);
+    let offset = b.get_header().bfOffBits-64+size;
+    let length = bytes.len() as u32;
+    bytes[2..6].copy_from_slice(&length.to_le_bytes());
+    bytes[10..14].copy_from_slice(&offset.to_le_bytes());
+    let cut = BMP::from_bytes(&bytes).unwrap();
+    let cut_header = cut.get_dib_header().unwrap();
+    assert!(cut_header.is_os2(), "{}", size);
+    assert_eq!(cut_header.compression.is_some(), size >= 20, "{}", size);
+    assert_eq!(cut_header.ClrUsed.is_some(), size >= 36, "{}", size);
+    assert_eq!(cut_header.Recording.is_some(), size >= 46, "{}", size);
+    assert_eq!(cut_header.ColorEncoding, if size >= 60 { Some(0) } else { None }, "{}", size);
+    assert_eq!(cut_header.to_bytes().unwrap(), bytes[14..14+size as usize].to_vec(), "{}", size);
+    assert_eq!(cut.get_color_of_pixel(0, 0).unwrap(), [0, 0, 255, 255], "{}", size);
+  }
+}
+
+#[test]
+fn png_decoding() {
+  //32x32 rgba with every filter type, split into two IDAT chunks, dynamic huffman codes
+  let b = BMP::new_from_file("example/images/png_rgba.bmp").unwrap();
+  assert_eq!(b.get_format(), "png");
+  for y in 0..32 {
+    for x in 0..32 {
+      assert_eq!(b.get_color_of_pixel(x, y).unwrap(), [(x*8) as u8, (y*8) as u8, ((x*y) % 256) as u8, (255-x-y) as u8]);
+    }
+  }
+  //9x9 4 bit palette, adam7 interlaced, stored blocks, index 0 is transparent
+  let image = BMP::new_from_file("example/images/png_palette_interlaced.bmp").unwrap().to_image().unwrap();
+  for y in 0..9 {
+    for x in 0..9 {
+      let index = ((x+2*y) % 16) as u8;
+      let alpha = if index == 0 { 0 } else { 255 };
+      assert_eq!(image.get_color_of_pixel(x, y).unwrap(), [index*16, 255-index*16, index, alpha]);
+    }
+  }
+  //16 bit grayscale with a transparent gray, fixed huffman codes
+  let b = BMP::new_from_file("example/images/png_gray16.bmp").unwrap();
+  assert_eq!(b.get_color_of_pixel(1, 0).unwrap(), [128, 128, 128, 0]);
+  assert_eq!(b.get_color_of_pixel(0, 1).unwrap(), [18, 18, 18, 255]);
+  assert_eq!(b.get_color_of_pixel(2, 1).unwrap(), [64, 64, 64, 255]);
+  //the png would need to be encoded again
+  let mut b = b;
+  assert_eq!(b.change_color_of_pixel(0, 0, [0, 0, 0, 255]), Err(ErrorKind::UnsupportedCompression("BI_PNG".to_string())));
+  //broken compressed data
+  let mut bytes = BMP::new_from_file("example/images/png_rgba.bmp").unwrap().to_bytes();
+  let length = bytes.len();
+  bytes.truncate(length-200);
+  bytes[2..6].copy_from_slice(&(length as u32-200).to_le_bytes());
+  bytes[34..38].copy_from_slice(&(length as u32-200-54).to_le_bytes());
+  assert!(matches!(BMP::from_bytes(&bytes).unwrap().get_color_of_pixel(0, 0), Err(ErrorKind::CorruptPixelData(_))));
+  //huge sizes are rejected before anything is allocated
+  let mut bytes = BMP::new_from_file("example/images/png_rgba.bmp").unwrap().to_bytes();
+  let ihdr = BMP::from_bytes(&bytes).unwrap().get_offset() as usize+16;
+  bytes[ihdr..ihdr+8].copy_from_slice(&[0xFF; 8]);
+  assert!(matches!(BMP::from_bytes(&bytes).unwrap().to_image(), Err(ErrorKind::CorruptHeader { .. })));
+  bytes[18..26].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0x7F]);
+  bytes[ihdr..ihdr+8].copy_from_slice(&[0x7F, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF]);
+  assert!(matches!(BMP::from_bytes(&bytes), Err(ErrorKind::CorruptHeader { field, .. }) if field == "height"));
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn jpeg_decoding() {
+  //32x16 baseline jpeg, 4:2:0 chroma subsampling and a restart interval of 1 MCU
+  let b = BMP::new_from_file("example/images/jpeg_420.bmp").unwrap();
+  assert_eq!(b.get_format(), "jpeg");
+  for y in 0..16 {
+    for x in 0..32 {
+      let color = b.get_color_of_pixel(x, y).unwrap();
+      let original = [x*8, y*16, (x+y)*5];
+      for channel in 0..3 {
+        assert!((i32::from(color[channel])-original[channel] as i32).abs() <= 12);
+      }
+      assert_eq!(color[3], 255);
+    }
+  }
+  //the end of image marker turned into a fill byte, so the file ends before the next marker
+  let mut bytes = fs::read("example/images/jpeg_420.bmp").unwrap();
+  let length = bytes.len();
+  bytes[length-1] = 0xFF;
+  assert!(matches!(BMP::from_bytes(&bytes).unwrap().get_color_of_pixel(0, 0), Err(ErrorKind::CorruptPixelData(_))));
+  //a few hundred bytes can not hold a 4000x4000 image, so it is rejected before anything that size is allocated
+  let mut bytes = fs::read("example/images/jpeg_420.bmp").unwrap();
+  let sof = bytes.windows(2).position(|marker| marker == [0xFF, 0xC0]).unwrap();
+  bytes[sof+5..sof+9].copy_from_slice(&[0x0F, 0xA0, 0x0F, 0xA0]);
+  bytes[18..22].copy_from_slice(&4000u32.to_le_bytes());
+  let height = i32::from_le_bytes(bytes[22..26].try_into().unwrap());
+  bytes[22..26].copy_from_slice(&(4000*height.signum()).to_le_bytes());
+  assert!(matches!(BMP::from_bytes(&bytes).unwrap().get_color_of_pixel(0, 0), Err(ErrorKind::CorruptPixelData(reason)) if reason.contains("too short")));
+}
+
+#[cfg(not(feature = "jpeg"))]
+#[test]
+fn jpeg_needs_feature() {
+  let b = BMP::new_from_file("example/images/jpeg_420.bmp").unwrap();
+  assert_eq!(b.get_color_of_pixel(0, 0), Err(ErrorKind::UnsupportedCompression("BI_JPEG".to_string())));
+}
+
+#[test]
+fn os2_compression() {
+  //compression 3 and 4 mean something else in OS/2 headers
+  let b = BMP::new_from_file("example/images/os2_huffman1d.bmp").unwrap();
+  assert_eq!(b.get_dib_header().unwrap().compression, Some("BI_HUFFMAN1D".to_string()));
+  //alternating white and black runs of each row, in the order they are stored (bottom up), with end of line codes and fill bits
+  let runs: [&[usize]; 5] = [&[1900], &[0, 1800, 100], &[3, 5, 64, 1, 127, 1700], &[0, 20, 1850, 30], &[1, 1, 2, 2, 63, 64, 65, 128, 1574]];
+  let image = b.to_image().unwrap();
+  for (line, row_runs) in runs.iter().enumerate() {
+    let mut x: usize = 0;
+    for (i, run) in row_runs.iter().enumerate() {
+      let color = if i % 2 == 0 { [255, 255, 255, 255] } else { [0, 0, 0, 255] };
+      for _ in 0..*run {
+        assert_eq!(image.get_color_of_pixel(x, 4-line).unwrap(), color, "{} {}", x, line);
+        x += 1;
+      }
+    }
+  }
+  let mut b = BMP::new_from_file("example/images/os2_rle24.bmp").unwrap();
+  assert_eq!(b.get_dib_header().unwrap().compression, Some("BI_RLE24".to_string()));
+  let black = [0, 0, 0, 255];
+  let white = [255, 255, 255, 255];
+  let red = [255, 0, 0, 255];
+  let green = [0, 255, 0, 255];
+  let blue = [0, 0, 255, 255];
+  let expected = [[black, white, white, white, black], [red, green, white, green, green], [blue, blue, blue, blue, blue]];
+  for y in 0..3 {
+    for x in 0..5 {
+      assert_eq!(b.get_color_of_pixel(x, y).unwrap(), expected[y][x]);
+    }
+  }
+  assert_eq!(b.change_color_of_pixel(0, 0, red), Err(ErrorKind::UnsupportedCompression("BI_RLE24".to_string())));
+  //the windows meaning of 4 is still used for other headers, and 5 is not valid in OS/2 headers
+  let mut bytes = b.to_bytes();
+  bytes[30] = 5;
+  assert!(matches!(BMP::from_bytes(&bytes), Err(ErrorKind::CorruptHeader { offset: 30, .. })));
+}
+
+#[test]
+fn color_profiles() {
+  let mut b = BMP::new(2, 2, None);
+  assert_eq!(b.get_color_profile(), Err(ErrorKind::DoesNotExist));
+  let icc: Vec<u8> = (0..=255).collect();
+  b.set_color_profile(ColorProfile::Embedded(icc.clone())).unwrap();
+  let b2 = BMP::from_bytes(&b.to_bytes()).unwrap();
+  assert_eq!(b2.get_color_profile().unwrap(), ColorProfile::Embedded(icc));
+  let dib_header = b2.get_dib_header().unwrap();
+  assert_eq!(dib_header.CSType, Some(ColorSpaceType::ProfileEmbedded));
+  //ProfileData is from the start of the DIB header, and the profile goes after the pixel array
+  assert_eq!((dib_header.ProfileData, dib_header.ProfileSize), (Some(124+16), Some(256)));
+  assert_eq!(b2.get_header().bfSize as usize, b2.contents.len());
+  assert_eq!(b2.get_color_of_pixel(1, 1).unwrap(), [255, 255, 255, 255]);
+  //the old profile is replaced
+  b.set_color_profile(ColorProfile::Linked("C:\\Color\\print.icc".to_string())).unwrap();
+  assert_eq!(b.get_color_profile().unwrap(), ColorProfile::Linked("C:\\Color\\print.icc".to_string()));
+  assert_eq!(b.contents.len(), 14+124+16+19);
+  //only the v5 header has profile fields
+  let mut b = BMP::new_from_file("example/images/core_24bit.bmp").unwrap();
+  assert_eq!(b.get_color_profile(), Err(ErrorKind::DoesNotExist));
+  assert!(matches!(b.set_color_profile(ColorProfile::Embedded(vec![0])), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn calibrated_rgb() {
+  let b = BMP::new_from_file("example/images/example.bmp").unwrap();
+  let dib_header = b.get_dib_header().unwrap();
+  assert_eq!((dib_header.CSType, dib_header.Intent), (Some(ColorSpaceType::Srgb), Some(RenderingIntent::Images)));
+  let endpoints = dib_header.get_endpoints().unwrap();
+  assert!((endpoints[0][0]-0.64).abs() < 0.0001 && (endpoints[2][2]-0.79).abs() < 0.0001);
+  //create a calibrated rgb image
+  let mut b = BMP::new(2, 2, None);
+  b.set_color_space_type(ColorSpaceType::CalibratedRgb).unwrap();
+  b.set_endpoints([[0.4124, 0.2126, 0.0193], [0.3576, 0.7152, 0.1192], [0.1805, 0.0722, 0.9505]]).unwrap();
+  b.set_gamma([2.2, 2.2, 1.8]).unwrap();
+  b.set_intent(RenderingIntent::AbsColorimetric).unwrap();
+  let b = BMP::from_bytes(&b.to_bytes()).unwrap();
+  let dib_header = b.get_dib_header().unwrap();
+  assert_eq!(dib_header.CSType, Some(ColorSpaceType::CalibratedRgb));
+  assert_eq!(dib_header.Intent, Some(RenderingIntent::AbsColorimetric));
+  assert_eq!(b.contents[14+108..14+112], [8, 0, 0, 0]);
+  assert!((dib_header.get_endpoints().unwrap()[1][1]-0.7152).abs() < 0.000001);
+  assert_eq!(dib_header.GammaBlue, Some(0x0001CCCD));
+  assert!((dib_header.get_gamma().unwrap()[0]-2.2).abs() < 0.0001);
+  assert!(matches!(BMP::new(1, 1, None).set_gamma([-1.0, 1.0, 1.0]), Err(ErrorKind::InvalidParameter(_))));
+  //the 40 byte header has no color space
+  let mut b = BMP::new_from_file("example/images/palette_8bit.bmp").unwrap();
+  assert!(b.get_dib_header().unwrap().get_gamma().is_none());
+  assert!(matches!(b.set_color_space_type(ColorSpaceType::Srgb), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn encoder_options() {
+  let mut b = BMP::new(3, 5, Some([255, 255, 255, 255]));
+  b.change_color_of_pixel(0, 0, [255, 0, 0, 255]).unwrap();
+  b.change_color_of_pixel(4, 2, [0, 0, 255, 255]).unwrap();
+  //24 bit BITMAPINFOHEADER, for the clipboard
+  let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 24, compression: EncoderCompression::Rgb, dpi: [300, 150], ..Default::default() };
+  let b2 = b.encode(&options).unwrap();
+  let dib_header = b2.get_dib_header().unwrap();
+  assert_eq!((dib_header.size, dib_header.bitcount, dib_header.compression.clone()), (40, 24, Some("BI_RGB".to_string())));
+  assert_eq!((dib_header.XPelsPerMeter, dib_header.YPelsPerMeter), (Some(11811), Some(5906)));
+  //rows are padded to 16 bytes
+  assert_eq!(b2.contents.len(), 14+40+16*3);
+  assert_eq!(b2.get_color_of_pixel(0, 0).unwrap(), [255, 0, 0, 255]);
+  assert_eq!(b2.get_color_of_pixel(4, 2).unwrap(), [0, 0, 255, 255]);
+  //the original is not changed
+  assert_eq!(b.get_dib_header().unwrap().bitcount, 32);
+  //every combination keeps the (opaque, in the default color tables) pixels
+  let combinations = [
+    (HeaderVersion::Core, 1, EncoderCompression::Rgb, false),
+    (HeaderVersion::Core, 8, EncoderCompression::Rgb, false),
+    (HeaderVersion::Info, 4, EncoderCompression::Rgb, true),
+    (HeaderVersion::Info, 16, EncoderCompression::Rgb, false),
+    (HeaderVersion::Info, 32, EncoderCompression::Bitfields, true),
+    (HeaderVersion::V4, 16, EncoderCompression::Bitfields, false),
+    (HeaderVersion::V5, 32, EncoderCompression::Rgb, true),
+  ];
+  let mut bw = BMP::new(3, 5, Some([0, 0, 0, 255]));
+  bw.change_color_of_pixel(1, 2, [255, 255, 255, 255]).unwrap();
+  for (header_version, bitcount, compression, top_down) in combinations {
+    let source = if bitcount == 1 { &bw } else { &b };
+    let options = EncoderOptions { header_version, bitcount, compression, top_down, ..Default::default() };
+    let b2 = BMP::from_bytes(&source.encode(&options).unwrap().to_bytes()).unwrap();
+    assert_eq!(b2.get_dib_header().unwrap().bitcount, bitcount);
+    assert_eq!(b2.get_dib_header().unwrap().height < 0, top_down);
+    for y in 0..3 {
+      for x in 0..5 {
+        assert_eq!(b2.get_color_of_pixel(x, y).unwrap(), source.get_color_of_pixel(x, y).unwrap());
+      }
+    }
+  }
+  //colors are mapped to the closest color in the given color table
+  let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 4, compression: EncoderCompression::Rgb, palette: Some(ColorTable::RGBQUAD(vec![[0, 0, 0, 0], [0, 0, 200, 0]])), ..Default::default() };
+  let b2 = b.encode(&options).unwrap();
+  assert_eq!(b2.get_dib_header().unwrap().ClrUsed, Some(2));
+  assert_eq!(b2.get_color_of_pixel(0, 0).unwrap(), [200, 0, 0, 255]);
+  assert_eq!(b2.get_color_of_pixel(4, 2).unwrap(), [0, 0, 0, 255]);
+  //invalid combinations
+  let options = EncoderOptions { header_version: HeaderVersion::Core, bitcount: 32, compression: EncoderCompression::Rgb, ..Default::default() };
+  assert!(matches!(b.encode(&options), Err(ErrorKind::InvalidParameter(_))));
+  let options = EncoderOptions { bitcount: 24, ..Default::default() };
+  assert!(matches!(b.encode(&options), Err(ErrorKind::InvalidParameter(_))));
+  let options = EncoderOptions { bitcount: 12, ..Default::default() };
+  assert_eq!(b.encode(&options).map(|_| ()), Err(ErrorKind::UnsupportedBitDepth(12)));
+  //the defaults are the format BMP::new() creates
+  let b2 = BMP::new_with_options(2, 2, None, &Default::default()).unwrap();
+  assert_eq!(b2.contents.len(), BMP::new(2, 2, None).contents.len());
+  assert_eq!(b2.get_bit_masks().unwrap().alpha, 0xFF000000);
+  assert_eq!(b2.get_color_of_pixel(1, 1).unwrap(), [255, 255, 255, 255]);
+}
+
+#[test]
+fn rle_encoding() {
+  //one row, with each pixel set to a color table index
+  let row_image = |indexes: &[usize], palette: &[[u8; 4]]| -> BMP {
+    let mut b = BMP::new(1, indexes.len() as u32, None);
+    for (x, index) in indexes.iter().enumerate() {
+      b.change_color_of_pixel(x as u16, 0, palette[*index]).unwrap();
+    }
+    return b;
+  };
+  let palette: Vec<[u8; 4]> = (0..16).map(|i| [i*16, 0, 0, 255]).collect();
+  let color_table = ColorTable::RGBQUAD(palette.iter().map(|rgba| [rgba[2], rgba[1], rgba[0], 0]).collect());
+  let rle8 = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 8, compression: EncoderCompression::Rle, palette: Some(color_table.clone()), ..Default::default() };
+  let rle4 = EncoderOptions { bitcount: 4, ..rle8.clone() };
+  //an encoded run, then an absolute run (cheaper than 5 encoded runs of 1), then end of bitmap
+  let b = row_image(&[1, 1, 1, 1, 2, 3, 4, 5, 6], &palette).encode(&rle8).unwrap();
+  let offset = b.get_offset() as usize;
+  assert_eq!(b.contents[offset..], [4, 1, 0, 5, 2, 3, 4, 5, 6, 0, 0, 1]);
+  let dib_header = b.get_dib_header().unwrap();
+  assert_eq!((dib_header.compression, dib_header.sizeimage), (Some("BI_RLE8".to_string()), Some(12)));
+  //RLE4 encoded runs can alternate between two indexes
+  let b = row_image(&[1, 2, 1, 2, 1, 3], &palette).encode(&rle4).unwrap();
+  let offset = b.get_offset() as usize;
+  assert_eq!(b.contents[offset..], [5, 0x12, 1, 0x30, 0, 1]);
+  assert_eq!(b.get_dib_header().unwrap().compression, Some("BI_RLE4".to_string()));
+  //a flat image with some detail shrinks, and decodes to the same pixels
+  let mut b = BMP::new(40, 300, Some(palette[3]));
+  b.draw_rectangle(Some(palette[9]), Some(palette[12]), [10, 5], [200, 30]).unwrap();
+  for x in 0..40 {
+    b.change_color_of_pixel(x*7, 20, palette[(x % 16) as usize]).unwrap();
+  }
+  for options in [&rle8, &rle4] {
+    let b2 = BMP::from_bytes(&b.encode(options).unwrap().to_bytes()).unwrap();
+    assert!(b2.contents.len() < 40*300/2);
+    assert_eq!(b2.get_dib_header().unwrap().sizeimage, Some(b2.contents.len() as u32-b2.get_offset()));
+    assert_eq!(b2.to_image().unwrap().get_pixels(), b.to_image().unwrap().get_pixels());
+  }
+  //re-encoding an RLE8 file keeps its color table and pixels
+  let b = BMP::new_from_file("example/images/rle8.bmp").unwrap();
+  let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 8, compression: EncoderCompression::Rle, ..Default::default() };
+  let b2 = b.encode(&options).unwrap();
+  assert_eq!(b2.get_color_table().unwrap(), b.get_color_table().unwrap());
+  assert_eq!(b2.to_image().unwrap().get_pixels(), b.to_image().unwrap().get_pixels());
+  //RLE images are bottom up
+  let options = EncoderOptions { top_down: true, ..rle8 };
+  assert!(matches!(b.encode(&options), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn quantization() {
+  //5 colors, which both quantizers keep exactly
+  let colors: [[u8; 4]; 5] = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [20, 20, 20, 255], [250, 250, 250, 255]];
+  let mut image = BMP::new(4, 5, None).to_image().unwrap();
+  for y in 0..4u16 {
+    for x in 0..5u16 {
+      image.change_color_of_pixel(x, y, colors[usize::from((x+y) % 5)]).unwrap();
+    }
+  }
+  for palette in [image.median_cut_palette(8).unwrap(), image.octree_palette(8).unwrap()] {
+    let mut rgb = palette.to_rgb();
+    rgb.sort();
+    let mut expected: Vec<[u8; 3]> = colors.iter().map(|rgba| [rgba[0], rgba[1], rgba[2]]).collect();
+    expected.sort();
+    assert_eq!(rgb, expected);
+    assert!(matches!(palette, ColorTable::RGBQUAD(_)));
+    let indexed = image.remap(&palette, None).unwrap();
+    let color = palette.to_rgb()[usize::from(indexed.get_index(2, 3).unwrap())];
+    assert_eq!(color, [255, 0, 0]);
+  }
+  //a gradient is reduced to the requested number of colors, which stay close to the original colors
+  let mut image = BMP::new(32, 64, None).to_image().unwrap();
+  for y in 0..32u16 {
+    for x in 0..64u16 {
+      image.change_color_of_pixel(x, y, [(x*4) as u8, (y*8) as u8, 128, 255]).unwrap();
+    }
+  }
+  for palette in [image.median_cut_palette(16).unwrap(), image.octree_palette(16).unwrap()] {
+    let rgb = palette.to_rgb();
+    assert!(rgb.len() > 8 && rgb.len() <= 16);
+    let indexed = image.remap(&palette, None).unwrap();
+    let mut error: u64 = 0;
+    for y in 0..32 {
+      for x in 0..64 {
+        let original = image.get_color_of_pixel(x, y).unwrap();
+        let mapped = rgb[usize::from(indexed.get_index(x, y).unwrap())];
+        error += (0..3).map(|i| u64::from(original[i].abs_diff(mapped[i]))).sum::<u64>();
+      }
+    }
+    assert!(error/(32*64) <= 36);
+    //and can be written as a 4 bit image
+    let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 4, compression: EncoderCompression::Rgb, ..Default::default() };
+    let b = indexed.encode(&options).unwrap();
+    assert_eq!(b.get_color_table().unwrap(), palette);
+    assert_eq!(b.get_color_of_pixel(63, 0).unwrap()[..3], rgb[usize::from(indexed.get_index(63, 0).unwrap())]);
+  }
+  //transparent pixels get the transparent index, and only they do
+  let mut image = BMP::new(2, 3, Some([255, 255, 255, 255])).to_image().unwrap();
+  image.change_color_of_pixel(0, 0, [0, 0, 0, 0]).unwrap();
+  image.change_color_of_pixel(1, 1, [0, 0, 0, 255]).unwrap();
+  let palette = ColorTable::RGBQUAD(vec![[0, 0, 0, 0], [255, 255, 255, 0], [0, 0, 0, 0]]);
+  let indexed = image.remap(&palette, Some(2)).unwrap();
+  assert_eq!(indexed.get_indexes(), [2, 1, 1, 1, 0, 1]);
+  assert_eq!(indexed.get_transparent_index(), Some(2));
+  assert_eq!(image.remap(&palette, None).unwrap().get_indexes(), [0, 1, 1, 1, 0, 1]);
+  //the quantizers ignore fully transparent pixels
+  assert_eq!(image.octree_palette(4).unwrap().to_rgb().len(), 2);
+  assert!(matches!(image.remap(&palette, Some(3)), Err(ErrorKind::InvalidParameter(_))));
+  assert!(matches!(image.median_cut_palette(0), Err(ErrorKind::InvalidParameter(_))));
+  assert!(matches!(image.octree_palette(257), Err(ErrorKind::InvalidParameter(_))));
+  let options = EncoderOptions { bitcount: 24, compression: EncoderCompression::Rgb, ..Default::default() };
+  assert!(matches!(indexed.encode(&options), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn dithering() {
+  let black_and_white = ColorTable::RGBQUAD(vec![[0, 0, 0, 0], [255, 255, 255, 0]]);
+  //a quarter of the pixels of a dark gray become white, instead of none
+  let gray = BMP::new(32, 32, Some([64, 64, 64, 255])).to_image().unwrap();
+  let white_fraction = |indexed: &IndexedImage| -> f32 { indexed.get_indexes().iter().filter(|index| **index == 1).count() as f32/1024.0 };
+  assert_eq!(white_fraction(&gray.remap(&black_and_white, None).unwrap()), 0.0);
+  for dither in [Dither::FloydSteinberg, Dither::JarvisJudiceNinke, Dither::Sierra, Dither::Bayer(2), Dither::Bayer(4), Dither::Bayer(8), Dither::Bayer(16)] {
+    let fraction = white_fraction(&gray.remap_dithered(&black_and_white, None, dither).unwrap());
+    assert!((fraction-0.25).abs() < 0.02, "{:?} {}", dither, fraction);
+  }
+  //atkinson loses some of the error, so dark grays get darker
+  let fraction = white_fraction(&gray.remap_dithered(&black_and_white, None, Dither::Atkinson).unwrap());
+  assert!(fraction > 0.1 && fraction < 0.25);
+  //ordered dithering is the same pattern everywhere
+  let indexed = gray.remap_dithered(&black_and_white, None, Dither::Bayer(4)).unwrap();
+  for y in 0..32 {
+    for x in 0..32 {
+      assert_eq!(indexed.get_index(x, y).unwrap(), indexed.get_index(x % 4, y % 4).unwrap());
+    }
+  }
+  //transparent pixels keep the transparent index, and do not spread error
+  let mut image = gray.clone();
+  image.change_color_of_pixel(0, 0, [255, 255, 255, 0]).unwrap();
+  let palette = ColorTable::RGBQUAD(vec![[0, 0, 0, 0], [255, 255, 255, 0], [0, 255, 0, 0]]);
+  let indexed = image.remap_dithered(&palette, Some(2), Dither::FloydSteinberg).unwrap();
+  assert_eq!(indexed.get_index(0, 0).unwrap(), 2);
+  assert_eq!(indexed.get_indexes().iter().filter(|index| **index == 2).count(), 1);
+  //1 bit output through the encoder
+  let b = BMP::new(32, 32, Some([64, 64, 64, 255]));
+  let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 1, compression: EncoderCompression::Rgb, palette: Some(black_and_white.clone()), dither: Dither::FloydSteinberg, ..Default::default() };
+  let pixels = b.encode(&options).unwrap().to_image().unwrap().get_pixels().to_vec();
+  let white = pixels.chunks_exact(4).filter(|rgba| rgba[0] == 255).count();
+  assert!((white as i32-256).abs() < 20);
+  //16 bit pixels average out to a red that 5 bits cannot store
+  let b = BMP::new(32, 32, Some([4, 0, 0, 255]));
+  for (dither, expected) in [(Dither::None, 0.0), (Dither::FloydSteinberg, 4.0), (Dither::Bayer(8), 4.0)] {
+    let options = EncoderOptions { bitcount: 16, compression: EncoderCompression::Rgb, dither, ..Default::default() };
+    let pixels = b.encode(&options).unwrap().to_image().unwrap().get_pixels().to_vec();
+    let average_red = pixels.chunks_exact(4).map(|rgba| f32::from(rgba[0])).sum::<f32>()/1024.0;
+    assert!((average_red-expected).abs() < 1.0, "{:?} {}", dither, average_red);
+  }
+  assert!(matches!(gray.remap_dithered(&black_and_white, None, Dither::Bayer(3)), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn orientation() {
+  let red = [255, 0, 0, 255];
+  //negative height, so the top row is stored first
+  let mut b = BMP::new(-3, 4, Some([255, 255, 255, 255]));
+  assert!(b.is_top_down().unwrap());
+  assert_eq!(b.get_color_of_pixel(3, 2).unwrap(), [255, 255, 255, 255]);
+  assert_eq!(b.get_color_of_pixel(3, 3), Err(ErrorKind::OutOfBounds));
+  assert_eq!(b.change_color_of_pixel(0, 3, red), Err(ErrorKind::OutOfBounds));
+  b.change_color_of_pixel(1, 2, red).unwrap();
+  assert_eq!(b.get_color_of_pixel(1, 2).unwrap(), red);
+  let offset = b.get_offset() as usize;
+  assert_eq!(b.contents[offset+2*16+4..offset+2*16+8], [0, 0, 255, 255]);
+  b.draw_line(red, [0, 0], [3, 0]).unwrap();
+  let pixels = b.to_image().unwrap().get_pixels().to_vec();
+  //flipping the storage does not change the image
+  b.set_top_down(false).unwrap();
+  assert!(!b.is_top_down().unwrap());
+  assert_eq!(b.get_dib_header().unwrap().height, 3);
+  assert_eq!(b.to_image().unwrap().get_pixels(), pixels);
+  assert_eq!(b.contents[offset+4..offset+8], [0, 0, 255, 255]);
+  assert_eq!(b.get_color_of_pixel(3, 0).unwrap(), red);
+  b.set_top_down(true).unwrap();
+  assert_eq!(b.to_image().unwrap().get_pixels(), pixels);
+  //color table images
+  let palette = BMP::new_from_file("example/images/palette_8bit.bmp").unwrap();
+  let options = EncoderOptions { header_version: HeaderVersion::Info, bitcount: 8, compression: EncoderCompression::Rgb, top_down: true, ..Default::default() };
+  let mut b = palette.encode(&options).unwrap();
+  let dib_header = b.get_dib_header().unwrap();
+  let (x, y) = (dib_header.width as usize-1, dib_header.height.unsigned_abs() as usize-1);
+  let color = b.get_color_of_pixel(0, 0).unwrap();
+  b.change_color_of_pixel(x as u16, y as u16, color).unwrap();
+  assert_eq!(b.get_color_of_pixel(x, y).unwrap(), color);
+  b.set_top_down(false).unwrap();
+  assert_eq!(b.get_color_of_pixel(x, y).unwrap(), color);
+  //RLE and BITMAPCOREHEADER images cannot be top down
+  let mut b = BMP::new_from_file("example/images/rle8.bmp").unwrap();
+  assert_eq!(b.set_top_down(true), Err(ErrorKind::UnsupportedCompression("BI_RLE8".to_string())));
+  let mut b = BMP::new_from_file("example/images/core_24bit.bmp").unwrap();
+  assert!(matches!(b.set_top_down(true), Err(ErrorKind::InvalidParameter(_))));
+  assert_eq!(b.set_top_down(false), Ok(()));
+}
+
+#[test]
+fn resizing() {
+  let red = [255, 0, 0, 255];
+  let blue = [0, 0, 255, 255];
+  //nearest doubles each pixel
+  let mut b = BMP::new(2, 2, Some(red));
+  b.change_color_of_pixel(1, 0, blue).unwrap();
+  let b2 = b.resize(4, 4, ResizeFilter::Nearest).unwrap();
+  let dib_header = b2.get_dib_header().unwrap();
+  assert_eq!((dib_header.width, dib_header.height), (4, 4));
+  //the file would be more than 4 GiB
+  assert!(matches!(b.resize(70000, 70000, ResizeFilter::Nearest), Err(ErrorKind::InvalidParameter(_))));
+  assert!(matches!(b.to_image().unwrap().scale_to_fit(u32::MAX, u32::MAX, ResizeFilter::Nearest), Err(ErrorKind::InvalidParameter(_))));
+  //heights that do not fit in the signed header field are too large too, not zero
+  assert_eq!(b.resize(1, 1 << 31, ResizeFilter::Nearest).err(), Some(ErrorKind::InvalidParameter("a 1x2147483648 image is too large".to_string())));
+  for y in 0..4 {
+    for x in 0..4 {
+      let expected = if x >= 2 && y < 2 { blue } else { red };
+      assert_eq!(b2.get_color_of_pixel(x, y).unwrap(), expected);
+    }
+  }
+  //a flat color stays the same with every filter, even when the filter has negative lobes
+  let flat = BMP::new(7, 9, Some([30, 140, 220, 255]));
+  for filter in [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::CatmullRom, ResizeFilter::Mitchell, ResizeFilter::Lanczos3] {
+    for (width, height) in [(3, 2), (20, 31)] {
+      let image = flat.resize(width, height, filter).unwrap().to_image().unwrap();
+      assert!(image.get_pixels().chunks_exact(4).all(|rgba| rgba == [30, 140, 220, 255]), "{:?}", filter);
+    }
+  }
+  //transparent pixels do not bleed their color into opaque ones
+  let mut b = BMP::new(1, 2, Some(red));
+  b.change_color_of_pixel(1, 0, [0, 255, 0, 0]).unwrap();
+  let color = b.resize(1, 1, ResizeFilter::Bilinear).unwrap().get_color_of_pixel(0, 0).unwrap();
+  assert_eq!(color, [255, 0, 0, 128]);
+  //shrinking a gradient keeps it in order
+  let mut image = BMP::new(1, 64, None).to_image().unwrap();
+  for x in 0..64u16 {
+    image.change_color_of_pixel(x, 0, [(x*4) as u8, 0, 0, 255]).unwrap();
+  }
+  let shrunk = image.resize(8, 1, ResizeFilter::Lanczos3).unwrap();
+  let reds: Vec<u8> = (0..8).map(|x| shrunk.get_color_of_pixel(x, 0).unwrap()[0]).collect();
+  assert!(reds.windows(2).all(|pair| pair[0] < pair[1]));
+  //pixel 3 is centered between source pixels 27 and 28
+  assert!(reds[3].abs_diff(110) <= 2);
+  //keeping the aspect ratio
+  let mut wide = BMP::new(100, 200, Some([0, 255, 0, 255]));
+  wide.draw_rectangle(Some(red), None, [0, 0], [49, 99]).unwrap();
+  wide.draw_rectangle(Some(blue), None, [150, 0], [199, 99]).unwrap();
+  let fit = wide.scale_to_fit(50, 50, ResizeFilter::Bilinear).unwrap();
+  assert_eq!((fit.get_dib_header().unwrap().width, fit.get_dib_header().unwrap().height), (50, 25));
+  //filling cuts off the red and blue sides
+  let fill = wide.scale_to_fill(50, 50, ResizeFilter::Mitchell).unwrap();
+  assert_eq!((fill.get_dib_header().unwrap().width, fill.get_dib_header().unwrap().height), (50, 50));
+  assert_eq!(fill.get_color_of_pixel(25, 25).unwrap(), [0, 255, 0, 255]);
+  let edge = fill.get_color_of_pixel(0, 25).unwrap();
+  assert!(edge[1] > 200 && edge[0] < 60);
+  assert!(matches!(wide.resize(0, 10, ResizeFilter::Bilinear), Err(ErrorKind::InvalidParameter(_))));
+  assert!(matches!(wide.scale_to_fit(10, 0, ResizeFilter::Bilinear), Err(ErrorKind::InvalidParameter(_))));
+}
+
+#[test]
+fn crop_and_canvas() {
+  //the headers are rewritten for the new size
+  let check_headers = |b: &BMP, width: u32, height: u32| {
+    let dib_header = b.get_dib_header().unwrap();
+    assert_eq!((dib_header.width, dib_header.height.unsigned_abs()), (width, height));
+    assert_eq!(b.get_header().bfSize as usize, b.contents.len());
+    let row_length = (u32::from(dib_header.bitcount)*width).div_ceil(32)*4;
+    if let Some(sizeimage) = dib_header.sizeimage {
+      assert_eq!(sizeimage, row_length*height);
+    }
+  };
+  for file in ["core_24bit.bmp", "palette_8bit.bmp", "indexed_4bit_odd.bmp", "bitfields_565.bmp", "rgb_555.bmp", "example.bmp"] {
+    let b = BMP::new_from_file(&format!("example/images/{}", file)).unwrap();
+    let image = b.to_image().unwrap();
+    let (width, height) = (image.get_width(), image.get_height());
+    let cropped = b.crop(1, 1, width-1, height-1).unwrap();
+    check_headers(&cropped, width-1, height-1);
+    let dib_header = cropped.get_dib_header().unwrap();
+    assert_eq!((dib_header.size, dib_header.bitcount), (b.get_dib_header().unwrap().size, b.get_dib_header().unwrap().bitcount));
+    let cropped = cropped.to_image().unwrap();
+    for y in 0..height as usize-1 {
+      for x in 0..width as usize-1 {
+        assert_eq!(cropped.get_color_of_pixel(x, y).unwrap(), image.get_color_of_pixel(x+1, y+1).unwrap(), "{}", file);
+      }
+    }
+  }
+  //top down images stay top down
+  let red = [255, 0, 0, 255];
+  let mut b = BMP::new(-4, 6, Some([0, 0, 255, 255]));
+  b.change_color_of_pixel(5, 3, red).unwrap();
+  let cropped = b.crop(4, 2, 2, 2).unwrap();
+  assert!(cropped.is_top_down().unwrap());
+  assert_eq!(cropped.get_color_of_pixel(1, 1).unwrap(), red);
+  assert_eq!(b.crop(4, 2, 3, 2).map(|_| ()), Err(ErrorKind::OutOfBounds));
+  assert!(matches!(b.crop(0, 0, 0, 2), Err(ErrorKind::InvalidParameter(_))));
+  //growing and shrinking the canvas
+  let white = [255, 255, 255, 255];
+  let grown = b.resize_canvas(10, 8, Anchor::Center, white).unwrap();
+  check_headers(&grown, 10, 8);
+  assert_eq!(grown.get_color_of_pixel(7, 5).unwrap(), red);
+  assert_eq!(grown.get_color_of_pixel(1, 1).unwrap(), white);
+  let grown = b.resize_canvas(10, 8, Anchor::BottomRight, white).unwrap();
+  assert_eq!(grown.get_color_of_pixel(9, 7).unwrap(), red);
+  assert_eq!(grown.get_color_of_pixel(3, 3).unwrap(), white);
+  let shrunk = b.resize_canvas(2, 1, Anchor::BottomRight, white).unwrap();
+  check_headers(&shrunk, 2, 1);
+  assert_eq!(shrunk.get_color_of_pixel(1, 0).unwrap(), red);
+  //padding
+  let padded = b.pad(1, 2, 3, 4, white).unwrap();
+  check_headers(&padded, 12, 8);
+  assert_eq!(padded.get_color_of_pixel(4+5, 1+3).unwrap(), red);
+  assert_eq!(padded.get_color_of_pixel(4, 1).unwrap(), [0, 0, 255, 255]);
+  assert_eq!(padded.get_color_of_pixel(3, 1).unwrap(), white);
+  assert_eq!(padded.get_color_of_pixel(11, 7).unwrap(), white);
+  //an embedded color profile moves with the end of the pixel array
+  let icc: Vec<u8> = (0..100).collect();
+  b.set_color_profile(ColorProfile::Embedded(icc.clone())).unwrap();
+  let padded = b.pad(0, 0, 5, 0, white).unwrap();
+  assert_eq!(padded.get_color_profile().unwrap(), ColorProfile::Embedded(icc));
+  //canvases whose file would not fit in 4 GiB are rejected
+  assert!(matches!(b.pad(0, 200_000_000, 4, 0, white), Err(ErrorKind::InvalidParameter(_))));
+  assert!(matches!(b.pad(0, 0, 70000, 70000, white), Err(ErrorKind::InvalidParameter(_))));
+  //compressed images become 32 bit
+  let rle = BMP::new_from_file("example/images/rle8.bmp").unwrap();
+  let cropped = rle.crop(0, 0, 2, 2).unwrap();
+  assert_eq!(cropped.get_dib_header().unwrap().bitcount, 32);
+  assert_eq!(cropped.get_color_of_pixel(1, 1).unwrap(), rle.get_color_of_pixel(1, 1).unwrap());
+}
+
+#[test]
+fn flips_and_right_angle_rotations() {
+  type SourceOf = Box<dyn Fn(usize, usize) -> (usize, usize)>;
+  for file in ["core_24bit.bmp", "palette_8bit.bmp", "indexed_1bit_odd.bmp", "indexed_2bit_odd.bmp", "indexed_4bit_odd.bmp", "bitfields_565.bmp", "bitfields_1010102.bmp", "v3_argb.bmp", "rle8.bmp"] {
+    let b = BMP::new_from_file(&format!("example/images/{}", file)).unwrap();
+    let image = b.to_image().unwrap();
+    let (width, height) = (image.get_width() as usize, image.get_height() as usize);
+    let rotated90 = b.rotate90().unwrap();
+    let rotated270 = b.rotate270().unwrap();
+    let rotations: [(BMP, [usize; 2], SourceOf); 5] = [
+      (b.flip_horizontal().unwrap(), [width, height], Box::new(move |x, y| (width-x-1, y))),
+      (b.flip_vertical().unwrap(), [width, height], Box::new(move |x, y| (x, height-y-1))),
+      (rotated90.clone(), [height, width], Box::new(move |x, y| (y, height-x-1))),
+      (b.rotate180().unwrap(), [width, height], Box::new(move |x, y| (width-x-1, height-y-1))),
+      (rotated270.clone(), [height, width], Box::new(move |x, y| (width-y-1, x))),
+    ];
+    for (rotated, [new_width, new_height], source_of) in rotations {
+      let rotated = rotated.to_image().unwrap();
+      assert_eq!([rotated.get_width() as usize, rotated.get_height() as usize], [new_width, new_height], "{}", file);
+      for y in 0..new_height {
+        for x in 0..new_width {
+          let (source_x, source_y) = source_of(x, y);
+          assert_eq!(rotated.get_color_of_pixel(x, y).unwrap(), image.get_color_of_pixel(source_x, source_y).unwrap(), "{}", file);
+        }
+      }
+    }
+    //the stored bits are untouched, so going all the way around gives back the same file (compressed images become 32 bit)
+    if file != "rle8.bmp" {
+      assert_eq!(rotated90.rotate90().unwrap().rotate180().unwrap().contents, b.contents, "{}", file);
+      assert_eq!(rotated270.rotate90().unwrap().contents, b.contents, "{}", file);
+      assert_eq!(b.flip_horizontal().unwrap().flip_horizontal().unwrap().contents, b.contents, "{}", file);
+      assert_eq!(b.flip_vertical().unwrap().flip_horizontal().unwrap().contents, b.rotate180().unwrap().contents, "{}", file);
+    }
+  }
+  //the resolution is swapped along with the dimensions
+  let mut b = BMP::new(-2, 3, Some([0, 0, 255, 255]));
+  let mut dib_header = b.get_dib_header().unwrap();
+  dib_header.XPelsPerMeter = Some(1000);
+  dib_header.YPelsPerMeter = Some(2000);
+  b.set_dib_header(dib_header).unwrap();
+  b.change_color_of_pixel(2, 1, [255, 0, 0, 255]).unwrap();
+  let rotated = b.rotate90().unwrap();
+  let dib_header = rotated.get_dib_header().unwrap();
+  assert_eq!((dib_header.width, dib_header.height), (2, -3));
+  assert_eq!((dib_header.XPelsPerMeter, dib_header.YPelsPerMeter), (Some(2000), Some(1000)));
+  assert_eq!(rotated.get_color_of_pixel(0, 2).unwrap(), [255, 0, 0, 255]);
+  let rotated = b.to_image().unwrap().rotate270().unwrap().to_bmp().unwrap();
+  assert_eq!(rotated.get_dib_header().unwrap().XPelsPerMeter, Some(2000));
+  assert_eq!(rotated.get_color_of_pixel(1, 0).unwrap(), [255, 0, 0, 255]);
+}
+
+#[test]
+fn rotation_with_options() {
+  let image = BMP::new_from_file("example/images/indexed_4bit_odd.bmp").unwrap().to_image().unwrap();
+  //right angles land exactly on pixels
+  let nearest = RotateOptions { filter: ResizeFilter::Nearest, expand: true, ..RotateOptions::default() };
+  let rotated = image.rotate_with_options(90.0, &nearest).unwrap();
+  assert_eq!((rotated.get_width(), rotated.get_height()), (3, 5));
+  assert_eq!(rotated.get_pixels(), image.rotate90().unwrap().get_pixels());
+  let cubic = RotateOptions { filter: ResizeFilter::CatmullRom, ..RotateOptions::default() };
+  assert_eq!(image.rotate_with_options(0.0, &cubic).unwrap().get_pixels(), image.get_pixels());
+  //expanding fits the whole image, and the rest is the background
+  let red = [255, 0, 0, 255];
+  let square = BMP::new(10, 10, Some(red)).to_image().unwrap();
+  let blue = [0, 0, 255, 255];
+  let options = RotateOptions { expand: true, background: blue, ..RotateOptions::default() };
+  let rotated = square.rotate_with_options(45.0, &options).unwrap();
+  assert_eq!((rotated.get_width(), rotated.get_height()), (15, 15));
+  assert_eq!(rotated.get_color_of_pixel(7, 7).unwrap(), red);
+  assert_eq!(rotated.get_color_of_pixel(7, 1).unwrap(), red);
+  assert_eq!(rotated.get_color_of_pixel(1, 1).unwrap(), blue);
+  //edges are blended with the background
+  let edge = rotated.get_color_of_pixel(4, 3).unwrap();
+  assert!(edge[0] > 0 && edge[2] > 0, "{:?}", edge);
+  //without expanding the corners are cut off
+  let rotated = square.rotate_with_options(45.0, &RotateOptions::default()).unwrap();
+  assert_eq!((rotated.get_width(), rotated.get_height()), (10, 10));
+  assert_eq!(rotated.get_color_of_pixel(0, 0).unwrap(), [0, 0, 0, 0]);
+  assert_eq!(rotated.get_color_of_pixel(5, 0).unwrap(), red);
+  //the canvas keeping rotate has no holes
+  let mut b = BMP::new(21, 21, Some(red));
+  b.rotate(30.0, Some([10, 10])).unwrap();
+  for y in 3..18 {
+    for x in 3..18 {
+      assert_eq!(b.get_color_of_pixel(x, y).unwrap(), red);
+    }
+  }
+  assert_eq!(b.get_color_of_pixel(0, 0).unwrap(), [255, 255, 255, 0]);
+  let rotated = b.rotate_with_options(30.0, &RotateOptions { expand: true, ..RotateOptions::default() }).unwrap();
+  assert_eq!(rotated.get_dib_header().unwrap().bitcount, 32);
+}
+
+#[test]
+fn affine_and_perspective_transforms() {
+  let image = BMP::new_from_file("example/images/indexed_4bit_odd.bmp").unwrap().to_image().unwrap();
+  let (width, height) = (image.get_width(), image.get_height());
+  let nearest = TransformOptions { filter: ResizeFilter::Nearest, ..TransformOptions::default() };
+  let identity = Transform::Affine([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+  assert_eq!(image.transform(&identity, &TransformOptions::default()).unwrap().get_pixels(), image.get_pixels());
+  //composing transforms
+  let rotation = Transform::rotation(90.0, [0.0, 0.0]).then(&Transform::translation(f64::from(height), 0.0));
+  let options = TransformOptions { size: Some([height, width]), ..nearest.clone() };
+  assert_eq!(image.transform(&rotation, &options).unwrap().get_pixels(), image.rotate90().unwrap().get_pixels());
+  let options = TransformOptions { size: Some([width*2, height*2]), ..nearest.clone() };
+  let scaled = image.transform(&Transform::scaling(2.0, 2.0), &options).unwrap();
+  assert_eq!(scaled.get_color_of_pixel(7, 4).unwrap(), image.get_color_of_pixel(3, 2).unwrap());
+  let sheared = image.transform(&Transform::shear(0.5, 0.0), &nearest).unwrap();
+  assert_eq!(sheared.get_color_of_pixel(3, 2).unwrap(), image.get_color_of_pixel(2, 2).unwrap());
+  //edge modes
+  let translation = Transform::translation(2.0, 1.0);
+  let edge = |edge: EdgeMode| -> Image { image.transform(&translation, &TransformOptions { edge, ..nearest.clone() }).unwrap() };
+  let transparent = edge(EdgeMode::Transparent);
+  assert_eq!(transparent.get_color_of_pixel(2, 1).unwrap(), image.get_color_of_pixel(0, 0).unwrap());
+  assert_eq!(transparent.get_color_of_pixel(0, 0).unwrap(), [0, 0, 0, 0]);
+  assert_eq!(edge(EdgeMode::Clamp).get_color_of_pixel(0, 0).unwrap(), image.get_color_of_pixel(0, 0).unwrap());
+  assert_eq!(edge(EdgeMode::Wrap).get_color_of_pixel(0, 0).unwrap(), image.get_color_of_pixel(width as usize-2, height as usize-1).unwrap());
+  assert_eq!(edge(EdgeMode::Mirror).get_color_of_pixel(0, 0).unwrap(), image.get_color_of_pixel(1, 0).unwrap());
+  assert!(matches!(image.transform(&Transform::scaling(0.0, 1.0), &nearest), Err(ErrorKind::InvalidParameter(_))));
+  //solving for the homography
+  let square = [[0.0, 0.0], [40.0, 0.0], [40.0, 40.0], [0.0, 40.0]];
+  let quad = [[6.0, 3.0], [35.0, 8.0], [38.0, 37.0], [2.0, 30.0]];
+  let homography = Transform::perspective_from_points(square, quad).unwrap();
+  for (from, to) in square.iter().zip(quad) {
+    let point = homography.apply(*from).unwrap();
+    assert!((point[0]-to[0]).abs() < 1e-9 && (point[1]-to[1]).abs() < 1e-9, "{:?} {:?}", point, to);
+  }
+  let undone = homography.then(&homography.inverse().unwrap()).apply([13.0, 27.0]).unwrap();
+  assert!((undone[0]-13.0).abs() < 1e-9 && (undone[1]-27.0).abs() < 1e-9);
+  //the homography times -1 is the same mapping
+  let Transform::Perspective(matrix) = homography else { panic!() };
+  let negated = Transform::Perspective(matrix.map(|row| row.map(|value| -value)));
+  assert_eq!(negated.apply([13.0, 27.0]), homography.apply([13.0, 27.0]));
+  assert_eq!(image.transform(&negated, &nearest).unwrap().get_pixels(), image.transform(&homography, &nearest).unwrap().get_pixels());
+  let line = [[0.0, 0.0], [1.0, 1.0], [2.0, 2.0], [0.0, 5.0]];
+  assert!(matches!(Transform::perspective_from_points(line, quad), Err(ErrorKind::InvalidParameter(_))));
+  //warping a "document" and straightening it back
+  let (red, blue) = ([255, 0, 0, 255], [0, 0, 255, 255]);
+  let mut document = BMP::new(40, 40, Some(red));
+  document.draw_rectangle(Some(blue), None, [20, 0], [39, 39]).unwrap();
+  let photo = document.warp_perspective(square, quad, &TransformOptions::default()).unwrap();
+  assert_eq!(photo.get_color_of_pixel(0, 39).unwrap(), [0, 0, 0, 0]);
+  let straightened = photo.warp_perspective(quad, square, &TransformOptions::default()).unwrap();
+  for y in 4..36 {
+    assert_eq!(straightened.get_color_of_pixel(8, y).unwrap(), red);
+    assert_eq!(straightened.get_color_of_pixel(31, y).unwrap(), blue);
+  }
+}
@@ -0,0 +1,159 @@
+//Modified Huffman (CCITT Group 3 one dimensional) decoding, for OS/2 BMP files with Huffman 1D compression
+//https://www.itu.int/rec/T-REC-T.4
+//each row is coded as alternating runs of white and black pixels, starting with white
+use std::collections::HashMap;
+use super::bmp::ErrorKind;
+
+//terminating codes, for runs of 0 to 63
+const WHITE_TERMINATING_CODES: [&str; 64] = [
+  "00110101", "000111", "0111", "1000", "1011", "1100", "1110", "1111",
+  "10011", "10100", "00111", "01000", "001000", "000011", "110100", "110101",
+  "101010", "101011", "0100111", "0001100", "0001000", "0010111", "0000011", "0000100",
+  "0101000", "0101011", "0010011", "0100100", "0011000", "00000010", "00000011", "00011010",
+  "00011011", "00010010", "00010011", "00010100", "00010101", "00010110", "00010111", "00101000",
+  "00101001", "00101010", "00101011", "00101100", "00101101", "00000100", "00000101", "00001010",
+  "00001011", "01010010", "01010011", "01010100", "01010101", "00100100", "00100101", "01011000",
+  "01011001", "01011010", "01011011", "01001010", "01001011", "00110010", "00110011", "00110100",
+];
+const BLACK_TERMINATING_CODES: [&str; 64] = [
+  "0000110111", "010", "11", "10", "011", "0011", "0010", "00011",
+  "000101", "000100", "0000100", "0000101", "0000111", "00000100", "00000111", "000011000",
+  "0000010111", "0000011000", "0000001000", "00001100111", "00001101000", "00001101100", "00000110111", "00000101000",
+  "00000010111", "00000011000", "000011001010", "000011001011", "000011001100", "000011001101", "000001101000", "000001101001",
+  "000001101010", "000001101011", "000011010010", "000011010011", "000011010100", "000011010101", "000011010110", "000011010111",
+  "000001101100", "000001101101", "000011011010", "000011011011", "000001010100", "000001010101", "000001010110", "000001010111",
+  "000001100100", "000001100101", "000001010010", "000001010011", "000000100100", "000000110111", "000000111000", "000000100111",
+  "000000101000", "000001011000", "000001011001", "000000101011", "000000101100", "000001011010", "000001100110", "000001100111",
+];
+//make up codes, for runs of 64 to 1728 in steps of 64
+const WHITE_MAKE_UP_CODES: [&str; 27] = [
+  "11011", "10010", "010111", "0110111", "00110110", "00110111", "01100100", "01100101", "01101000",
+  "01100111", "011001100", "011001101", "011010010", "011010011", "011010100", "011010101", "011010110",
+  "011010111", "011011000", "011011001", "011011010", "011011011", "010011000", "010011001", "010011010",
+  "011000", "010011011",
+];
+const BLACK_MAKE_UP_CODES: [&str; 27] = [
+  "0000001111", "000011001000", "000011001001", "000001011011", "000000110011", "000000110100", "000000110101", "0000001101100", "0000001101101",
+  "0000001001010", "0000001001011", "0000001001100", "0000001001101", "0000001110010", "0000001110011", "0000001110100", "0000001110101",
+  "0000001110110", "0000001110111", "0000001010010", "0000001010011", "0000001010100", "0000001010101", "0000001011010", "0000001011011",
+  "0000001100100", "0000001100101",
+];
+//extra make up codes shared by white and black, for runs of 1792 to 2560 in steps of 64
+const EXTENDED_MAKE_UP_CODES: [&str; 13] = [
+  "00000001000", "00000001100", "00000001101", "000000010010", "000000010011", "000000010100", "000000010101",
+  "000000010110", "000000010111", "000000011100", "000000011101", "000000011110", "000000011111",
+];
+
+fn corrupt(reason: &str) -> ErrorKind {
+  return ErrorKind::CorruptPixelData("Huffman 1D: ".to_string()+reason);
+}
+
+//codes (as the code length and value) to run lengths
+fn code_table(terminating: &[&str; 64], make_up: &[&str; 27]) -> HashMap<(usize, u32), usize> {
+  let mut table: HashMap<(usize, u32), usize> = HashMap::new();
+  let mut add = |code: &str, run: usize| {
+    table.insert((code.len(), u32::from_str_radix(code, 2).unwrap()), run);
+  };
+  for (run, code) in terminating.iter().enumerate() {
+    add(code, run);
+  }
+  for (i, code) in make_up.iter().enumerate() {
+    add(code, (i+1)*64);
+  }
+  for (i, code) in EXTENDED_MAKE_UP_CODES.iter().enumerate() {
+    add(code, 1792+i*64);
+  }
+  return table;
+}
+
+//read most significant bit first
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> BitReader<'a> {
+  fn bit(&mut self) -> Option<u32> {
+    let byte = self.data.get(self.position/8)?;
+    let bit = (byte >> (7-self.position % 8)) & 1;
+    self.position += 1;
+    return Some(u32::from(bit));
+  }
+  //skip an end of line code (at least 11 zeros then a one, the extra zeros are fill bits), if there is one
+  fn skip_end_of_line(&mut self) {
+    let start = self.position;
+    let mut zeros: usize = 0;
+    while let Some(0) = self.bit() {
+      zeros += 1;
+    }
+    if zeros < 11 {
+      self.position = start;
+    }
+  }
+}
+
+//decode `height` rows of `width` pixels, in the order they are stored, with 0 for white and 1 for black.
+//if the data ends early, the rest of the pixels are white
+pub fn decode_huffman_1d(data: &[u8], width: usize, height: usize) -> Result<Vec<Vec<u8>>, ErrorKind> {
+  let white_codes = code_table(&WHITE_TERMINATING_CODES, &WHITE_MAKE_UP_CODES);
+  let black_codes = code_table(&BLACK_TERMINATING_CODES, &BLACK_MAKE_UP_CODES);
+  //rows are only allocated as they are reached, and allocation failures are errors instead of aborts
+  let new_row = || -> Result<Vec<u8>, ErrorKind> {
+    let mut row: Vec<u8> = Vec::new();
+    if row.try_reserve_exact(width).is_err() {
+      return Err(corrupt("image is too large"));
+    }
+    row.resize(width, 0);
+    return Ok(row);
+  };
+  let mut rows: Vec<Vec<u8>> = Vec::new();
+  if rows.try_reserve_exact(height).is_err() {
+    return Err(corrupt("image is too large"));
+  }
+  let mut reader = BitReader { data, position: 0 };
+  'rows: while rows.len() < height {
+    reader.skip_end_of_line();
+    let mut row = new_row()?;
+    let mut x: usize = 0;
+    let mut color: u8 = 0;
+    while x < width {
+      //a run is any number of make up codes followed by a terminating code
+      let mut run: usize = 0;
+      loop {
+        let codes = if color == 0 { &white_codes } else { &black_codes };
+        let mut code: u32 = 0;
+        let mut length: usize = 0;
+        let code_run = loop {
+          code = match reader.bit() {
+            Some(bit) => (code << 1) | bit,
+            None => {
+              rows.push(row);
+              break 'rows;
+            },
+          };
+          length += 1;
+          if let Some(code_run) = codes.get(&(length, code)) {
+            break *code_run;
+          }
+          if length >= 13 {
+            return Err(corrupt("invalid code"));
+          }
+        };
+        run += code_run;
+        if code_run < 64 {
+          break;
+        }
+      }
+      for pixel in row.iter_mut().skip(x).take(run) {
+        *pixel = color;
+      }
+      x += run;
+      color ^= 1;
+    }
+    rows.push(row);
+  }
+  while rows.len() < height {
+    rows.push(new_row()?);
+  }
+  return Ok(rows);
+}
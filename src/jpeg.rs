@@ -0,0 +1,518 @@
+//Baseline JPEG decoding, for BMP files with BI_JPEG compression, where the pixel array is a whole JPEG file
+//Only enabled with the "jpeg" feature
+//https://www.w3.org/Graphics/JPEG/itu-t81.pdf
+use super::bmp::ErrorKind;
+use std::sync::OnceLock;
+
+//index in the 8x8 block of each coefficient, in the order they are stored
+const ZIGZAG: [usize; 64] = [
+  0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5,
+  12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+  35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+  58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+fn corrupt(reason: &str) -> ErrorKind {
+  return ErrorKind::CorruptPixelData("JPEG: ".to_string()+reason);
+}
+
+//a zeroed buffer, erroring instead of aborting if it can not be allocated
+fn zeroed(length: usize) -> Result<Vec<u8>, ErrorKind> {
+  let mut buffer: Vec<u8> = Vec::new();
+  if buffer.try_reserve_exact(length).is_err() {
+    return Err(corrupt("image is too large"));
+  }
+  buffer.resize(length, 0);
+  return Ok(buffer);
+}
+
+//entropy coded data is read most significant bit first, with 0xFF bytes followed by a stuffed 0x00
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+  bit_buffer: u32,
+  bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+  fn bit(&mut self) -> Result<u32, ErrorKind> {
+    if self.bit_count == 0 {
+      if self.position >= self.data.len() {
+        return Err(corrupt("entropy coded data ends early"));
+      }
+      let mut byte = self.data[self.position];
+      if byte == 0xFF {
+        let next = self.data.get(self.position+1).copied().unwrap_or(0xD9);
+        if next == 0 {
+          self.position += 2;
+        } else {
+          //a marker, which is left for the caller. Some encoders end the data early, so the rest is read as 0s
+          byte = 0;
+        }
+      } else {
+        self.position += 1;
+      }
+      self.bit_buffer = u32::from(byte);
+      self.bit_count = 8;
+    }
+    self.bit_count -= 1;
+    return Ok((self.bit_buffer >> self.bit_count) & 1);
+  }
+  fn bits(&mut self, num: u8) -> Result<u32, ErrorKind> {
+    let mut value: u32 = 0;
+    for _ in 0..num {
+      value = (value << 1) | self.bit()?;
+    }
+    return Ok(value);
+  }
+  //read `num` bits as a signed value, where values with the top bit unset are negative
+  fn receive_extend(&mut self, num: u8) -> Result<i32, ErrorKind> {
+    if num == 0 {
+      return Ok(0);
+    }
+    let value = self.bits(num)? as i32;
+    if value < 1 << (num-1) {
+      return Ok(value-(1 << num)+1);
+    }
+    return Ok(value);
+  }
+  //skip to the byte after the restart marker
+  fn restart(&mut self) -> Result<(), ErrorKind> {
+    self.bit_count = 0;
+    while self.position+1 < self.data.len() {
+      if self.data[self.position] == 0xFF && (0xD0..=0xD7).contains(&self.data[self.position+1]) {
+        self.position += 2;
+        return Ok(());
+      }
+      self.position += 1;
+    }
+    return Err(corrupt("missing restart marker"));
+  }
+}
+
+#[derive(Clone)]
+struct HuffmanTable {
+  //largest code of each length, or -1 if there are no codes of that length
+  max_codes: [i32; 17],
+  //added to a code to get the index of its value
+  value_offsets: [i32; 17],
+  values: Vec<u8>,
+}
+
+impl HuffmanTable {
+  fn new(counts: &[u8], values: &[u8]) -> HuffmanTable {
+    let mut max_codes: [i32; 17] = [-1; 17];
+    let mut value_offsets: [i32; 17] = [0; 17];
+    let mut code: i32 = 0;
+    let mut index: i32 = 0;
+    for length in 1..17 {
+      let count = i32::from(counts[length-1]);
+      value_offsets[length] = index-code;
+      index += count;
+      code += count;
+      if count > 0 {
+        max_codes[length] = code-1;
+      }
+      code <<= 1;
+    }
+    return HuffmanTable { max_codes, value_offsets, values: values.to_vec() };
+  }
+  fn decode(&self, reader: &mut BitReader) -> Result<u8, ErrorKind> {
+    let mut code: i32 = 0;
+    for length in 1..17 {
+      code = (code << 1) | reader.bit()? as i32;
+      if code <= self.max_codes[length] {
+        return match self.values.get((code+self.value_offsets[length]) as usize) {
+          Some(value) => Ok(*value),
+          None => Err(corrupt("invalid huffman code")),
+        };
+      }
+    }
+    return Err(corrupt("invalid huffman code"));
+  }
+}
+
+struct Component {
+  id: u8,
+  horizontal_sampling: usize,
+  vertical_sampling: usize,
+  quantization_table: usize,
+  dc_table: usize,
+  ac_table: usize,
+  //the sum of every difference, so it is wide enough not to overflow
+  dc_prediction: i64,
+  //the component is stored as whole blocks, so it can be wider and taller than the image.
+  //samples are only allocated at the first scan, once it is clear there is enough data for them
+  blocks_per_line: usize,
+  blocks_per_column: usize,
+  samples: Vec<u8>,
+}
+
+//tables that scans refer to by index
+struct Tables {
+  quantization: [[u16; 64]; 4],
+  dc: [Option<HuffmanTable>; 4],
+  ac: [Option<HuffmanTable>; 4],
+}
+
+struct Frame {
+  width: usize,
+  height: usize,
+  max_horizontal_sampling: usize,
+  max_vertical_sampling: usize,
+  mcus_per_line: usize,
+  mcus_per_column: usize,
+  components: Vec<Component>,
+}
+
+//cosines[x*8+u] = C(u)/2*cos((2x+1)uπ/16), calculated the first time they are needed
+fn idct_cosines() -> &'static [f32; 64] {
+  static COSINES: OnceLock<[f32; 64]> = OnceLock::new();
+  return COSINES.get_or_init(|| {
+    let mut cosines: [f32; 64] = [0.0; 64];
+    for x in 0..8 {
+      for u in 0..8 {
+        let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+        cosines[x*8+u] = scale/2.0*(((2*x+1) as f32)*(u as f32)*std::f32::consts::PI/16.0).cos();
+      }
+    }
+    cosines
+  });
+}
+
+//inverse discrete cosine transform of one block, then level shifted back to 0-255
+fn idct(coefficients: &[f32; 64]) -> [u8; 64] {
+  let cosines = idct_cosines();
+  //rows, then columns
+  let mut rows: [f32; 64] = [0.0; 64];
+  for v in 0..8 {
+    for x in 0..8 {
+      rows[v*8+x] = (0..8).map(|u| cosines[x*8+u]*coefficients[v*8+u]).sum();
+    }
+  }
+  let mut output: [u8; 64] = [0; 64];
+  for y in 0..8 {
+    for x in 0..8 {
+      let value: f32 = (0..8).map(|v| cosines[y*8+v]*rows[v*8+x]).sum();
+      output[y*8+x] = (value+128.0).round().clamp(0.0, 255.0) as u8;
+    }
+  }
+  return output;
+}
+
+//decode one block of a component, and write it into the component's samples
+fn decode_block(reader: &mut BitReader, component: &mut Component, block_x: usize, block_y: usize, tables: &Tables) -> Result<(), ErrorKind> {
+  let (dc_table, ac_table) = match (&tables.dc[component.dc_table], &tables.ac[component.ac_table]) {
+    (Some(dc_table), Some(ac_table)) => (dc_table, ac_table),
+    _ => return Err(corrupt("missing huffman table")),
+  };
+  let quantization_table = &tables.quantization[component.quantization_table];
+  let mut coefficients: [f32; 64] = [0.0; 64];
+  //the dc coefficient is stored as the difference from the previous block's
+  let dc_length = dc_table.decode(reader)?;
+  if dc_length > 16 {
+    return Err(corrupt("invalid dc coefficient length"));
+  }
+  component.dc_prediction += i64::from(reader.receive_extend(dc_length)?);
+  //16 bit quantization tables times 16 bit values can overflow an i32, so multiply as floats
+  coefficients[0] = component.dc_prediction as f32*f32::from(quantization_table[0]);
+  //ac coefficients are stored as a run of zeros followed by a value
+  let mut k: usize = 1;
+  while k < 64 {
+    let run_length = ac_table.decode(reader)?;
+    let run = (run_length >> 4) as usize;
+    let length = run_length & 0x0F;
+    if length == 0 {
+      if run == 15 {
+        //16 zeros
+        k += 16;
+        continue;
+      }
+      //end of block, the rest are zeros
+      break;
+    }
+    k += run;
+    if k > 63 {
+      return Err(corrupt("too many coefficients in block"));
+    }
+    coefficients[ZIGZAG[k]] = reader.receive_extend(length)? as f32*f32::from(quantization_table[k]);
+    k += 1;
+  }
+  if block_x >= component.blocks_per_line || block_y >= component.blocks_per_column {
+    //blocks outside of the component still need to be read, but are not kept
+    return Ok(());
+  }
+  let block = idct(&coefficients);
+  let line_length = component.blocks_per_line*8;
+  for y in 0..8 {
+    let start = (block_y*8+y)*line_length+block_x*8;
+    component.samples[start..start+8].copy_from_slice(&block[y*8..y*8+8]);
+  }
+  return Ok(());
+}
+
+//decode a JPEG file into top down rgba pixels, erroring if it is not the `width` and `height` the DIB header says
+pub fn decode_jpeg(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, ErrorKind> {
+  if data.len() < 2 || data[0] != 0xFF || data[1] != 0xD8 {
+    return Err(corrupt("missing start of image marker"));
+  }
+  let mut position: usize = 2;
+  let mut tables = Tables { quantization: [[0; 64]; 4], dc: [None, None, None, None], ac: [None, None, None, None] };
+  let mut frame: Option<Frame> = None;
+  let mut restart_interval: usize = 0;
+  //the Adobe APP14 segment says whether 3 component images are YCbCr (1) or RGB (0)
+  let mut adobe_transform: Option<u8> = None;
+  loop {
+    //markers can be padded with any number of 0xFF bytes
+    if position+1 >= data.len() || data[position] != 0xFF {
+      return Err(corrupt("expected a marker"));
+    }
+    while position+1 < data.len() && data[position+1] == 0xFF {
+      position += 1;
+    }
+    if position+1 >= data.len() {
+      return Err(corrupt("expected a marker"));
+    }
+    let marker = data[position+1];
+    position += 2;
+    match marker {
+      //end of image
+      0xD9 => break,
+      //markers with no segment
+      0x01 | 0xD0..=0xD8 => continue,
+      _ => {},
+    }
+    if position+2 > data.len() {
+      return Err(corrupt("segment ends after the end of the file"));
+    }
+    let length = u16::from_be_bytes([data[position], data[position+1]]) as usize;
+    if length < 2 || position+length > data.len() {
+      return Err(corrupt("segment ends after the end of the file"));
+    }
+    let segment = &data[position+2..position+length];
+    position += length;
+    match marker {
+      //define quantization tables
+      0xDB => {
+        let mut i: usize = 0;
+        while i < segment.len() {
+          let precision = segment[i] >> 4;
+          let table = (segment[i] & 0x0F) as usize;
+          let value_size: usize = if precision == 0 { 1 } else { 2 };
+          if table > 3 || i+1+64*value_size > segment.len() {
+            return Err(corrupt("invalid quantization table"));
+          }
+          for k in 0..64 {
+            let start = i+1+k*value_size;
+            tables.quantization[table][k] = if value_size == 1 { u16::from(segment[start]) } else { u16::from_be_bytes([segment[start], segment[start+1]]) };
+          }
+          i += 1+64*value_size;
+        }
+      },
+      //define huffman tables
+      0xC4 => {
+        let mut i: usize = 0;
+        while i < segment.len() {
+          let class = segment[i] >> 4;
+          let table = (segment[i] & 0x0F) as usize;
+          if table > 3 || i+17 > segment.len() {
+            return Err(corrupt("invalid huffman table"));
+          }
+          let counts = &segment[i+1..i+17];
+          let value_num: usize = counts.iter().map(|count| *count as usize).sum();
+          if i+17+value_num > segment.len() {
+            return Err(corrupt("invalid huffman table"));
+          }
+          let huffman_table = HuffmanTable::new(counts, &segment[i+17..i+17+value_num]);
+          if class == 0 {
+            tables.dc[table] = Some(huffman_table);
+          } else {
+            tables.ac[table] = Some(huffman_table);
+          }
+          i += 17+value_num;
+        }
+      },
+      //baseline and extended sequential huffman frames
+      0xC0 | 0xC1 => {
+        if segment.len() < 6 || segment[0] != 8 {
+          return Err(ErrorKind::UnsupportedCompression("BI_JPEG (only 8 bit precision is supported)".to_string()));
+        }
+        let jpeg_height = u16::from_be_bytes([segment[1], segment[2]]);
+        let jpeg_width = u16::from_be_bytes([segment[3], segment[4]]);
+        let component_num = segment[5] as usize;
+        if jpeg_width == 0 || jpeg_height == 0 {
+          return Err(corrupt("image has no width or height"));
+        }
+        //checked before the components are allocated
+        if u32::from(jpeg_width) != width || u32::from(jpeg_height) != height {
+          return Err(ErrorKind::embedded_size_mismatch(width, height, u32::from(jpeg_width), u32::from(jpeg_height)));
+        }
+        let (width, height) = (usize::from(jpeg_width), usize::from(jpeg_height));
+        if component_num != 1 && component_num != 3 {
+          return Err(ErrorKind::UnsupportedCompression(format!("BI_JPEG ({} components)", component_num)));
+        }
+        if segment.len() < 6+component_num*3 {
+          return Err(corrupt("frame header is too short"));
+        }
+        let mut components: Vec<Component> = Vec::new();
+        for c in 0..component_num {
+          let start = 6+c*3;
+          let horizontal_sampling = (segment[start+1] >> 4) as usize;
+          let vertical_sampling = (segment[start+1] & 0x0F) as usize;
+          if !(1..=4).contains(&horizontal_sampling) || !(1..=4).contains(&vertical_sampling) || segment[start+2] > 3 {
+            return Err(corrupt("invalid component"));
+          }
+          components.push(Component {
+            id: segment[start],
+            horizontal_sampling,
+            vertical_sampling,
+            quantization_table: segment[start+2] as usize,
+            dc_table: 0,
+            ac_table: 0,
+            dc_prediction: 0,
+            blocks_per_line: 0,
+            blocks_per_column: 0,
+            samples: Vec::new(),
+          });
+        }
+        let max_horizontal_sampling = components.iter().map(|component| component.horizontal_sampling).max().unwrap();
+        let max_vertical_sampling = components.iter().map(|component| component.vertical_sampling).max().unwrap();
+        let mcus_per_line = width.div_ceil(8*max_horizontal_sampling);
+        let mcus_per_column = height.div_ceil(8*max_vertical_sampling);
+        for component in &mut components {
+          component.blocks_per_line = mcus_per_line*component.horizontal_sampling;
+          component.blocks_per_column = mcus_per_column*component.vertical_sampling;
+        }
+        frame = Some(Frame { width, height, max_horizontal_sampling, max_vertical_sampling, mcus_per_line, mcus_per_column, components });
+      },
+      //progressive, lossless, hierarchical and arithmetic coded frames
+      0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+        return Err(ErrorKind::UnsupportedCompression("BI_JPEG (only baseline JPEGs are supported)".to_string()));
+      },
+      //define restart interval
+      0xDD => {
+        if segment.len() < 2 {
+          return Err(corrupt("invalid restart interval"));
+        }
+        restart_interval = u16::from_be_bytes([segment[0], segment[1]]) as usize;
+      },
+      0xEE if segment.len() >= 12 && segment.starts_with(b"Adobe") => {
+        adobe_transform = Some(segment[11]);
+      },
+      //start of scan
+      0xDA => {
+        let frame = match &mut frame {
+          Some(frame) => frame,
+          None => return Err(corrupt("scan before the frame header")),
+        };
+        let scan_component_num = *segment.first().unwrap_or(&0) as usize;
+        if scan_component_num == 0 || segment.len() < 1+scan_component_num*2 {
+          return Err(corrupt("invalid scan header"));
+        }
+        let mut scan_components: Vec<usize> = Vec::new();
+        for c in 0..scan_component_num {
+          let id = segment[1+c*2];
+          let tables = segment[2+c*2];
+          let component_index = match frame.components.iter().position(|component| component.id == id) {
+            Some(component_index) => component_index,
+            None => return Err(corrupt("scan refers to a component that does not exist")),
+          };
+          if tables >> 4 > 3 || tables & 0x0F > 3 {
+            return Err(corrupt("invalid huffman table"));
+          }
+          frame.components[component_index].dc_table = (tables >> 4) as usize;
+          frame.components[component_index].ac_table = (tables & 0x0F) as usize;
+          frame.components[component_index].dc_prediction = 0;
+          scan_components.push(component_index);
+        }
+        let mut reader = BitReader { data, position, bit_buffer: 0, bit_count: 0 };
+        //a scan with one component is not interleaved, and goes over just the blocks that are in the image
+        let (mcus_per_line, mcu_num) = if scan_components.len() == 1 {
+          let component = &frame.components[scan_components[0]];
+          let component_width = (frame.width*component.horizontal_sampling).div_ceil(frame.max_horizontal_sampling);
+          let component_height = (frame.height*component.vertical_sampling).div_ceil(frame.max_vertical_sampling);
+          (component_width.div_ceil(8), component_width.div_ceil(8)*component_height.div_ceil(8))
+        } else {
+          (frame.mcus_per_line, frame.mcus_per_line*frame.mcus_per_column)
+        };
+        //every block takes at least 2 bits (a dc and an end of block code), so tiny files can not claim huge images
+        let blocks_per_mcu: usize = if scan_components.len() == 1 {
+          1
+        } else {
+          scan_components.iter().map(|component_index| frame.components[*component_index].horizontal_sampling*frame.components[*component_index].vertical_sampling).sum()
+        };
+        if (mcu_num*blocks_per_mcu*2).div_ceil(8) > data.len()-position {
+          return Err(corrupt("scan data is too short for the image size"));
+        }
+        for component in &mut frame.components {
+          if component.samples.is_empty() {
+            component.samples = zeroed(component.blocks_per_line*component.blocks_per_column*64)?;
+          }
+        }
+        for mcu in 0..mcu_num {
+          if restart_interval != 0 && mcu != 0 && mcu % restart_interval == 0 {
+            reader.restart()?;
+            for component_index in &scan_components {
+              frame.components[*component_index].dc_prediction = 0;
+            }
+          }
+          let mcu_x = mcu % mcus_per_line;
+          let mcu_y = mcu / mcus_per_line;
+          if scan_components.len() == 1 {
+            decode_block(&mut reader, &mut frame.components[scan_components[0]], mcu_x, mcu_y, &tables)?;
+          } else {
+            for component_index in &scan_components {
+              let component = &mut frame.components[*component_index];
+              for v in 0..component.vertical_sampling {
+                for h in 0..component.horizontal_sampling {
+                  let (block_x, block_y) = (mcu_x*component.horizontal_sampling+h, mcu_y*component.vertical_sampling+v);
+                  decode_block(&mut reader, component, block_x, block_y, &tables)?;
+                }
+              }
+            }
+          }
+        }
+        //skip to the next marker
+        position = reader.position;
+        while position+1 < data.len() && !(data[position] == 0xFF && data[position+1] != 0 && !(0xD0..=0xD7).contains(&data[position+1])) {
+          position += 1;
+        }
+      },
+      //application data, comments, etc
+      _ => {},
+    }
+  }
+  let frame = match frame {
+    Some(frame) => frame,
+    None => return Err(corrupt("missing frame header")),
+  };
+  //upsample each component to the size of the image, and convert to rgb
+  if frame.components.iter().any(|component| component.samples.is_empty()) {
+    return Err(corrupt("missing scan"));
+  }
+  let mut pixels: Vec<u8> = zeroed(frame.width*frame.height*4)?;
+  let is_ycbcr = adobe_transform != Some(0);
+  for y in 0..frame.height {
+    for x in 0..frame.width {
+      let samples: Vec<f32> = frame.components.iter().map(|component| {
+        let component_x = x*component.horizontal_sampling/frame.max_horizontal_sampling;
+        let component_y = y*component.vertical_sampling/frame.max_vertical_sampling;
+        f32::from(component.samples[component_y*component.blocks_per_line*8+component_x])
+      }).collect();
+      let rgb: [f32; 3] = if samples.len() == 1 {
+        [samples[0], samples[0], samples[0]]
+      } else if is_ycbcr {
+        let (luma, cb, cr) = (samples[0], samples[1]-128.0, samples[2]-128.0);
+        [luma+1.402*cr, luma-0.344136*cb-0.714136*cr, luma+1.772*cb]
+      } else {
+        [samples[0], samples[1], samples[2]]
+      };
+      let start = (y*frame.width+x)*4;
+      for (channel, value) in rgb.iter().enumerate() {
+        pixels[start+channel] = value.round().clamp(0.0, 255.0) as u8;
+      }
+      pixels[start+3] = 255;
+    }
+  }
+  return Ok(pixels);
+}
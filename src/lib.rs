@@ -4,4 +4,8 @@
 //! The library can also parse the file header, DIB header and other parts of the file.
 //! Many useful utility functions are also included.
 //! 
-pub mod bmp;
\ No newline at end of file
+pub mod bmp;
+mod png;
+mod ccitt;
+#[cfg(feature = "jpeg")]
+mod jpeg;
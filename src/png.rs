@@ -0,0 +1,464 @@
+//PNG decoding, for BMP files with BI_PNG compression, where the pixel array is a whole PNG file
+//https://www.w3.org/TR/png/
+//zlib: https://www.rfc-editor.org/rfc/rfc1950
+//deflate: https://www.rfc-editor.org/rfc/rfc1951
+use super::bmp::ErrorKind;
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+fn corrupt(reason: &str) -> ErrorKind {
+  return ErrorKind::CorruptPixelData("PNG: ".to_string()+reason);
+}
+
+//deflate streams are read least significant bit first
+struct BitReader<'a> {
+  data: &'a [u8],
+  position: usize,
+  bit_buffer: u32,
+  bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+  fn new(data: &'a [u8]) -> BitReader<'a> {
+    return BitReader { data, position: 0, bit_buffer: 0, bit_count: 0 };
+  }
+  fn bits(&mut self, num: u32) -> Result<u32, ErrorKind> {
+    while self.bit_count < num {
+      if self.position >= self.data.len() {
+        return Err(corrupt("compressed data ends early"));
+      }
+      self.bit_buffer |= u32::from(self.data[self.position]) << self.bit_count;
+      self.position += 1;
+      self.bit_count += 8;
+    }
+    let value = self.bit_buffer & ((1u32 << num)-1);
+    self.bit_buffer >>= num;
+    self.bit_count -= num;
+    return Ok(value);
+  }
+  //stored blocks start at the next byte
+  fn align_to_byte(&mut self) {
+    //there are never more than 7 bits left in the buffer between reads
+    self.bit_buffer = 0;
+    self.bit_count = 0;
+  }
+}
+
+//canonical huffman code, stored as the number of codes of each length and the symbols in code order
+struct Huffman {
+  counts: [u16; 16],
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Result<Huffman, ErrorKind> {
+    let mut counts: [u16; 16] = [0; 16];
+    for length in lengths {
+      counts[*length as usize] += 1;
+    }
+    counts[0] = 0;
+    //offsets of the first symbol of each length
+    let mut offsets: [u16; 16] = [0; 16];
+    for length in 1..15 {
+      offsets[length+1] = offsets[length]+counts[length];
+    }
+    let mut symbols: Vec<u16> = vec![0; lengths.len()];
+    for (symbol, length) in lengths.iter().enumerate() {
+      if *length != 0 {
+        symbols[offsets[*length as usize] as usize] = symbol as u16;
+        offsets[*length as usize] += 1;
+      }
+    }
+    return Ok(Huffman { counts, symbols });
+  }
+  fn decode(&self, reader: &mut BitReader) -> Result<u16, ErrorKind> {
+    //codes of each length are consecutive, so keep track of the first code and first symbol index of the current length
+    let mut code: i32 = 0;
+    let mut first: i32 = 0;
+    let mut index: i32 = 0;
+    for length in 1..16 {
+      code |= reader.bits(1)? as i32;
+      let count = i32::from(self.counts[length]);
+      if code-count < first {
+        return Ok(self.symbols[(index+code-first) as usize]);
+      }
+      index += count;
+      first += count;
+      first <<= 1;
+      code <<= 1;
+    }
+    return Err(corrupt("invalid huffman code"));
+  }
+}
+
+const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+//order the code length code lengths are stored in, for dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+//decompress a zlib stream, erroring if it decompresses to more than `limit` bytes
+fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, ErrorKind> {
+  if data.len() < 2 {
+    return Err(corrupt("zlib stream is too short"));
+  }
+  let cmf = data[0];
+  let flg = data[1];
+  //compression method 8 is deflate, and the header is a multiple of 31
+  if cmf & 0x0F != 8 || (u16::from(cmf)*256+u16::from(flg)) % 31 != 0 {
+    return Err(corrupt("invalid zlib header"));
+  }
+  if flg & 0x20 != 0 {
+    return Err(corrupt("zlib preset dictionaries are not allowed"));
+  }
+  return inflate(&data[2..], limit);
+}
+
+//decompress a raw deflate stream, erroring if it decompresses to more than `limit` bytes
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, ErrorKind> {
+  let mut reader = BitReader::new(data);
+  let mut output: Vec<u8> = Vec::new();
+  loop {
+    let is_final = reader.bits(1)? == 1;
+    match reader.bits(2)? {
+      0 => {
+        //stored, not compressed
+        reader.align_to_byte();
+        let length = reader.bits(16)?;
+        let length_complement = reader.bits(16)?;
+        if length != !length_complement & 0xFFFF {
+          return Err(corrupt("stored block length does not match its complement"));
+        }
+        let start = reader.position;
+        if start+length as usize > data.len() {
+          return Err(corrupt("compressed data ends early"));
+        }
+        if output.len()+length as usize > limit {
+          return Err(too_big());
+        }
+        output.extend_from_slice(&data[start..start+length as usize]);
+        reader.position += length as usize;
+      },
+      1 => {
+        //fixed huffman codes
+        let mut literal_lengths: [u8; 288] = [8; 288];
+        literal_lengths[144..256].fill(9);
+        literal_lengths[256..280].fill(7);
+        let literals = Huffman::new(&literal_lengths)?;
+        let distances = Huffman::new(&[5; 30])?;
+        inflate_block(&mut reader, &mut output, &literals, &distances, limit)?;
+      },
+      2 => {
+        //dynamic huffman codes, the code lengths are themselves huffman coded
+        let literal_num = reader.bits(5)? as usize+257;
+        let distance_num = reader.bits(5)? as usize+1;
+        let code_length_num = reader.bits(4)? as usize+4;
+        let mut code_length_lengths: [u8; 19] = [0; 19];
+        for i in 0..code_length_num {
+          code_length_lengths[CODE_LENGTH_ORDER[i]] = reader.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_length_lengths)?;
+        let mut lengths: Vec<u8> = Vec::new();
+        while lengths.len() < literal_num+distance_num {
+          let symbol = code_lengths.decode(&mut reader)?;
+          match symbol {
+            0..=15 => lengths.push(symbol as u8),
+            16 => {
+              //repeat the previous length 3 to 6 times
+              let previous = match lengths.last() {
+                Some(previous) => *previous,
+                None => return Err(corrupt("repeated code length with no previous length")),
+              };
+              let repeat = reader.bits(2)?+3;
+              lengths.resize(lengths.len()+repeat as usize, previous);
+            },
+            17 => {
+              //repeat 0 3 to 10 times
+              let repeat = reader.bits(3)?+3;
+              lengths.resize(lengths.len()+repeat as usize, 0);
+            },
+            _ => {
+              //repeat 0 11 to 138 times
+              let repeat = reader.bits(7)?+11;
+              lengths.resize(lengths.len()+repeat as usize, 0);
+            },
+          }
+        }
+        if lengths.len() > literal_num+distance_num {
+          return Err(corrupt("too many code lengths"));
+        }
+        let literals = Huffman::new(&lengths[..literal_num])?;
+        let distances = Huffman::new(&lengths[literal_num..])?;
+        inflate_block(&mut reader, &mut output, &literals, &distances, limit)?;
+      },
+      _ => return Err(corrupt("invalid deflate block type")),
+    }
+    if is_final {
+      break;
+    }
+  }
+  return Ok(output);
+}
+
+//the decompressed data can not be longer than the image needs, so a small file can not decompress to gigabytes
+fn too_big() -> ErrorKind {
+  return corrupt("decompressed data is longer than the image");
+}
+
+//decode the literals and length/distance pairs of a huffman coded block
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, limit: usize) -> Result<(), ErrorKind> {
+  loop {
+    let symbol = literals.decode(reader)? as usize;
+    if symbol < 256 {
+      if output.len() >= limit {
+        return Err(too_big());
+      }
+      output.push(symbol as u8);
+    } else if symbol == 256 {
+      //end of block
+      return Ok(());
+    } else {
+      //copy `length` bytes from `distance` bytes back
+      let length_index = symbol-257;
+      if length_index >= LENGTH_BASES.len() {
+        return Err(corrupt("invalid length symbol"));
+      }
+      let length = LENGTH_BASES[length_index] as usize+reader.bits(u32::from(LENGTH_EXTRA_BITS[length_index]))? as usize;
+      let distance_index = distances.decode(reader)? as usize;
+      if distance_index >= DISTANCE_BASES.len() {
+        return Err(corrupt("invalid distance symbol"));
+      }
+      let distance = DISTANCE_BASES[distance_index] as usize+reader.bits(u32::from(DISTANCE_EXTRA_BITS[distance_index]))? as usize;
+      if distance > output.len() {
+        return Err(corrupt("distance is further back than the start of the data"));
+      }
+      if output.len()+length > limit {
+        return Err(too_big());
+      }
+      //the copied bytes can overlap with the bytes being written, so copy one at a time
+      let start = output.len()-distance;
+      for i in 0..length {
+        output.push(output[start+i]);
+      }
+    }
+  }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+  let p = i16::from(a)+i16::from(b)-i16::from(c);
+  let pa = (p-i16::from(a)).abs();
+  let pb = (p-i16::from(b)).abs();
+  let pc = (p-i16::from(c)).abs();
+  if pa <= pb && pa <= pc {
+    return a;
+  } else if pb <= pc {
+    return b;
+  } else {
+    return c;
+  }
+}
+
+//undo the filter of every row, `bpp` is the number of bytes per complete pixel (at least 1)
+fn unfilter(data: &[u8], row_length: usize, rows: usize, bpp: usize) -> Result<Vec<u8>, ErrorKind> {
+  if data.len() < (row_length+1)*rows {
+    return Err(corrupt("image data ends early"));
+  }
+  let mut output: Vec<u8> = vec![0; row_length*rows];
+  for y in 0..rows {
+    let filter_type = data[y*(row_length+1)];
+    let row = &data[y*(row_length+1)+1..(y+1)*(row_length+1)];
+    for x in 0..row_length {
+      let a: u8 = if x >= bpp { output[y*row_length+x-bpp] } else { 0 };
+      let b: u8 = if y > 0 { output[(y-1)*row_length+x] } else { 0 };
+      let c: u8 = if x >= bpp && y > 0 { output[(y-1)*row_length+x-bpp] } else { 0 };
+      let predictor: u8 = match filter_type {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((u16::from(a)+u16::from(b))/2) as u8,
+        4 => paeth(a, b, c),
+        _ => return Err(corrupt("invalid filter type")),
+      };
+      output[y*row_length+x] = row[x].wrapping_add(predictor);
+    }
+  }
+  return Ok(output);
+}
+
+//decode a PNG file into top down rgba pixels, erroring if it is not the `width` and `height` the DIB header says
+pub fn decode_png(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, ErrorKind> {
+  if data.len() < 8 || data[..8] != PNG_SIGNATURE {
+    return Err(corrupt("missing PNG signature"));
+  }
+  let mut position: usize = 8;
+  let mut header: Option<[u32; 5]> = None;
+  let mut palette: Vec<[u8; 4]> = Vec::new();
+  let mut transparency: Vec<u8> = Vec::new();
+  let mut compressed: Vec<u8> = Vec::new();
+  let mut interlaced = false;
+  //chunks: 4 byte length, 4 byte type, data, 4 byte crc
+  while position+8 <= data.len() {
+    let length = u32::from_be_bytes(data[position..position+4].try_into().unwrap()) as usize;
+    let chunk_type = &data[position+4..position+8];
+    let start = position+8;
+    if start+length > data.len() {
+      return Err(corrupt("chunk ends after the end of the file"));
+    }
+    let chunk = &data[start..start+length];
+    match chunk_type {
+      b"IHDR" => {
+        if length < 13 {
+          return Err(corrupt("IHDR chunk is too short"));
+        }
+        let png_width = u32::from_be_bytes(chunk[0..4].try_into().unwrap());
+        let png_height = u32::from_be_bytes(chunk[4..8].try_into().unwrap());
+        //checked before anything the size of the image is allocated
+        if png_width != width || png_height != height {
+          return Err(ErrorKind::embedded_size_mismatch(width, height, png_width, png_height));
+        }
+        //bit depth, color type, then compression method and filter method which are always 0
+        header = Some([png_width, png_height, u32::from(chunk[8]), u32::from(chunk[9]), 0]);
+        interlaced = chunk[12] == 1;
+      },
+      b"PLTE" => {
+        palette = chunk.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect();
+      },
+      b"tRNS" => {
+        transparency = chunk.to_vec();
+      },
+      b"IDAT" => {
+        compressed.extend_from_slice(chunk);
+      },
+      b"IEND" => {
+        break;
+      },
+      _ => {},
+    }
+    position = start+length+4;
+  }
+  let [width, height, bit_depth, color_type, _] = match header {
+    Some(header) => header,
+    None => return Err(corrupt("missing IHDR chunk")),
+  };
+  //samples per pixel
+  let channels: u32 = match color_type {
+    0 => 1,
+    2 => 3,
+    3 => 1,
+    4 => 2,
+    6 => 4,
+    _ => return Err(corrupt("invalid color type")),
+  };
+  let valid_bit_depth = match color_type {
+    0 => [1, 2, 4, 8, 16].contains(&bit_depth),
+    3 => [1, 2, 4, 8].contains(&bit_depth),
+    _ => bit_depth == 8 || bit_depth == 16,
+  };
+  if !valid_bit_depth {
+    return Err(corrupt("invalid bit depth"));
+  }
+  if color_type == 3 {
+    //alpha values for the palette entries, the rest are opaque
+    for (entry, alpha) in palette.iter_mut().zip(transparency.iter()) {
+      entry[3] = *alpha;
+    }
+  }
+  //for grayscale and rgb, tRNS is a single color (as 16 bit samples) that is fully transparent
+  let transparent_color: Option<Vec<u16>> = if (color_type == 0 || color_type == 2) && transparency.len() >= channels as usize*2 {
+    Some(transparency.chunks_exact(2).take(channels as usize).map(|sample| u16::from_be_bytes([sample[0], sample[1]])).collect())
+  } else {
+    None
+  };
+  //Adam7 interlacing has 7 passes over smaller images, each with a starting x and y, and step between pixels
+  let passes: Vec<[u32; 4]> = if interlaced {
+    vec![[0, 0, 8, 8], [4, 0, 8, 8], [0, 4, 4, 8], [2, 0, 4, 4], [0, 2, 2, 4], [1, 0, 2, 2], [0, 1, 1, 2]]
+  } else {
+    vec![[0, 0, 1, 1]]
+  };
+  let bits_per_pixel = channels*bit_depth;
+  let too_large = || ErrorKind::InvalidParameter(format!("a {}x{} image is too large", width, height));
+  //the width and height of each pass, and the length of its rows in bytes (without the filter type byte)
+  let mut pass_sizes: Vec<[usize; 3]> = Vec::new();
+  let mut data_length: usize = 0;
+  for [x_start, y_start, x_step, y_step] in passes.iter().copied() {
+    let pass_width = (width-x_start.min(width)).div_ceil(x_step) as usize;
+    let pass_height = (height-y_start.min(height)).div_ceil(y_step) as usize;
+    let row_length = pass_width.checked_mul(bits_per_pixel as usize).ok_or_else(too_large)?.div_ceil(8);
+    if pass_width > 0 && pass_height > 0 {
+      data_length = (row_length+1).checked_mul(pass_height).and_then(|length| length.checked_add(data_length)).ok_or_else(too_large)?;
+    }
+    pass_sizes.push([pass_width, pass_height, row_length]);
+  }
+  let decompressed = zlib_decompress(&compressed, data_length)?;
+  if decompressed.len() < data_length {
+    return Err(corrupt("image data ends early"));
+  }
+  let mut pixels: Vec<u8> = vec![0; (width as usize).checked_mul(height as usize).and_then(|length| length.checked_mul(4)).ok_or_else(too_large)?];
+  let bpp = (bits_per_pixel as usize).div_ceil(8);
+  let max_sample: u32 = (1 << bit_depth)-1;
+  let mut data_position: usize = 0;
+  for ([x_start, y_start, x_step, y_step], [pass_width, pass_height, row_length]) in passes.into_iter().zip(pass_sizes) {
+    if pass_width == 0 || pass_height == 0 {
+      continue;
+    }
+    let pass_data_length = (row_length+1)*pass_height;
+    if data_position+pass_data_length > decompressed.len() {
+      return Err(corrupt("image data ends early"));
+    }
+    let rows = unfilter(&decompressed[data_position..data_position+pass_data_length], row_length, pass_height, bpp)?;
+    data_position += pass_data_length;
+    for py in 0..pass_height {
+      let row = &rows[py*row_length..(py+1)*row_length];
+      for px in 0..pass_width {
+        //read the raw samples of the pixel
+        let mut samples: [u16; 4] = [0; 4];
+        for (channel, sample) in samples.iter_mut().enumerate().take(channels as usize) {
+          let bit_position = (px*channels as usize+channel)*bit_depth as usize;
+          *sample = if bit_depth == 16 {
+            u16::from_be_bytes([row[bit_position/8], row[bit_position/8+1]])
+          } else {
+            //samples smaller than a byte are packed starting from the highest bits
+            let shift = 8-bit_depth as usize-bit_position % 8;
+            u16::from((row[bit_position/8] >> shift) & max_sample as u8)
+          };
+        }
+        //scale samples to 8 bits
+        let to_8_bit = |sample: u16| -> u8 {
+          if bit_depth == 16 {
+            (sample >> 8) as u8
+          } else {
+            (u32::from(sample)*255/max_sample) as u8
+          }
+        };
+        let rgba: [u8; 4] = match color_type {
+          0 => {
+            let gray = to_8_bit(samples[0]);
+            let alpha = if transparent_color.as_deref() == Some(&samples[..1]) { 0 } else { 255 };
+            [gray, gray, gray, alpha]
+          },
+          2 => {
+            let alpha = if transparent_color.as_deref() == Some(&samples[..3]) { 0 } else { 255 };
+            [to_8_bit(samples[0]), to_8_bit(samples[1]), to_8_bit(samples[2]), alpha]
+          },
+          3 => {
+            match palette.get(samples[0] as usize) {
+              Some(rgba) => *rgba,
+              None => return Err(corrupt("palette index is out of range")),
+            }
+          },
+          4 => {
+            let gray = to_8_bit(samples[0]);
+            [gray, gray, gray, to_8_bit(samples[1])]
+          },
+          _ => [to_8_bit(samples[0]), to_8_bit(samples[1]), to_8_bit(samples[2]), to_8_bit(samples[3])],
+        };
+        let x = (x_start+px as u32*x_step) as usize;
+        let y = (y_start+py as u32*y_step) as usize;
+        let start = (y*width as usize+x)*4;
+        pixels[start..start+4].copy_from_slice(&rgba);
+      }
+    }
+  }
+  return Ok(pixels);
+}